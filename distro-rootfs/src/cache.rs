@@ -1,6 +1,7 @@
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
+use distro::{CancellationToken, ItemKind, ProgressEvent};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use tracing::warn;
@@ -8,6 +9,9 @@ use tracing::warn;
 use crate::Error;
 
 /// Metadata stored alongside a cached rootfs archive.
+///
+/// The rootfs tarball's metadata lives in `metadata.json`; other item kinds
+/// of the same build are stored next to it with `metadata-{kind}.json`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheMetadata {
     /// Distribution name (e.g. `"alpine"`).
@@ -16,6 +20,16 @@ pub struct CacheMetadata {
     pub version: String,
     /// Architecture (e.g. `"aarch64"`).
     pub arch: String,
+    /// LXC image variant (e.g. `"default"`, `"cloud"`).
+    #[serde(default = "default_variant")]
+    pub variant: String,
+    /// Simplestreams build serial of the archive (e.g. `"20260218_07:42"`),
+    /// if known.
+    #[serde(default)]
+    pub serial: Option<String>,
+    /// Simplestreams item kind of the file.
+    #[serde(default)]
+    pub kind: ItemKind,
     /// SHA-256 hex digest of the archive file.
     pub sha256: String,
     /// Archive filename on disk (e.g. `"rootfs.tar.xz"`).
@@ -24,6 +38,10 @@ pub struct CacheMetadata {
     pub size: u64,
     /// Unix timestamp (seconds) when the archive was downloaded.
     pub downloaded_at: String,
+    /// Combined SHA-256 of the build's LXD metadata followed by this file,
+    /// from the index. Checked by [`CachedRootfs::verify_combined`].
+    #[serde(default)]
+    pub combined_sha256: Option<String>,
 }
 
 /// A handle to a cached rootfs archive on disk.
@@ -38,8 +56,31 @@ pub struct CachedRootfs {
 impl CachedRootfs {
     /// Extracts the cached archive to the target directory.
     pub fn extract_to(&self, target: impl AsRef<Path>) -> Result<(), Error> {
+        self.extract_to_with_progress(target, &CancellationToken::new(), |_| {})
+    }
+
+    /// Extracts the cached archive to the target directory, reporting
+    /// [`ProgressEvent::Extracting`] as entries are unpacked.
+    ///
+    /// Returns [`Error::Cancelled`] once `cancel` fires; a target directory
+    /// created by this call is removed again.
+    pub fn extract_to_with_progress<F>(
+        &self,
+        target: impl AsRef<Path>,
+        cancel: &CancellationToken,
+        on_progress: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(ProgressEvent),
+    {
         let format = crate::extract::ExtractFormat::detect(&self.archive_path)?;
-        crate::extract::extract_archive(&self.archive_path, target.as_ref(), format)
+        crate::extract::extract_archive(
+            &self.archive_path,
+            target.as_ref(),
+            format,
+            cancel,
+            on_progress,
+        )
     }
 
     /// Verifies the archive's SHA256 against the stored metadata using
@@ -60,6 +101,37 @@ impl CachedRootfs {
         let actual = hex::encode(hasher.finalize());
         Ok(actual == self.metadata.sha256)
     }
+
+    /// Verifies the combined SHA256 of `lxd_metadata` followed by this file
+    /// against the `combined_*_sha256` value recorded from the index.
+    ///
+    /// This is the fingerprint LXD uses for a split image. Returns
+    /// `Ok(false)` if the files do not match it, if `lxd_metadata` is not the
+    /// LXD metadata of the same build, or if no combined hash was recorded.
+    pub fn verify_combined(&self, lxd_metadata: &CachedRootfs) -> Result<bool, Error> {
+        let Some(expected) = &self.metadata.combined_sha256 else {
+            return Ok(false);
+        };
+        if lxd_metadata.metadata.kind != ItemKind::LxdMetadata
+            || lxd_metadata.metadata.serial != self.metadata.serial
+        {
+            return Ok(false);
+        }
+        let mut hasher = Sha256::new();
+        for path in [&lxd_metadata.archive_path, &self.archive_path] {
+            let mut file = std::fs::File::open(path)?;
+            std::io::copy(&mut file, &mut hasher)?;
+        }
+        Ok(hex::encode(hasher.finalize()) == *expected)
+    }
+}
+
+/// Returns the metadata filename for an item kind in a cache entry.
+pub(crate) fn metadata_file(kind: ItemKind) -> String {
+    match kind {
+        ItemKind::Rootfs => "metadata.json".to_owned(),
+        kind => format!("metadata-{kind}.json"),
+    }
 }
 
 /// Loads a cached entry from a directory, verifying integrity.
@@ -67,8 +139,8 @@ impl CachedRootfs {
 /// Computes a streaming SHA256 over the archive and compares against the
 /// stored metadata. If the checksum does not match, the corrupted entry is
 /// removed and `None` is returned so a fresh download will be triggered.
-pub(crate) fn load_cached(entry_dir: &Path) -> Result<Option<CachedRootfs>, Error> {
-    let Some(cached) = load_entry(entry_dir)? else {
+pub(crate) fn load_cached(entry_dir: &Path, kind: ItemKind) -> Result<Option<CachedRootfs>, Error> {
+    let Some(cached) = load_entry(entry_dir, kind)? else {
         return Ok(None);
     };
 
@@ -78,7 +150,7 @@ pub(crate) fn load_cached(entry_dir: &Path) -> Result<Option<CachedRootfs>, Erro
             expected = %cached.metadata.sha256,
             "cached rootfs integrity check failed, removing corrupted entry"
         );
-        let _ = std::fs::remove_dir_all(entry_dir);
+        let _ = remove(&cached);
         return Ok(None);
     }
 
@@ -89,8 +161,8 @@ pub(crate) fn load_cached(entry_dir: &Path) -> Result<Option<CachedRootfs>, Erro
 ///
 /// Used by [`list_all`] and [`prune`] to avoid reading every archive file
 /// when only metadata is needed.
-fn load_entry(entry_dir: &Path) -> Result<Option<CachedRootfs>, Error> {
-    let metadata_path = entry_dir.join("metadata.json");
+fn load_entry(entry_dir: &Path, kind: ItemKind) -> Result<Option<CachedRootfs>, Error> {
+    let metadata_path = entry_dir.join(metadata_file(kind));
     if !metadata_path.exists() {
         return Ok(None);
     }
@@ -109,13 +181,20 @@ fn load_entry(entry_dir: &Path) -> Result<Option<CachedRootfs>, Error> {
     }))
 }
 
-/// Stores a download result into the cache entry directory.
+/// Records a download result of item `kind` in the cache entry directory.
+///
+/// The archive is expected to have been streamed into `entry_dir` already;
+/// if it lives elsewhere it is moved into place. Only the metadata is written
+/// here.
 pub(crate) fn store(
     entry_dir: &Path,
     result: &distro::DownloadResult,
+    kind: ItemKind,
 ) -> Result<CachedRootfs, Error> {
     let archive_path = entry_dir.join(&result.filename);
-    std::fs::write(&archive_path, &result.data)?;
+    if result.path != archive_path {
+        move_file(&result.path, &archive_path)?;
+    }
 
     // Extract distro/version/arch from the directory structure.
     let components: Vec<&str> = entry_dir
@@ -125,18 +204,29 @@ pub(crate) fn store(
         .map(|c| c.as_os_str().to_str().unwrap_or("unknown"))
         .collect();
 
+    // Non-default variants are stored as `{arch}-{variant}`.
+    let entry = components.first().unwrap_or(&"unknown");
+    let (arch, variant) = entry.split_once('-').unwrap_or((entry, "default"));
+
     let metadata = CacheMetadata {
         distro: components.get(2).unwrap_or(&"unknown").to_string(),
         version: components.get(1).unwrap_or(&"unknown").to_string(),
-        arch: components.first().unwrap_or(&"unknown").to_string(),
+        arch: arch.to_string(),
+        variant: variant.to_string(),
+        serial: result.serial.clone(),
+        kind,
         sha256: result.sha256.clone(),
         filename: result.filename.clone(),
-        size: result.data.len() as u64,
+        size: result.size,
         downloaded_at: chrono_now(),
+        combined_sha256: result.combined_sha256.clone(),
     };
 
-    let metadata_path = entry_dir.join("metadata.json");
-    std::fs::write(&metadata_path, serde_json::to_string_pretty(&metadata)?)?;
+    // Write-then-rename so readers never see a half-written metadata file.
+    let metadata_path = entry_dir.join(metadata_file(kind));
+    let tmp_path = entry_dir.join(format!("{}.tmp", metadata_file(kind)));
+    std::fs::write(&tmp_path, serde_json::to_string_pretty(&metadata)?)?;
+    std::fs::rename(&tmp_path, &metadata_path)?;
 
     Ok(CachedRootfs {
         archive_path,
@@ -144,6 +234,38 @@ pub(crate) fn store(
     })
 }
 
+/// Variant of entries written before variants were recorded.
+fn default_variant() -> String {
+    "default".to_owned()
+}
+
+/// Removes a cached item. The entry directory, with any partial downloads,
+/// goes too once no other item kind is cached in it.
+pub(crate) fn remove(cached: &CachedRootfs) -> Result<(), Error> {
+    let Some(entry_dir) = cached.archive_path.parent() else {
+        return Ok(());
+    };
+    let others_cached = ItemKind::all()
+        .iter()
+        .any(|&kind| kind != cached.metadata.kind && entry_dir.join(metadata_file(kind)).exists());
+    if others_cached {
+        std::fs::remove_file(entry_dir.join(metadata_file(cached.metadata.kind)))?;
+        std::fs::remove_file(&cached.archive_path)?;
+    } else {
+        std::fs::remove_dir_all(entry_dir)?;
+    }
+    Ok(())
+}
+
+/// Moves a file, falling back to copy + remove across filesystems.
+fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
 /// Lists all cached rootfs entries under the cache root.
 pub(crate) fn list_all(cache_dir: &Path) -> Result<Vec<CachedRootfs>, Error> {
     let mut entries = Vec::new();
@@ -168,8 +290,10 @@ pub(crate) fn list_all(cache_dir: &Path) -> Result<Vec<CachedRootfs>, Error> {
                 if !arch_dir.is_dir() {
                     continue;
                 }
-                if let Some(cached) = load_entry(&arch_dir)? {
-                    entries.push(cached);
+                for &kind in ItemKind::all() {
+                    if let Some(cached) = load_entry(&arch_dir, kind)? {
+                        entries.push(cached);
+                    }
                 }
             }
         }
@@ -178,32 +302,36 @@ pub(crate) fn list_all(cache_dir: &Path) -> Result<Vec<CachedRootfs>, Error> {
     Ok(entries)
 }
 
-/// Prunes old cache entries, keeping at most `keep_latest` per distro.
-/// Returns the number of bytes freed.
+/// Prunes old cache entries, keeping at most `keep_latest` per distro,
+/// architecture, variant and item kind. Returns the number of bytes freed.
 pub(crate) fn prune(cache_dir: &Path, keep_latest: usize) -> Result<u64, Error> {
     let mut freed = 0u64;
     let all = list_all(cache_dir)?;
 
-    // Group by distro.
-    let mut by_distro: std::collections::HashMap<String, Vec<CachedRootfs>> =
+    // Group by distro, arch, variant and item kind; versions of the same
+    // image compete with each other.
+    type Group = (String, String, String, ItemKind);
+    let mut by_distro: std::collections::HashMap<Group, Vec<CachedRootfs>> =
         std::collections::HashMap::new();
     for entry in all {
-        by_distro
-            .entry(entry.metadata.distro.clone())
-            .or_default()
-            .push(entry);
+        let metadata = &entry.metadata;
+        let group = (
+            metadata.distro.clone(),
+            metadata.arch.clone(),
+            metadata.variant.clone(),
+            metadata.kind,
+        );
+        by_distro.entry(group).or_default().push(entry);
     }
 
-    for (_distro, mut entries) in by_distro {
+    for (_group, mut entries) in by_distro {
         // Sort by download time (newest first).
         entries.sort_by(|a, b| b.metadata.downloaded_at.cmp(&a.metadata.downloaded_at));
 
         // Remove entries beyond the keep limit.
         for old in entries.into_iter().skip(keep_latest) {
-            if let Some(parent) = old.archive_path.parent() {
-                if std::fs::remove_dir_all(parent).is_ok() {
-                    freed += old.metadata.size;
-                }
+            if remove(&old).is_ok() {
+                freed += old.metadata.size;
             }
         }
     }
@@ -224,12 +352,18 @@ fn chrono_now() -> String {
 mod tests {
     use super::*;
 
-    fn make_test_result(content: &[u8], filename: &str) -> distro::DownloadResult {
-        let sha256 = hex::encode(Sha256::digest(content));
+    /// Writes `content` into `dir` the way a streamed download would.
+    fn make_test_result(dir: &Path, content: &[u8], filename: &str) -> distro::DownloadResult {
+        let path = dir.join(filename);
+        std::fs::write(&path, content).unwrap();
         distro::DownloadResult {
-            data: content.to_vec(),
-            sha256,
+            path,
+            sha256: hex::encode(Sha256::digest(content)),
+            sha512: None,
+            size: content.len() as u64,
             filename: filename.to_owned(),
+            serial: None,
+            combined_sha256: None,
         }
     }
 
@@ -239,18 +373,105 @@ mod tests {
         let entry = dir.path().join("alpine").join("3.21").join("aarch64");
         std::fs::create_dir_all(&entry).unwrap();
 
-        let result = make_test_result(b"fake rootfs data", "rootfs.tar.gz");
-        let cached = store(&entry, &result).unwrap();
+        let result = make_test_result(&entry, b"fake rootfs data", "rootfs.tar.gz");
+        let cached = store(&entry, &result, ItemKind::Rootfs).unwrap();
 
         assert_eq!(cached.metadata.sha256, result.sha256);
         assert_eq!(cached.metadata.filename, "rootfs.tar.gz");
         assert!(cached.archive_path.exists());
 
         // Load should succeed.
-        let loaded = load_cached(&entry).unwrap();
+        let loaded = load_cached(&entry, ItemKind::Rootfs).unwrap();
         assert!(loaded.is_some());
         let loaded = loaded.unwrap();
         assert_eq!(loaded.metadata.sha256, result.sha256);
+        assert_eq!(loaded.metadata.arch, "aarch64");
+        assert_eq!(loaded.metadata.variant, "default");
+    }
+
+    #[test]
+    fn store_records_variant_from_entry_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = dir.path().join("alpine").join("3.21").join("x86_64-cloud");
+        std::fs::create_dir_all(&entry).unwrap();
+
+        let mut result = make_test_result(&entry, b"cloud rootfs", "rootfs.tar.xz");
+        result.serial = Some("20260218_13:00".to_owned());
+        let cached = store(&entry, &result, ItemKind::Rootfs).unwrap();
+        assert_eq!(cached.metadata.serial.as_deref(), Some("20260218_13:00"));
+        assert_eq!(cached.metadata.arch, "x86_64");
+        assert_eq!(cached.metadata.variant, "cloud");
+    }
+
+    #[test]
+    fn metadata_without_variant_is_default() {
+        let json = r#"{"distro":"alpine","version":"3.21","arch":"aarch64","sha256":"ab",
+            "filename":"rootfs.tar.xz","size":1,"downloaded_at":"0"}"#;
+        let metadata: CacheMetadata = serde_json::from_str(json).unwrap();
+        assert_eq!(metadata.variant, "default");
+        assert_eq!(metadata.serial, None);
+    }
+
+    #[test]
+    fn item_kinds_stored_side_by_side() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = dir.path().join("alpine").join("3.21").join("x86_64");
+        std::fs::create_dir_all(&entry).unwrap();
+
+        let mut lxd = make_test_result(&entry, b"lxd metadata", "lxd.tar.xz");
+        lxd.serial = Some("20260218_13:00".to_owned());
+        let lxd = store(&entry, &lxd, ItemKind::LxdMetadata).unwrap();
+        let mut rootfs = make_test_result(&entry, b"squashfs rootfs", "rootfs.squashfs");
+        rootfs.serial = Some("20260218_13:00".to_owned());
+        rootfs.combined_sha256 = Some(hex::encode(Sha256::digest(b"lxd metadatasquashfs rootfs")));
+        let squashfs = store(&entry, &rootfs, ItemKind::Squashfs).unwrap();
+
+        assert!(entry.join("metadata-lxd-metadata.json").exists());
+        assert!(entry.join("metadata-squashfs.json").exists());
+        assert!(load_cached(&entry, ItemKind::Rootfs).unwrap().is_none());
+        assert_eq!(list_all(dir.path()).unwrap().len(), 2);
+        assert!(squashfs.verify_combined(&lxd).unwrap());
+        assert!(!lxd.verify_combined(&squashfs).unwrap());
+
+        // Removing one kind keeps the other.
+        remove(&squashfs).unwrap();
+        assert!(
+            load_cached(&entry, ItemKind::LxdMetadata)
+                .unwrap()
+                .is_some()
+        );
+        assert!(!squashfs.archive_path.exists());
+    }
+
+    #[test]
+    fn verify_combined_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = dir.path().join("alpine").join("3.21").join("x86_64");
+        std::fs::create_dir_all(&entry).unwrap();
+
+        let lxd = make_test_result(&entry, b"lxd metadata", "lxd.tar.xz");
+        let lxd = store(&entry, &lxd, ItemKind::LxdMetadata).unwrap();
+        let mut rootfs = make_test_result(&entry, b"rootfs", "rootfs.tar.xz");
+        rootfs.combined_sha256 = Some("00".repeat(32));
+        let rootfs = store(&entry, &rootfs, ItemKind::Rootfs).unwrap();
+        assert!(!rootfs.verify_combined(&lxd).unwrap());
+    }
+
+    #[test]
+    fn store_moves_archive_into_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = dir.path().join("staging");
+        let entry = dir.path().join("alpine").join("3.21").join("aarch64");
+        std::fs::create_dir_all(&staging).unwrap();
+        std::fs::create_dir_all(&entry).unwrap();
+
+        let result = make_test_result(&staging, b"staged rootfs", "rootfs.tar.xz");
+        let cached = store(&entry, &result, ItemKind::Rootfs).unwrap();
+
+        assert_eq!(cached.archive_path, entry.join("rootfs.tar.xz"));
+        assert!(cached.archive_path.exists());
+        assert!(!result.path.exists());
+        assert_eq!(cached.metadata.size, 13);
     }
 
     #[test]
@@ -259,7 +480,7 @@ mod tests {
         let entry = dir.path().join("alpine").join("3.21").join("x86_64");
         std::fs::create_dir_all(&entry).unwrap();
 
-        assert!(load_cached(&entry).unwrap().is_none());
+        assert!(load_cached(&entry, ItemKind::Rootfs).unwrap().is_none());
     }
 
     #[test]
@@ -273,10 +494,14 @@ mod tests {
             distro: "debian".to_owned(),
             version: "12".to_owned(),
             arch: "amd64".to_owned(),
+            variant: "default".to_owned(),
+            serial: None,
+            kind: ItemKind::Rootfs,
             sha256: "deadbeef".to_owned(),
             filename: "rootfs.tar.xz".to_owned(),
             size: 100,
             downloaded_at: "0".to_owned(),
+            combined_sha256: None,
         };
         std::fs::write(
             entry.join("metadata.json"),
@@ -284,7 +509,7 @@ mod tests {
         )
         .unwrap();
 
-        assert!(load_cached(&entry).unwrap().is_none());
+        assert!(load_cached(&entry, ItemKind::Rootfs).unwrap().is_none());
     }
 
     #[test]
@@ -294,14 +519,14 @@ mod tests {
         std::fs::create_dir_all(&entry).unwrap();
 
         // Store a valid entry first.
-        let result = make_test_result(b"original data", "rootfs.tar.xz");
-        store(&entry, &result).unwrap();
+        let result = make_test_result(&entry, b"original data", "rootfs.tar.xz");
+        store(&entry, &result, ItemKind::Rootfs).unwrap();
 
         // Corrupt the archive.
         std::fs::write(entry.join("rootfs.tar.xz"), b"corrupted").unwrap();
 
         // Load should detect corruption and return None.
-        let loaded = load_cached(&entry).unwrap();
+        let loaded = load_cached(&entry, ItemKind::Rootfs).unwrap();
         assert!(loaded.is_none());
         // The corrupted entry should have been cleaned up.
         assert!(!entry.exists());
@@ -323,10 +548,11 @@ mod tests {
             let entry = dir.path().join(distro).join(ver).join("aarch64");
             std::fs::create_dir_all(&entry).unwrap();
             let result = make_test_result(
+                &entry,
                 format!("data-{distro}").as_bytes(),
                 "rootfs.tar.gz",
             );
-            store(&entry, &result).unwrap();
+            store(&entry, &result, ItemKind::Rootfs).unwrap();
         }
 
         let entries = list_all(dir.path()).unwrap();
@@ -342,10 +568,11 @@ mod tests {
             let entry = dir.path().join("alpine").join(ver).join("aarch64");
             std::fs::create_dir_all(&entry).unwrap();
             let result = make_test_result(
+                &entry,
                 format!("data-{ver}").as_bytes(),
                 "rootfs.tar.gz",
             );
-            let mut cached = store(&entry, &result).unwrap();
+            let mut cached = store(&entry, &result, ItemKind::Rootfs).unwrap();
             // Set increasing timestamps so "3" is newest.
             cached.metadata.downloaded_at = format!("{}", 1000 + i);
             std::fs::write(
@@ -364,14 +591,57 @@ mod tests {
         assert_eq!(remaining[0].metadata.version, "3");
     }
 
+    #[test]
+    fn prune_keeps_latest_per_arch_and_variant() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Two versions of each image; "2" is newer everywhere.
+        let images = ["aarch64", "aarch64-cloud", "x86_64"];
+        for (i, ver) in ["1", "2"].iter().enumerate() {
+            for image in images {
+                let entry = dir.path().join("ubuntu").join(ver).join(image);
+                std::fs::create_dir_all(&entry).unwrap();
+                let result = make_test_result(
+                    &entry,
+                    format!("data-{ver}-{image}").as_bytes(),
+                    "rootfs.tar.gz",
+                );
+                let mut cached = store(&entry, &result, ItemKind::Rootfs).unwrap();
+                cached.metadata.downloaded_at = format!("{}", 1000 + i);
+                std::fs::write(
+                    entry.join("metadata.json"),
+                    serde_json::to_string_pretty(&cached.metadata).unwrap(),
+                )
+                .unwrap();
+            }
+        }
+
+        prune(dir.path(), 1).unwrap();
+
+        let mut remaining: Vec<_> = list_all(dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.metadata.version, e.metadata.arch, e.metadata.variant))
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            [
+                ("2".to_owned(), "aarch64".to_owned(), "cloud".to_owned()),
+                ("2".to_owned(), "aarch64".to_owned(), "default".to_owned()),
+                ("2".to_owned(), "x86_64".to_owned(), "default".to_owned()),
+            ]
+        );
+    }
+
     #[test]
     fn verify_integrity_valid() {
         let dir = tempfile::tempdir().unwrap();
         let entry = dir.path().join("alpine").join("3.21").join("aarch64");
         std::fs::create_dir_all(&entry).unwrap();
 
-        let result = make_test_result(b"valid content", "rootfs.tar.gz");
-        let cached = store(&entry, &result).unwrap();
+        let result = make_test_result(&entry, b"valid content", "rootfs.tar.gz");
+        let cached = store(&entry, &result, ItemKind::Rootfs).unwrap();
         assert!(cached.verify_integrity().unwrap());
     }
 
@@ -381,8 +651,8 @@ mod tests {
         let entry = dir.path().join("debian").join("12").join("arm64");
         std::fs::create_dir_all(&entry).unwrap();
 
-        let result = make_test_result(b"original", "rootfs.tar.xz");
-        let cached = store(&entry, &result).unwrap();
+        let result = make_test_result(&entry, b"original", "rootfs.tar.xz");
+        let cached = store(&entry, &result, ItemKind::Rootfs).unwrap();
 
         // Corrupt the archive on disk.
         std::fs::write(&cached.archive_path, b"tampered").unwrap();
@@ -397,8 +667,8 @@ mod tests {
         for ver in ["1", "2"] {
             let entry = dir.path().join("fedora").join(ver).join("x86_64");
             std::fs::create_dir_all(&entry).unwrap();
-            let result = make_test_result(b"data", "rootfs.tar.gz");
-            let mut cached = store(&entry, &result).unwrap();
+            let result = make_test_result(&entry, b"data", "rootfs.tar.gz");
+            let mut cached = store(&entry, &result, ItemKind::Rootfs).unwrap();
             cached.metadata.downloaded_at = if ver == "1" {
                 "1000".to_owned()
             } else {
@@ -3,7 +3,12 @@
 pub enum Error {
     /// An error propagated from the [`distro`] crate (download, verification).
     #[error("distro error: {0}")]
-    Distro(#[from] distro::Error),
+    Distro(#[source] distro::Error),
+
+    /// The operation was cancelled through its
+    /// [`CancellationToken`](distro::CancellationToken).
+    #[error("operation cancelled")]
+    Cancelled,
 
     /// A filesystem I/O operation failed.
     #[error("I/O error: {0}")]
@@ -13,7 +18,26 @@ pub enum Error {
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
 
+    /// A cached item and the LXD metadata of its build do not match the
+    /// `combined_*_sha256` listed in the index.
+    #[error("combined SHA256 mismatch for {}: expected {expected}", path.display())]
+    CombinedMismatch {
+        /// The item that was removed from the cache.
+        path: std::path::PathBuf,
+        /// Combined SHA256 from the index.
+        expected: String,
+    },
+
     /// The archive has an unrecognized file extension.
     #[error("unsupported archive format: {0}")]
     UnsupportedFormat(String),
 }
+
+impl From<distro::Error> for Error {
+    fn from(e: distro::Error) -> Self {
+        match e {
+            distro::Error::Cancelled => Self::Cancelled,
+            e => Self::Distro(e),
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use std::io::Read;
 use std::path::Path;
 
+use distro::{CancellationToken, ProgressEvent};
+
 use crate::Error;
 
 /// Supported archive formats for rootfs tarballs.
@@ -29,23 +32,74 @@ impl ExtractFormat {
     }
 }
 
-/// Extracts an archive to the target directory.
-pub fn extract_archive(archive: &Path, target: &Path, format: ExtractFormat) -> Result<(), Error> {
+/// Extracts an archive to the target directory, reporting
+/// [`ProgressEvent::Extracting`] after each entry.
+///
+/// `cancel` is checked between entries. On cancellation the target directory
+/// is removed if this call created it; a pre-existing target is left as is.
+pub fn extract_archive<F>(
+    archive: &Path,
+    target: &Path,
+    format: ExtractFormat,
+    cancel: &CancellationToken,
+    mut on_progress: F,
+) -> Result<(), Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    let created = !target.exists();
     std::fs::create_dir_all(target)?;
 
     let file = std::fs::File::open(archive)?;
 
-    match format {
+    let result = match format {
         ExtractFormat::TarGz => {
             let decoder = flate2::read::GzDecoder::new(file);
-            let mut archive = tar::Archive::new(decoder);
-            archive.unpack(target)?;
+            unpack(decoder, target, cancel, &mut on_progress)
         }
         ExtractFormat::TarXz => {
             let decoder = xz2::read::XzDecoder::new(file);
-            let mut archive = tar::Archive::new(decoder);
-            archive.unpack(target)?;
+            unpack(decoder, target, cancel, &mut on_progress)
         }
+    };
+
+    if matches!(result, Err(Error::Cancelled)) && created {
+        let _ = std::fs::remove_dir_all(target);
+    }
+    result
+}
+
+/// Unpacks a tar stream entry by entry, like [`tar::Archive::unpack`].
+fn unpack<R: Read>(
+    reader: R,
+    target: &Path,
+    cancel: &CancellationToken,
+    on_progress: &mut dyn FnMut(ProgressEvent),
+) -> Result<(), Error> {
+    let target = target.canonicalize()?;
+    let mut archive = tar::Archive::new(reader);
+    let mut bytes = 0;
+
+    // Directories are created last, deepest first, so that restrictive
+    // permissions do not block the files extracted into them.
+    let mut directories = Vec::new();
+    for (index, entry) in archive.entries()?.enumerate() {
+        if cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let mut entry = entry?;
+        let entries = index as u64 + 1;
+        bytes += entry.size();
+        if entry.header().entry_type().is_dir() {
+            directories.push(entry);
+        } else {
+            entry.unpack_in(&target)?;
+        }
+        on_progress(ProgressEvent::Extracting { entries, bytes });
+    }
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for mut dir in directories {
+        dir.unpack_in(&target)?;
     }
 
     Ok(())
@@ -132,7 +186,22 @@ mod tests {
         let target = dir.path().join("out-gz");
 
         create_tar_gz(&archive, "etc/os-release", b"ID=alpine\n").unwrap();
-        extract_archive(&archive, &target, ExtractFormat::TarGz).unwrap();
+        let mut events = Vec::new();
+        extract_archive(
+            &archive,
+            &target,
+            ExtractFormat::TarGz,
+            &CancellationToken::new(),
+            |e| events.push(e),
+        )
+        .unwrap();
+        assert_eq!(
+            events,
+            vec![ProgressEvent::Extracting {
+                entries: 1,
+                bytes: 10
+            }]
+        );
 
         let extracted = target.join("etc/os-release");
         assert!(extracted.exists());
@@ -146,7 +215,14 @@ mod tests {
         let target = dir.path().join("out-xz");
 
         create_tar_xz(&archive, "usr/lib/os-release", b"ID=debian\n").unwrap();
-        extract_archive(&archive, &target, ExtractFormat::TarXz).unwrap();
+        extract_archive(
+            &archive,
+            &target,
+            ExtractFormat::TarXz,
+            &CancellationToken::new(),
+            |_| {},
+        )
+        .unwrap();
 
         let extracted = target.join("usr/lib/os-release");
         assert!(extracted.exists());
@@ -160,7 +236,14 @@ mod tests {
         let target = dir.path().join("out-broken");
         std::fs::write(&archive, b"not-a-valid-gzip-tar").unwrap();
 
-        let err = extract_archive(&archive, &target, ExtractFormat::TarGz).unwrap_err();
+        let err = extract_archive(
+            &archive,
+            &target,
+            ExtractFormat::TarGz,
+            &CancellationToken::new(),
+            |_| {},
+        )
+        .unwrap_err();
         match err {
             Error::Io(_) => {}
             _ => panic!("unexpected error variant"),
@@ -174,6 +257,30 @@ mod tests {
         let target = dir.path().join("out-mismatch");
 
         create_tar_xz(&archive, "etc/issue", b"Welcome\n").unwrap();
-        assert!(extract_archive(&archive, &target, ExtractFormat::TarGz).is_err());
+        assert!(
+            extract_archive(
+                &archive,
+                &target,
+                ExtractFormat::TarGz,
+                &CancellationToken::new(),
+                |_| {},
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn extract_archive_cancelled_removes_new_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("rootfs.tar.gz");
+        let target = dir.path().join("out-cancelled");
+
+        create_tar_gz(&archive, "etc/os-release", b"ID=alpine\n").unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let err = extract_archive(&archive, &target, ExtractFormat::TarGz, &cancel, |_| {})
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert!(!target.exists());
     }
 }
@@ -11,7 +11,7 @@
 //! # Example
 //!
 //! ```no_run
-//! use distro::{Distro, Arch, Mirror};
+//! use distro::{Distro, Arch, DownloadOptions, Mirror, Variant};
 //! use distro_rootfs::RootfsManager;
 //!
 //! # async fn example() -> Result<(), distro_rootfs::Error> {
@@ -22,10 +22,10 @@
 //!     Distro::Alpine,
 //!     &"3.21".into(),
 //!     Arch::current(),
+//!     &Variant::default(),
 //!     &Mirror::default(),
-//!     |downloaded, total| {
-//!         eprintln!("{downloaded}/{total} bytes");
-//!     },
+//!     &DownloadOptions::default(),
+//!     |event| eprintln!("{event:?}"),
 //! ).await?;
 //!
 //! // Extract to a target directory.
@@ -37,6 +37,7 @@
 mod cache;
 mod error;
 mod extract;
+mod lock;
 
 pub use cache::CachedRootfs;
 pub use error::Error;
@@ -44,12 +45,22 @@ pub use extract::ExtractFormat;
 
 use std::path::{Path, PathBuf};
 
-use distro::{Arch, Distro, Mirror, Version};
+use distro::{
+    Arch, DeltaBase, Distro, DownloadOptions, ItemKind, MirrorSet, ProgressEvent, Variant, Version,
+};
 use tracing::{debug, info};
 
+/// File in the cache directory that keeps the `Mirror::Auto` ranking.
+const MIRROR_PROBE_FILE: &str = "mirror-probe.json";
+
+/// File in the cache directory read by [`RootfsManager::configured_mirrors`].
+pub const MIRROR_CONFIG_FILE: &str = "mirrors.toml";
+
 /// Manages rootfs downloads, caching, and extraction.
 pub struct RootfsManager {
     cache_dir: PathBuf,
+    /// Coalesces concurrent `ensure` calls for the same entry.
+    entry_locks: lock::EntryLocks,
 }
 
 impl RootfsManager {
@@ -57,67 +68,289 @@ impl RootfsManager {
     pub fn new(cache_dir: impl Into<PathBuf>) -> Result<Self, Error> {
         let cache_dir = cache_dir.into();
         std::fs::create_dir_all(&cache_dir)?;
-        Ok(Self { cache_dir })
+        Ok(Self {
+            cache_dir,
+            entry_locks: lock::EntryLocks::default(),
+        })
     }
 
     /// Ensures a rootfs archive is available locally, downloading if necessary.
     ///
-    /// Uses LXC Images (via the specified mirror) as the download source,
-    /// which supports all 16 distros through a unified API.
+    /// Uses LXC Images (via the specified mirror or [`MirrorSet`]) as the
+    /// download source, which supports all 16 distros through a unified API.
+    /// Retries and mirror failover are controlled by `options`. `on_progress` receives
+    /// every phase from the index fetch to the cache store.
+    ///
+    /// Cancelling `options.cancel` returns [`Error::Cancelled`]. The partial
+    /// download stays in the cache entry without metadata, so it is never
+    /// served as cached and the next call resumes it.
+    ///
+    /// Concurrent calls for the same entry download it once: other callers in
+    /// this process wait for the first one, and other processes sharing the
+    /// cache directory wait on an advisory lock (`<entry>.lock`). Waiters
+    /// receive [`ProgressEvent::WaitingForLock`] and then the cached entry.
+    ///
+    /// Each `variant` is cached separately, so the `default` and `cloud`
+    /// images of one release can be kept side by side. With
+    /// `options.serial` set, a cached entry from another build is replaced by
+    /// the pinned one.
+    ///
+    /// `options.item` selects the file of the build: the rootfs tarball by
+    /// default, or the LXD metadata, squashfs, or VM disk image. Each kind is
+    /// cached next to the others in the same entry. When both an item and
+    /// the LXD metadata of the same build are cached, their
+    /// `combined_*_sha256` from the index is checked; a mismatch removes the
+    /// newly downloaded item and fails with [`Error::CombinedMismatch`].
+    #[allow(clippy::too_many_arguments)]
     pub async fn ensure<F>(
         &self,
         distro: Distro,
         version: &Version,
         arch: Arch,
-        mirror: &Mirror,
+        variant: &Variant,
+        mirrors: impl Into<MirrorSet>,
+        options: &DownloadOptions,
+        on_progress: F,
+    ) -> Result<CachedRootfs, Error>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        self.ensure_entry(
+            distro,
+            version,
+            arch,
+            variant,
+            &mirrors.into(),
+            options,
+            false,
+            on_progress,
+        )
+        .await
+    }
+
+    /// Updates a cached entry to the newest build on the mirror, or to
+    /// `options.serial` if pinned.
+    ///
+    /// The cached build is used as a [`DeltaBase`](distro::DeltaBase): when
+    /// the index lists `.vcdiff` deltas from it, only those are downloaded
+    /// and applied, and the result is verified against the new build's
+    /// SHA256. Without a delta chain, or if applying it fails, the full file
+    /// is downloaded. An entry that is already current is re-verified and
+    /// kept. Without a cached entry this behaves like [`ensure`](Self::ensure).
+    #[allow(clippy::too_many_arguments)]
+    pub async fn refresh<F>(
+        &self,
+        distro: Distro,
+        version: &Version,
+        arch: Arch,
+        variant: &Variant,
+        mirrors: impl Into<MirrorSet>,
+        options: &DownloadOptions,
         on_progress: F,
     ) -> Result<CachedRootfs, Error>
     where
-        F: FnMut(u64, u64),
+        F: FnMut(ProgressEvent),
+    {
+        self.ensure_entry(
+            distro,
+            version,
+            arch,
+            variant,
+            &mirrors.into(),
+            options,
+            true,
+            on_progress,
+        )
+        .await
+    }
+
+    /// Shared implementation of [`ensure`](Self::ensure) and
+    /// [`refresh`](Self::refresh).
+    #[allow(clippy::too_many_arguments)]
+    async fn ensure_entry<F>(
+        &self,
+        distro: Distro,
+        version: &Version,
+        arch: Arch,
+        variant: &Variant,
+        mirrors: &MirrorSet,
+        options: &DownloadOptions,
+        refresh: bool,
+        mut on_progress: F,
+    ) -> Result<CachedRootfs, Error>
+    where
+        F: FnMut(ProgressEvent),
     {
-        let entry_dir = self.entry_dir(distro, version, arch);
+        let entry_dir = self.entry_dir(distro, version, arch, variant);
+
+        // Only one caller per entry gets past here at a time, in this process
+        // and across processes. The guards are held until the entry is stored.
+        let mut waiting = false;
+        let mut on_wait = || {
+            if !std::mem::replace(&mut waiting, true) {
+                on_progress(ProgressEvent::WaitingForLock {
+                    path: entry_dir.clone(),
+                });
+            }
+        };
+        let _entry_guard = self
+            .entry_locks
+            .lock(&entry_dir, &options.cancel, &mut on_wait)
+            .await?;
+        let _file_lock =
+            lock::FileLock::acquire(&lock::lock_path(&entry_dir), &options.cancel, on_wait).await?;
 
-        // Check cache first.
-        if let Some(cached) = cache::load_cached(&entry_dir)? {
+        // Check cache first; a caller we waited for may have just filled it.
+        // An entry from a different build than the pinned serial is replaced,
+        // and a refresh always asks the mirror; the cached build then serves
+        // as the base for delta updates.
+        let kind = options.item;
+        let cached = cache::load_cached(&entry_dir, kind)?;
+        let (cached, replaced) = match (cached, &options.serial) {
+            (Some(cached), Some(serial)) if cached.metadata.serial.as_ref() != Some(serial) => {
+                info!(
+                    cached = cached.metadata.serial.as_deref().unwrap_or("unknown"),
+                    pinned = %serial,
+                    "cached rootfs is a different build, replacing"
+                );
+                std::fs::remove_file(entry_dir.join(cache::metadata_file(kind)))?;
+                (None, Some(cached))
+            }
+            (Some(cached), _) if refresh => (None, Some(cached)),
+            (cached, _) => (cached, None),
+        };
+        if let Some(cached) = cached {
             info!(
                 distro = %distro,
                 version = %version,
                 arch = %arch,
+                variant = %variant,
+                item = %kind,
                 "using cached rootfs"
             );
+            on_progress(ProgressEvent::CacheHit {
+                path: cached.archive_path.clone(),
+            });
             return Ok(cached);
         }
 
-        // Download from LXC Images.
-        info!(distro = %distro, version = %version, arch = %arch, mirror = %mirror, "downloading rootfs");
-        let result =
-            distro::download_from_lxc(distro, version, arch, mirror, on_progress).await?;
-
-        // Save to cache.
+        // Stream from LXC Images straight into the cache entry.
+        info!(distro = %distro, version = %version, arch = %arch, variant = %variant, item = %kind, mirrors = %mirrors, "downloading rootfs");
         std::fs::create_dir_all(&entry_dir)?;
-        let cached = cache::store(&entry_dir, &result)?;
+        let delta_base = replaced.and_then(|cached| {
+            Some(DeltaBase {
+                serial: cached.metadata.serial?,
+                path: cached.archive_path,
+            })
+        });
+        let mut options = DownloadOptions {
+            delta_base: delta_base.or_else(|| options.delta_base.clone()),
+            ..options.clone()
+        };
+        // Keep the `Mirror::Auto` ranking with the cache unless told otherwise.
+        if options.mirror_probe.cache_file.is_none() {
+            options.mirror_probe.cache_file = Some(self.cache_dir.join(MIRROR_PROBE_FILE));
+        }
+        let result = distro::download_from_lxc(
+            distro,
+            version,
+            arch,
+            variant,
+            mirrors,
+            &entry_dir,
+            &options,
+            &mut on_progress,
+        )
+        .await?;
+
+        // Record metadata for the cached archive.
+        on_progress(ProgressEvent::Storing {
+            path: entry_dir.join(&result.filename),
+        });
+        let cached = cache::store(&entry_dir, &result, kind)?;
+        verify_combined(&entry_dir, &cached)?;
 
         debug!(path = %cached.archive_path.display(), "rootfs cached");
         Ok(cached)
     }
 
+    /// Returns the mirrors configured for this cache (see
+    /// [`MirrorSet::load`]): the `ARCBOX_MIRROR` environment variable if set,
+    /// then [`MIRROR_CONFIG_FILE`] in the cache directory if present, then
+    /// the official server. Pass the result to [`ensure`](Self::ensure) when
+    /// no mirror was chosen explicitly.
+    pub fn configured_mirrors(&self) -> Result<MirrorSet, Error> {
+        Ok(MirrorSet::load(Some(
+            &self.cache_dir.join(MIRROR_CONFIG_FILE),
+        ))?)
+    }
+
     /// Lists all cached rootfs entries.
     pub fn list_cached(&self) -> Result<Vec<CachedRootfs>, Error> {
         cache::list_all(&self.cache_dir)
     }
 
-    /// Removes cached rootfs entries, keeping only the N most recent per distro.
+    /// Removes cached rootfs entries, keeping only the N most recent per
+    /// distro, architecture and variant.
     pub fn prune(&self, keep_latest: usize) -> Result<u64, Error> {
         cache::prune(&self.cache_dir, keep_latest)
     }
 
-    /// Returns the cache directory path for a specific distro/version/arch combination.
-    fn entry_dir(&self, distro: Distro, version: &Version, arch: Arch) -> PathBuf {
+    /// Returns the cache directory path for a specific distro/version/arch/variant
+    /// combination.
+    ///
+    /// The `default` variant uses `{arch}`; other variants use
+    /// `{arch}-{variant}` next to it.
+    fn entry_dir(
+        &self,
+        distro: Distro,
+        version: &Version,
+        arch: Arch,
+        variant: &Variant,
+    ) -> PathBuf {
+        let entry = if variant.is_default() {
+            arch.linux_name().to_owned()
+        } else {
+            format!("{}-{variant}", arch.linux_name())
+        };
         self.cache_dir
             .join(distro.as_str())
             .join(version.as_str())
-            .join(arch.linux_name())
+            .join(entry)
+    }
+}
+
+/// Checks the combined SHA256 of a newly stored item against the other half
+/// of its split LXD image (metadata + rootfs, squashfs, or disk), if that is
+/// cached from the same build. On mismatch the new item is removed.
+fn verify_combined(entry_dir: &Path, stored: &CachedRootfs) -> Result<(), Error> {
+    let mut pairs = Vec::new();
+    if stored.metadata.kind == ItemKind::LxdMetadata {
+        for &kind in ItemKind::all() {
+            if kind != ItemKind::LxdMetadata {
+                if let Some(item) = cache::load_cached(entry_dir, kind)? {
+                    pairs.push((item, stored.clone()));
+                }
+            }
+        }
+    } else if let Some(lxd) = cache::load_cached(entry_dir, ItemKind::LxdMetadata)? {
+        pairs.push((stored.clone(), lxd));
+    }
+
+    for (item, lxd) in pairs {
+        let Some(expected) = &item.metadata.combined_sha256 else {
+            continue;
+        };
+        if item.metadata.serial != lxd.metadata.serial || item.verify_combined(&lxd)? {
+            continue;
+        }
+        cache::remove(stored)?;
+        return Err(Error::CombinedMismatch {
+            path: stored.archive_path.clone(),
+            expected: expected.clone(),
+        });
     }
+    Ok(())
 }
 
 /// Returns the default cache directory (`~/.local/share/arcbox/rootfs`).
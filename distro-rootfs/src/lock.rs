@@ -0,0 +1,193 @@
+//! Locking for cache entries.
+//!
+//! Two layers keep concurrent [`ensure`](crate::RootfsManager::ensure) calls
+//! for the same entry from downloading twice:
+//!
+//! - [`EntryLocks`] serializes callers within one process, so later callers
+//!   wait for the first download and then find the entry cached.
+//! - [`FileLock`] takes an advisory lock on `<entry>.lock` next to the entry
+//!   directory, so separate processes sharing a cache directory do the same.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use distro::CancellationToken;
+use fs4::fs_std::FileExt;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::Error;
+
+/// How often a contended file lock is retried.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// In-process locks, one per cache entry directory.
+#[derive(Debug, Default)]
+pub(crate) struct EntryLocks {
+    locks: Mutex<HashMap<PathBuf, Weak<tokio::sync::Mutex<()>>>>,
+}
+
+impl EntryLocks {
+    /// Locks `entry_dir` for this process, waiting for any other holder.
+    ///
+    /// `on_wait` is called once if the lock is already held.
+    pub(crate) async fn lock(
+        &self,
+        entry_dir: &Path,
+        cancel: &CancellationToken,
+        on_wait: impl FnOnce(),
+    ) -> Result<OwnedMutexGuard<()>, Error> {
+        let lock = self.get(entry_dir);
+        if let Ok(guard) = lock.clone().try_lock_owned() {
+            return Ok(guard);
+        }
+        on_wait();
+        cancel
+            .run_until_cancelled(lock.lock_owned())
+            .await
+            .ok_or(Error::Cancelled)
+    }
+
+    fn get(&self, entry_dir: &Path) -> Arc<tokio::sync::Mutex<()>> {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(lock) = locks.get(entry_dir).and_then(Weak::upgrade) {
+            return lock;
+        }
+        // Forget entries nobody is waiting on any more.
+        locks.retain(|_, lock| lock.strong_count() > 0);
+        let lock = Arc::new(tokio::sync::Mutex::new(()));
+        locks.insert(entry_dir.to_owned(), Arc::downgrade(&lock));
+        lock
+    }
+}
+
+/// An advisory exclusive lock on a file, released on drop.
+#[derive(Debug)]
+pub(crate) struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    /// Acquires an exclusive lock on `path`, creating the file if needed.
+    ///
+    /// Polls while another process holds the lock; `on_wait` is called once
+    /// if it does.
+    pub(crate) async fn acquire(
+        path: &Path,
+        cancel: &CancellationToken,
+        on_wait: impl FnOnce(),
+    ) -> Result<Self, Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+
+        let mut on_wait = Some(on_wait);
+        while !FileExt::try_lock_exclusive(&file)? {
+            if let Some(on_wait) = on_wait.take() {
+                on_wait();
+            }
+            cancel
+                .run_until_cancelled(tokio::time::sleep(LOCK_POLL_INTERVAL))
+                .await
+                .ok_or(Error::Cancelled)?;
+        }
+        Ok(Self { file })
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// Returns the lock file path for a cache entry directory.
+pub(crate) fn lock_path(entry_dir: &Path) -> PathBuf {
+    let mut name = entry_dir.file_name().unwrap_or_default().to_owned();
+    name.push(".lock");
+    entry_dir.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn lock_path_is_a_sibling() {
+        assert_eq!(
+            lock_path(Path::new("/cache/alpine/3.21/aarch64")),
+            Path::new("/cache/alpine/3.21/aarch64.lock")
+        );
+    }
+
+    #[test]
+    fn entry_locks_serialize_same_entry() {
+        let rt = runtime();
+        let locks = EntryLocks::default();
+        let cancel = CancellationToken::new();
+        let entry = Path::new("/cache/alpine/3.21/aarch64");
+
+        rt.block_on(async {
+            let _held = locks.lock(entry, &cancel, || {}).await.unwrap();
+
+            // A different entry is independent.
+            let other = Path::new("/cache/debian/12/aarch64");
+            let mut waited = false;
+            locks.lock(other, &cancel, || waited = true).await.unwrap();
+            assert!(!waited);
+
+            // The same entry waits; cancelling gives up.
+            let mut waited = false;
+            cancel.cancel();
+            let err = locks
+                .lock(entry, &cancel, || waited = true)
+                .await
+                .unwrap_err();
+            assert!(waited);
+            assert!(matches!(err, Error::Cancelled));
+        });
+    }
+
+    #[test]
+    fn file_lock_excludes_second_holder() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entry.lock");
+        let cancel = CancellationToken::new();
+
+        rt.block_on(async {
+            let held = FileLock::acquire(&path, &cancel, || {}).await.unwrap();
+
+            // A second handle (as another process would have) cannot lock it.
+            let mut waited = false;
+            let waiter = CancellationToken::new();
+            let timeout = waiter.clone();
+            let (result, ()) = futures::join!(
+                FileLock::acquire(&path, &waiter, || waited = true),
+                async move {
+                    tokio::time::sleep(Duration::from_millis(250)).await;
+                    timeout.cancel();
+                },
+            );
+            assert!(matches!(result, Err(Error::Cancelled)));
+            assert!(waited);
+
+            drop(held);
+            assert!(FileLock::acquire(&path, &cancel, || {}).await.is_ok());
+        });
+    }
+}
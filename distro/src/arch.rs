@@ -40,6 +40,18 @@ impl Arch {
     pub fn lxc_name(&self) -> &'static str {
         self.deb_name()
     }
+
+    /// Parses an LXC Images architecture name (e.g. `"arm64"`).
+    ///
+    /// Returns `None` for architectures this crate does not support
+    /// (e.g. `"armhf"`, `"riscv64"`).
+    pub fn from_lxc_name(name: &str) -> Option<Arch> {
+        match name {
+            "arm64" => Some(Self::Aarch64),
+            "amd64" => Some(Self::X86_64),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Arch {
@@ -0,0 +1,269 @@
+//! Parallel downloads over several ranged connections.
+//!
+//! A file of known size is split into contiguous byte ranges that are
+//! fetched concurrently and written at their offsets in a preallocated
+//! `.part` file. The digest is computed in a single pass once every range
+//! has landed, so the result is identical to a single-stream download.
+
+use std::cell::{Cell, RefCell};
+use std::io::SeekFrom;
+use std::path::Path;
+
+use reqwest::StatusCode;
+use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, HeaderMap, IF_RANGE, RANGE};
+use tokio::io::AsyncSeekExt;
+use tracing::debug;
+
+use super::{
+    DownloadOptions, DownloadResult, ResumeMeta, SizeLimits, StreamHasher, cancellable,
+    content_range_start, part_path, resume_meta_path, stream_body,
+};
+use crate::Error;
+use crate::http::check_status;
+use crate::progress::{ProgressEvent, TransferProgress};
+use crate::provider::HashAlgorithm;
+
+/// Smallest range worth opening a separate connection for.
+const MIN_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Why a single range could not be fetched.
+enum RangeFailure {
+    /// The server ignored the `Range` header or the resource changed; the
+    /// caller should fall back to a single stream.
+    Unsupported,
+    /// A network or I/O error.
+    Failed(Error),
+}
+
+impl From<Error> for RangeFailure {
+    fn from(e: Error) -> Self {
+        Self::Failed(e)
+    }
+}
+
+impl From<std::io::Error> for RangeFailure {
+    fn from(e: std::io::Error) -> Self {
+        Self::Failed(e.into())
+    }
+}
+
+impl From<reqwest::Error> for RangeFailure {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Failed(e.into())
+    }
+}
+
+/// Downloads `url` to `path` over `options.connections` ranged requests.
+///
+/// Returns `Ok(None)` when a parallel download is not possible (the server
+/// does not accept ranges, the size is unknown, or the file is too small to
+/// split); the caller then falls back to a single stream.
+pub(super) async fn fetch<F>(
+    options: &DownloadOptions,
+    url: &str,
+    path: &Path,
+    algorithm: HashAlgorithm,
+    limits: SizeLimits,
+    on_progress: &mut F,
+) -> Result<Option<DownloadResult>, Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    let request = options.client.head(url);
+    let response = cancellable(&options.cancel, async { Ok(request.send().await?) }).await?;
+    if !response.status().is_success() {
+        debug!(url, status = %response.status(), "HEAD failed, using a single stream");
+        return Ok(None);
+    }
+    let headers = response.headers();
+    if !accepts_ranges(headers) {
+        debug!(url, "server does not accept ranges, using a single stream");
+        return Ok(None);
+    }
+    let announced = header_content_length(headers);
+    if let Some(size) = announced {
+        limits.check_announced(url, size)?;
+    }
+    let Some(size) = announced.or(limits.expected) else {
+        return Ok(None);
+    };
+    limits.check_max(url, size)?;
+    let ranges = split_ranges(size, options.connections);
+    if ranges.len() < 2 {
+        return Ok(None);
+    }
+    let validator = ResumeMeta::from_headers(url, headers);
+    let validator = validator.as_ref().and_then(ResumeMeta::validator);
+
+    debug!(
+        url,
+        size,
+        connections = ranges.len(),
+        "starting parallel download"
+    );
+
+    let part_path = part_path(path);
+    let _ = tokio::fs::remove_file(resume_meta_path(path)).await;
+    tokio::fs::File::create(&part_path)
+        .await?
+        .set_len(size)
+        .await?;
+
+    let progress = TransferProgress::new(0, Some(size));
+    let downloaded = Cell::new(0u64);
+    let on_progress = RefCell::new(on_progress);
+    let report = |n: u64| {
+        downloaded.set(downloaded.get() + n);
+        (*on_progress.borrow_mut())(progress.event(downloaded.get()));
+    };
+    let fetches = ranges.iter().map(|&(start, end)| {
+        fetch_range(options, url, &part_path, (start, end), validator, &report)
+    });
+
+    match futures::future::try_join_all(fetches).await {
+        Ok(_) => {}
+        Err(failure) => {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return match failure {
+                RangeFailure::Unsupported => {
+                    debug!(url, "range request rejected, using a single stream");
+                    Ok(None)
+                }
+                RangeFailure::Failed(e) => Err(e),
+            };
+        }
+    }
+
+    let hasher = StreamHasher::from_file(&part_path, algorithm).await?;
+    tokio::fs::rename(&part_path, path).await?;
+    Ok(Some(DownloadResult::new(url, path, hasher.finish())))
+}
+
+/// Fetches bytes `start..=end` of `url` into the same offsets of `part_path`.
+async fn fetch_range<R>(
+    options: &DownloadOptions,
+    url: &str,
+    part_path: &Path,
+    (start, end): (u64, u64),
+    validator: Option<&str>,
+    report: &R,
+) -> Result<(), RangeFailure>
+where
+    R: Fn(u64),
+{
+    let mut request = options
+        .client
+        .get(url)
+        .header(RANGE, format!("bytes={start}-{end}"));
+    if let Some(validator) = validator {
+        request = request.header(IF_RANGE, validator);
+    }
+    let response = cancellable(&options.cancel, async { Ok(request.send().await?) }).await?;
+    let response = check_status(response)?;
+    if response.status() != StatusCode::PARTIAL_CONTENT
+        || content_range_start(response.headers()) != Some(start)
+    {
+        return Err(RangeFailure::Unsupported);
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(part_path)
+        .await?;
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut written = 0u64;
+    let len = end - start + 1;
+    stream_body(response, &mut file, options, |chunk| {
+        written += chunk.len() as u64;
+        if written > len {
+            return Err(Error::SizeMismatch {
+                url: url.to_owned(),
+                expected: len,
+                actual: written,
+            });
+        }
+        report(chunk.len() as u64);
+        Ok(())
+    })
+    .await?;
+
+    if written != len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("range {start}-{end} of {url} ended after {written} bytes"),
+        )
+        .into());
+    }
+    file.sync_all().await?;
+    Ok(())
+}
+
+/// Returns `true` if the server advertises byte-range support.
+fn accepts_ranges(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_RANGES)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|unit| unit.trim().eq_ignore_ascii_case("bytes"))
+}
+
+fn header_content_length(headers: &HeaderMap) -> Option<u64> {
+    headers.get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+/// Splits `size` bytes into at most `connections` inclusive ranges of at
+/// least [`MIN_CHUNK_SIZE`] bytes each.
+fn split_ranges(size: u64, connections: usize) -> Vec<(u64, u64)> {
+    if size == 0 {
+        return Vec::new();
+    }
+    let count = (size / MIN_CHUNK_SIZE).clamp(1, connections.max(1) as u64);
+    let chunk = size.div_ceil(count);
+    (0..count)
+        .map(|i| (i * chunk, ((i + 1) * chunk).min(size) - 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn split_covers_whole_file() {
+        let size = 100 * MIB + 7;
+        let ranges = split_ranges(size, 4);
+        assert_eq!(ranges.len(), 4);
+        assert_eq!(ranges[0].0, 0);
+        assert_eq!(ranges[3].1, size - 1);
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].1 + 1, pair[1].0);
+        }
+    }
+
+    #[test]
+    fn split_respects_min_chunk_size() {
+        assert_eq!(split_ranges(MIB, 8), vec![(0, MIB - 1)]);
+        assert_eq!(split_ranges(10 * MIB, 8).len(), 2);
+        assert_eq!(split_ranges(10 * MIB, 1).len(), 1);
+        assert_eq!(split_ranges(10 * MIB, 0).len(), 1);
+    }
+
+    #[test]
+    fn split_empty_file() {
+        assert!(split_ranges(0, 4).is_empty());
+    }
+
+    #[test]
+    fn accept_ranges_detection() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_ranges(&headers));
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("none"));
+        assert!(!accepts_ranges(&headers));
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("Bytes"));
+        assert!(accepts_ranges(&headers));
+    }
+}
@@ -0,0 +1,177 @@
+//! Delta updates from an earlier build.
+//!
+//! LXC image servers publish `.vcdiff` deltas between consecutive builds.
+//! When a local copy of an earlier build is available, the chain of deltas
+//! up to the resolved build is downloaded and applied instead of the full
+//! file. Every intermediate result is verified against the SHA256 its build
+//! lists in the index.
+
+use std::path::{Path, PathBuf};
+
+use sha2::Sha256;
+use tracing::{debug, info};
+
+use super::{
+    DeltaBase, DownloadOptions, DownloadResult, discard_partial, download_file, hash_file,
+    part_path, resume_meta_path, verify_hash,
+};
+use crate::Error;
+use crate::lxc::{LxcClient, ResolvedDelta, ResolvedImage, SimplestreamsIndex};
+use crate::progress::ProgressEvent;
+use crate::provider::HashAlgorithm;
+
+/// Updates `base` to the resolved build by applying deltas, writing the
+/// result to `dest_dir`.
+///
+/// Returns `Ok(None)` when the index has no delta chain from the base build,
+/// or when the deltas together are not smaller than the full file.
+pub(super) async fn update<F>(
+    client: &LxcClient,
+    index: &SimplestreamsIndex,
+    resolved: &ResolvedImage,
+    base: &DeltaBase,
+    dest_dir: &Path,
+    options: &DownloadOptions,
+    mut on_progress: F,
+) -> Result<Option<DownloadResult>, Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    let Some(chain) = client.resolve_delta_chain(index, resolved, &base.serial) else {
+        debug!(from = %base.serial, to = %resolved.serial, "no delta chain");
+        return Ok(None);
+    };
+    let delta_size: u64 = chain.iter().map(|step| step.size).sum();
+    if !chain.is_empty() && delta_size >= resolved.size {
+        debug!(
+            delta_size,
+            full_size = resolved.size,
+            "deltas are not smaller than the full file"
+        );
+        return Ok(None);
+    }
+    info!(
+        from = %base.serial,
+        to = %resolved.serial,
+        steps = chain.len(),
+        bytes = delta_size,
+        "updating from deltas"
+    );
+
+    let path = dest_dir.join(&resolved.filename);
+    let mut source = base.path.clone();
+    for step in &chain {
+        if options.cancel.is_cancelled() {
+            remove_intermediate(&source, base).await;
+            return Err(Error::Cancelled);
+        }
+        let applied =
+            apply_step(step, &source, dest_dir, resolved, options, &mut on_progress).await;
+        remove_intermediate(&source, base).await;
+        source = applied?;
+    }
+
+    if chain.is_empty() {
+        // The local copy already is the resolved build.
+        let base_path = base.path.clone();
+        let sha256 = blocking(move || hash_file::<Sha256>(&base_path)).await?;
+        if sha256 != resolved.sha256 {
+            return Err(Error::ChecksumMismatch {
+                expected: resolved.sha256.clone(),
+                actual: sha256,
+            });
+        }
+        if base.path != path {
+            tokio::fs::copy(&base.path, &path).await?;
+        }
+    } else {
+        tokio::fs::rename(&source, &path).await?;
+    }
+
+    let size = tokio::fs::metadata(&path).await?.len();
+    Ok(Some(DownloadResult {
+        path,
+        sha256: resolved.sha256.clone(),
+        sha512: None,
+        size,
+        filename: resolved.filename.clone(),
+        serial: Some(resolved.serial.clone()),
+        combined_sha256: resolved.combined_sha256.clone(),
+    }))
+}
+
+/// Downloads and applies one delta to `source`, returning the verified
+/// output path.
+async fn apply_step<F>(
+    step: &ResolvedDelta,
+    source: &Path,
+    dest_dir: &Path,
+    resolved: &ResolvedImage,
+    options: &DownloadOptions,
+    on_progress: &mut F,
+) -> Result<PathBuf, Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    let delta_path = dest_dir.join(format!("{}.{}.vcdiff", resolved.filename, step.serial));
+    let output = dest_dir.join(format!("{}.{}.delta", resolved.filename, step.serial));
+
+    let applied = async {
+        let delta = download_file(
+            &step.url,
+            &delta_path,
+            HashAlgorithm::Sha256,
+            Some(step.size),
+            options,
+            &mut *on_progress,
+        )
+        .await?;
+        verify_hash(&step.sha256, &delta, HashAlgorithm::Sha256)?;
+
+        on_progress(ProgressEvent::ApplyingDelta {
+            from: step.base_serial.clone(),
+            to: step.serial.clone(),
+        });
+        let (source, delta, target) = (source.to_owned(), delta_path.clone(), output.clone());
+        let sha256 = blocking(move || {
+            crate::vcdiff::apply(&source, &delta, &target)?;
+            hash_file::<Sha256>(&target)
+        })
+        .await?;
+        if sha256 != step.target_sha256 {
+            return Err(Error::ChecksumMismatch {
+                expected: step.target_sha256.clone(),
+                actual: sha256,
+            });
+        }
+        Ok(())
+    }
+    .await;
+
+    let _ = tokio::fs::remove_file(&delta_path).await;
+    if let Err(e) = applied {
+        discard_partial(&part_path(&delta_path), &resume_meta_path(&delta_path)).await;
+        let _ = tokio::fs::remove_file(&output).await;
+        return Err(e);
+    }
+    debug!(serial = %step.serial, "delta applied");
+    Ok(output)
+}
+
+/// Removes an intermediate build, leaving the caller's base file alone.
+async fn remove_intermediate(path: &Path, base: &DeltaBase) {
+    if path != base.path {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+}
+
+/// Runs blocking file work off the async runtime.
+async fn blocking<T, F>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?
+}
@@ -0,0 +1,115 @@
+//! Files from local mirrors (`file://` URLs).
+//!
+//! A Simplestreams tree on a USB stick or an NFS share is read straight from
+//! the filesystem. The copy goes through the same size limits, hashing,
+//! progress events, rate limit and cancellation as an HTTP transfer; there
+//! is nothing to resume or retry.
+
+use std::path::Path;
+
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::debug;
+
+use super::{
+    DownloadOptions, DownloadResult, SizeLimits, StreamDigest, StreamHasher, cancellable,
+    part_path, resume_meta_path,
+};
+use crate::Error;
+use crate::mirror::local_error;
+use crate::progress::{ProgressEvent, TransferProgress};
+use crate::provider::HashAlgorithm;
+
+/// Size of each read from the source file.
+const BUF_SIZE: usize = 256 * 1024;
+
+/// Copies `source` to `path` through `<path>.part`, like a download of
+/// `url`.
+pub(super) async fn copy_to_file<F>(
+    source: &Path,
+    url: &str,
+    path: &Path,
+    algorithm: HashAlgorithm,
+    limits: SizeLimits,
+    options: &DownloadOptions,
+    on_progress: F,
+) -> Result<DownloadResult, Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    debug!(source = %source.display(), "copying from local mirror");
+    let part_path = part_path(path);
+    // A partial HTTP download of the same file cannot be resumed from here.
+    let _ = tokio::fs::remove_file(resume_meta_path(path)).await;
+
+    let mut file = tokio::fs::File::create(&part_path).await?;
+    let copied = async {
+        let digest = copy(
+            source,
+            url,
+            &mut file,
+            algorithm,
+            limits,
+            options,
+            on_progress,
+        )
+        .await?;
+        file.sync_all().await?;
+        Ok(digest)
+    }
+    .await;
+    drop(file);
+    let digest = match copied {
+        Ok(digest) => digest,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(e);
+        }
+    };
+    tokio::fs::rename(&part_path, path).await?;
+    Ok(DownloadResult::new(url, path, digest))
+}
+
+/// Copies `source` into `writer`, hashing the bytes on the way.
+pub(super) async fn copy<W, F>(
+    source: &Path,
+    url: &str,
+    writer: &mut W,
+    algorithm: HashAlgorithm,
+    limits: SizeLimits,
+    options: &DownloadOptions,
+    mut on_progress: F,
+) -> Result<StreamDigest, Error>
+where
+    W: AsyncWrite + Unpin,
+    F: FnMut(ProgressEvent),
+{
+    let mut file = tokio::fs::File::open(source)
+        .await
+        .map_err(|e| local_error(source, e))?;
+    let size = file.metadata().await?.len();
+    limits.check_announced(url, size)?;
+    let progress = TransferProgress::new(0, Some(size));
+
+    let cancel = &options.cancel;
+    let mut hasher = StreamHasher::new(algorithm);
+    let mut buf = vec![0u8; BUF_SIZE];
+    loop {
+        let n = cancellable(cancel, async { Ok(file.read(&mut buf).await?) }).await?;
+        if n == 0 {
+            break;
+        }
+        let chunk = &buf[..n];
+        limits.check_received(url, hasher.size + n as u64)?;
+        hasher.update(chunk);
+        writer.write_all(chunk).await?;
+        on_progress(progress.event(hasher.size));
+        cancellable(cancel, async {
+            options.rate_limit.throttle(n as u64).await;
+            Ok(())
+        })
+        .await?;
+    }
+    writer.flush().await?;
+    limits.check_complete(url, hasher.size)?;
+    Ok(hasher.finish())
+}
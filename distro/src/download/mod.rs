@@ -0,0 +1,1614 @@
+mod chunked;
+mod delta;
+mod local;
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+
+use futures::StreamExt;
+use sha2::{Digest, Sha256, Sha512};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::http::{HttpClient, check_status};
+use crate::lxc::{IndexCache, Keyring, LxcClient};
+use crate::mirror::{Mirror, MirrorSet, file_path, no_index_mirror};
+use crate::probe::MirrorProbe;
+use crate::progress::{ProgressEvent, TransferProgress};
+use crate::provider::HashAlgorithm;
+use crate::rate_limit::RateLimit;
+use crate::retry::RetryPolicy;
+use crate::{Arch, Distro, Error, ItemKind, Variant, Version};
+
+/// Result of a successful download.
+///
+/// The archive itself lives on disk at [`path`](Self::path); only its digests
+/// are kept in memory.
+#[derive(Debug, Clone)]
+pub struct DownloadResult {
+    /// Path of the downloaded archive on disk.
+    pub path: PathBuf,
+    /// SHA256 hex digest of the downloaded data (always computed).
+    pub sha256: String,
+    /// SHA512 hex digest, computed while streaming when the source publishes
+    /// SHA-512 checksums.
+    pub sha512: Option<String>,
+    /// Size of the downloaded file in bytes.
+    pub size: u64,
+    /// Original filename from the URL.
+    pub filename: String,
+    /// Simplestreams build serial, for downloads resolved from an LXC index.
+    pub serial: Option<String>,
+    /// Combined SHA256 of the build's `lxd.tar.xz` followed by this file, as
+    /// listed in the LXC index (see [`ResolvedImage::combined_sha256`]).
+    ///
+    /// [`ResolvedImage::combined_sha256`]: crate::lxc::ResolvedImage::combined_sha256
+    pub combined_sha256: Option<String>,
+}
+
+impl DownloadResult {
+    /// Builds the result for a file that finished downloading to `path`.
+    fn new(url: &str, path: &Path, digest: StreamDigest) -> Self {
+        let filename = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(str::to_owned)
+            .unwrap_or_else(|| url_filename(url, "rootfs.tar.xz"));
+        Self {
+            path: path.to_owned(),
+            sha256: digest.sha256,
+            sha512: digest.sha512,
+            size: digest.size,
+            filename,
+            serial: None,
+            combined_sha256: None,
+        }
+    }
+
+    /// Returns the hex digest of the downloaded file for `algorithm`.
+    ///
+    /// Digests computed during the download are returned directly; a missing
+    /// SHA-512 digest is computed by streaming the file from disk.
+    pub fn digest(&self, algorithm: HashAlgorithm) -> Result<String, Error> {
+        match algorithm {
+            HashAlgorithm::Sha256 => Ok(self.sha256.clone()),
+            HashAlgorithm::Sha512 => match &self.sha512 {
+                Some(sha512) => Ok(sha512.clone()),
+                None => hash_file::<Sha512>(&self.path),
+            },
+        }
+    }
+}
+
+/// Digests computed while streaming a download into a sink.
+#[derive(Debug, Clone)]
+pub struct StreamDigest {
+    /// SHA256 hex digest of the streamed bytes.
+    pub sha256: String,
+    /// SHA512 hex digest, if requested.
+    pub sha512: Option<String>,
+    /// Number of bytes streamed.
+    pub size: u64,
+}
+
+/// Incremental hasher fed with each chunk as it is written out.
+struct StreamHasher {
+    sha256: Sha256,
+    sha512: Option<Sha512>,
+    size: u64,
+}
+
+impl StreamHasher {
+    /// Creates a hasher. SHA-256 is always computed; SHA-512 only when
+    /// `algorithm` asks for it.
+    fn new(algorithm: HashAlgorithm) -> Self {
+        Self {
+            sha256: Sha256::new(),
+            sha512: matches!(algorithm, HashAlgorithm::Sha512).then(Sha512::new),
+            size: 0,
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        self.sha256.update(chunk);
+        if let Some(sha512) = &mut self.sha512 {
+            sha512.update(chunk);
+        }
+        self.size += chunk.len() as u64;
+    }
+
+    fn finish(self) -> StreamDigest {
+        StreamDigest {
+            sha256: hex::encode(self.sha256.finalize()),
+            sha512: self.sha512.map(|h| hex::encode(h.finalize())),
+            size: self.size,
+        }
+    }
+}
+
+/// Hashes a file on disk in 8 KiB chunks.
+pub(crate) fn hash_file<D: Digest>(path: &Path) -> Result<String, Error> {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = D::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub(crate) fn verify_hash(
+    expected: &str,
+    result: &DownloadResult,
+    algorithm: HashAlgorithm,
+) -> Result<(), Error> {
+    let actual = result.digest(algorithm)?;
+    if actual != expected {
+        return Err(Error::ChecksumMismatch {
+            expected: expected.to_owned(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Returns the last path segment of a URL, or `fallback` if it has none.
+fn url_filename(url: &str, fallback: &str) -> String {
+    url.rsplit('/')
+        .next()
+        .filter(|s| !s.is_empty())
+        .unwrap_or(fallback)
+        .to_owned()
+}
+
+/// A local copy of an earlier build's item, which [`download_from_lxc`] can
+/// update with Simplestreams deltas instead of downloading the full file.
+#[derive(Debug, Clone)]
+pub struct DeltaBase {
+    /// Build serial of the local copy (e.g. `"20260217_07:42"`).
+    pub serial: String,
+    /// Path of the local copy.
+    pub path: PathBuf,
+}
+
+/// Options shared by the download entry points.
+///
+/// Cloning is cheap; the HTTP client's connection pool is shared between
+/// clones.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptions {
+    /// HTTP client used for all requests. Build one from an
+    /// [`HttpConfig`](crate::HttpConfig) to configure timeouts, proxy, CA
+    /// certificates, user agent, or extra headers.
+    pub client: HttpClient,
+    /// Retry policy for transient network failures, applied per mirror.
+    pub retry: RetryPolicy,
+    /// Mirrors to fall back to, in order, once retries against the primary
+    /// mirror are exhausted.
+    pub fallback_mirrors: Vec<Mirror>,
+    /// Number of parallel connections for a single file download.
+    ///
+    /// With `2` or more, files whose size is known are split into byte
+    /// ranges that are fetched concurrently and reassembled on disk. Servers
+    /// that do not advertise `Accept-Ranges: bytes` fall back to a single
+    /// stream. `0` or `1` (the default) always uses a single stream.
+    pub connections: usize,
+    /// Token that aborts the download when cancelled.
+    ///
+    /// A cancelled download returns [`Error::Cancelled`]. A single-stream
+    /// download keeps its `.part` file so a later call resumes it; a
+    /// parallel download removes its partial file.
+    pub cancel: CancellationToken,
+    /// Bandwidth limit for the download.
+    ///
+    /// Clones of a [`RateLimit`] share one budget, and the limit can be
+    /// changed while downloads are running. Parallel connections count
+    /// against the same limit. Unlimited by default.
+    pub rate_limit: RateLimit,
+    /// Hard cap on the size of a single download, in bytes.
+    ///
+    /// A response whose `Content-Length` exceeds the cap fails with
+    /// [`Error::TooLarge`] before its body is read, and a body that grows
+    /// past it is aborted. LXC downloads are already held to the size from
+    /// the index; this mainly guards official-source downloads, which publish
+    /// no size.
+    pub max_bytes: Option<u64>,
+    /// On-disk cache for Simplestreams indexes used by
+    /// [`download_from_lxc`]. `None` (the default) fetches the index on every
+    /// call.
+    pub index_cache: Option<IndexCache>,
+    /// Trusted keys for signed Simplestreams indexes. When set,
+    /// [`download_from_lxc`] only uses `.sjson` indexes whose OpenPGP
+    /// signature verifies against this keyring; a mirror serving bad
+    /// signatures is skipped like an unavailable one.
+    pub keyring: Option<Keyring>,
+    /// Simplestreams build serial (e.g. `"20260218_07:42"`) to download
+    /// instead of the newest build. Unpinned downloads use the same serial on
+    /// every fallback mirror as on the mirror they were first resolved from.
+    pub serial: Option<String>,
+    /// Simplestreams item [`download_from_lxc`] fetches for the build: the
+    /// rootfs tarball (the default), LXD metadata, squashfs, or VM disk.
+    pub item: ItemKind,
+    /// Earlier build of the item available locally. When the index lists a
+    /// chain of `.vcdiff` deltas from it to the resolved build,
+    /// [`download_from_lxc`] downloads and applies those instead of the full
+    /// file, and falls back to a full download if that fails.
+    pub delta_base: Option<DeltaBase>,
+    /// How [`download_from_lxc`] ranks mirrors for [`Mirror::Auto`]. Set
+    /// [`MirrorProbe::cache_file`] to reuse a ranking across runs.
+    pub mirror_probe: MirrorProbe,
+}
+
+/// Upper bound for small files fetched into memory (checksum lists).
+const MAX_IN_MEMORY_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Size constraints for a single download.
+#[derive(Debug, Clone, Copy, Default)]
+struct SizeLimits {
+    /// Exact size listed in an index.
+    expected: Option<u64>,
+    /// Maximum accepted size.
+    max: Option<u64>,
+}
+
+impl SizeLimits {
+    /// Checks the size announced by `Content-Length` before the body is read.
+    fn check_announced(&self, url: &str, size: u64) -> Result<(), Error> {
+        match self.expected {
+            Some(expected) if size != expected => Err(self.mismatch(url, expected, size)),
+            _ => self.check_max(url, size),
+        }
+    }
+
+    /// Checks the running total of received bytes.
+    fn check_received(&self, url: &str, size: u64) -> Result<(), Error> {
+        match self.expected {
+            Some(expected) if size > expected => Err(self.mismatch(url, expected, size)),
+            _ => self.check_max(url, size),
+        }
+    }
+
+    /// Checks the final size once the body has ended.
+    fn check_complete(&self, url: &str, size: u64) -> Result<(), Error> {
+        match self.expected {
+            Some(expected) if size != expected => Err(self.mismatch(url, expected, size)),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_max(&self, url: &str, size: u64) -> Result<(), Error> {
+        match self.max {
+            Some(limit) if size > limit => Err(Error::TooLarge {
+                url: url.to_owned(),
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn mismatch(&self, url: &str, expected: u64, actual: u64) -> Error {
+        Error::SizeMismatch {
+            url: url.to_owned(),
+            expected,
+            actual,
+        }
+    }
+}
+
+/// Downloads a distro rootfs using LXC Images as the source.
+///
+/// This is the recommended method — it supports all 16 distributions through
+/// a single unified API. The archive is streamed into `dest_dir` and verified
+/// against the SHA256 from the Simplestreams index; on mismatch the file is
+/// removed. `variant` selects the image variant (e.g. `cloud`); use
+/// [`Variant::default()`] for the plain `default` image. `options.item`
+/// selects the file of the build to download; each kind is verified against
+/// its own SHA256. With `options.delta_base`, the file is built from an
+/// earlier local copy and the published deltas where possible.
+///
+/// `mirrors` is a single [`Mirror`] or a [`MirrorSet`]. Transient failures
+/// are retried according to `options.retry`. When a mirror keeps failing,
+/// the download moves on to the next index mirror of the set and then to the
+/// entries of `options.fallback_mirrors`. A later mirror must list the same
+/// SHA256 for the same build serial in its own index as the mirror the image
+/// was first resolved from. [`Mirror::Auto`] is replaced by the mirrors of
+/// `options.mirror_probe`, fastest first.
+///
+/// The file itself is fetched from the set's download mirrors first, then
+/// from the mirror whose index was used. A download mirror that serves a
+/// file with another SHA256 than that index lists is skipped.
+#[allow(clippy::too_many_arguments)]
+pub async fn download_from_lxc<F>(
+    distro: Distro,
+    version: &Version,
+    arch: Arch,
+    variant: &Variant,
+    mirrors: impl Into<MirrorSet>,
+    dest_dir: &Path,
+    options: &DownloadOptions,
+    mut on_progress: F,
+) -> Result<DownloadResult, Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    let mut expected_sha256: Option<String> = None;
+    let mut serial = options.serial.clone();
+    let mut last_error = None;
+
+    let mirrors = mirrors.into();
+    let index_mirrors = expand_auto(&mirrors.index, options).await?;
+    let download_mirrors = expand_auto(&mirrors.downloads, options).await?;
+    for mirror in index_mirrors.iter().chain(&options.fallback_mirrors) {
+        let set = MirrorSet {
+            index: vec![mirror.clone()],
+            downloads: download_mirrors.clone(),
+        };
+        let mut client = LxcClient::new(set)
+            .with_http_client(options.client.clone())
+            .with_retry(options.retry.clone())
+            .with_cancellation(options.cancel.clone());
+        if let Some(cache) = &options.index_cache {
+            client = client.with_index_cache(cache.clone());
+        }
+        if let Some(keyring) = &options.keyring {
+            client = client.with_keyring(keyring.clone());
+        }
+        on_progress(ProgressEvent::FetchingIndex {
+            url: client.index_url(),
+        });
+        let fetched = cancellable(&options.cancel, async {
+            let index = client.fetch_index().await?;
+            let resolved = client.resolve_item_from_index(
+                &index,
+                distro,
+                version,
+                arch,
+                variant,
+                serial.as_deref(),
+                options.item,
+            )?;
+            Ok((index, resolved))
+        })
+        .await;
+        let (index, resolved) = match fetched {
+            Ok(fetched) => fetched,
+            Err(e)
+                if e.is_transient()
+                    || matches!(
+                        e,
+                        Error::IndexNotCached { .. }
+                            | Error::SignatureInvalid { .. }
+                            | Error::Json(_)
+                            | Error::TooLarge { .. }
+                    ) =>
+            {
+                warn!(mirror = %mirror, error = %e, "mirror unavailable, trying next");
+                last_error = Some(e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        match &expected_sha256 {
+            Some(expected) if *expected != resolved.sha256 => {
+                warn!(mirror = %mirror, "mirror lists a different SHA256, skipping");
+                last_error = Some(Error::MirrorMismatch {
+                    mirror: mirror.to_string(),
+                    expected: expected.clone(),
+                    actual: resolved.sha256,
+                });
+                continue;
+            }
+            Some(_) => {}
+            None => {
+                expected_sha256 = Some(resolved.sha256.clone());
+                serial = Some(resolved.serial.clone());
+            }
+        }
+        on_progress(ProgressEvent::Resolved {
+            url: resolved.url.clone(),
+            size: resolved.size,
+        });
+        if resolved.end_of_life {
+            warn!(
+                release = %resolved.release_title,
+                eol = ?resolved.supported_eol,
+                "release is past its end of life"
+            );
+            on_progress(ProgressEvent::EndOfLife {
+                release: resolved.release_title.clone(),
+                date: resolved.supported_eol.clone(),
+            });
+        }
+
+        info!(
+            distro = %distro,
+            version = %version,
+            arch = %arch,
+            variant = %variant,
+            serial = %resolved.serial,
+            item = %resolved.kind,
+            mirror = %mirror,
+            url = %resolved.url,
+            "downloading from LXC images"
+        );
+
+        if let Some(base) = &options.delta_base {
+            match delta::update(
+                &client,
+                &index,
+                &resolved,
+                base,
+                dest_dir,
+                options,
+                &mut on_progress,
+            )
+            .await
+            {
+                Ok(Some(result)) => return Ok(result),
+                Ok(None) => {}
+                Err(Error::Cancelled) => return Err(Error::Cancelled),
+                Err(e) => warn!(error = %e, "delta update failed, downloading the full file"),
+            }
+        }
+
+        let path = dest_dir.join(&resolved.filename);
+        for (i, source) in download_mirrors.iter().chain([mirror]).enumerate() {
+            // A download mirror may lag behind the index and lack the file.
+            let download_mirror = i < download_mirrors.len();
+            let url = source.image_url(&resolved.path);
+            let mut result = match download_file(
+                &url,
+                &path,
+                HashAlgorithm::Sha256,
+                Some(resolved.size),
+                options,
+                &mut on_progress,
+            )
+            .await
+            {
+                Ok(result) => result,
+                Err(e)
+                    if e.is_transient()
+                        || matches!(e, Error::SizeMismatch { .. })
+                        || (download_mirror && !matches!(e, Error::Cancelled)) =>
+                {
+                    warn!(mirror = %source, error = %e, "download failed, trying next mirror");
+                    last_error = Some(e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            // Verify SHA256 against the value from the Simplestreams index.
+            on_progress(ProgressEvent::Verifying);
+            if let Err(e) = verify_hash(&resolved.sha256, &result, HashAlgorithm::Sha256) {
+                let _ = tokio::fs::remove_file(&result.path).await;
+                warn!(
+                    mirror = %source,
+                    index_mirror = %mirror,
+                    error = %e,
+                    "file does not match the index, trying next mirror"
+                );
+                last_error = Some(e);
+                continue;
+            }
+
+            info!(mirror = %source, "SHA256 checksum verified");
+            result.serial = Some(resolved.serial);
+            result.combined_sha256 = resolved.combined_sha256;
+            return Ok(result);
+        }
+    }
+
+    // Every path that continues records an error, so there is none only if
+    // there was no mirror to try.
+    Err(last_error.unwrap_or_else(no_index_mirror))
+}
+
+/// Replaces [`Mirror::Auto`] in `mirrors` with the mirrors of
+/// `options.mirror_probe`, fastest first, or with [`Mirror::Official`] when
+/// there is nothing to rank (as [`MirrorProbe::select`] does). Probing stops
+/// with [`Error::Cancelled`] when `options.cancel` fires.
+async fn expand_auto(mirrors: &[Mirror], options: &DownloadOptions) -> Result<Vec<Mirror>, Error> {
+    let mut expanded = Vec::new();
+    for mirror in mirrors {
+        match mirror {
+            Mirror::Auto => {
+                let ranking = cancellable(&options.cancel, async {
+                    Ok(options.mirror_probe.ranked(&options.client).await)
+                })
+                .await?;
+                if ranking.is_empty() {
+                    expanded.push(Mirror::Official);
+                } else {
+                    expanded.extend(ranking);
+                }
+            }
+            mirror => expanded.push(mirror.clone()),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Downloads a distro image from the official source using DistroSpec templates.
+///
+/// Only available for distros that have an official DistroSpec defined
+/// (Alpine, Ubuntu, Debian, Fedora). For all other distros, use
+/// [`download_from_lxc`] instead.
+pub async fn download_distro<F>(
+    distro: Distro,
+    version: &Version,
+    arch: Arch,
+    dest_dir: &Path,
+    options: &DownloadOptions,
+    on_progress: F,
+) -> Result<DownloadResult, Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    let provider = crate::provider::get_official_provider(distro)
+        .ok_or_else(|| Error::UnsupportedDistro(distro.as_str().to_owned()))?;
+    let url = provider.rootfs_url(version, arch);
+    let path = dest_dir.join(url_filename(&url, "rootfs.tar.gz"));
+
+    info!(distro = %distro, version = %version, arch = %arch, url = %url, "downloading from official source");
+
+    let result =
+        download_to_file(&url, &path, provider.hash_algorithm(), options, on_progress).await?;
+
+    debug!(sha256 = %result.sha256, size = result.size, "download complete");
+
+    Ok(result)
+}
+
+/// Downloads from the official source with checksum verification.
+///
+/// Only available for distros with DistroSpec (Alpine, Ubuntu, Debian, Fedora).
+pub async fn download_with_verification<F>(
+    distro: Distro,
+    version: &Version,
+    arch: Arch,
+    dest_dir: &Path,
+    options: &DownloadOptions,
+    mut on_progress: F,
+) -> Result<DownloadResult, Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    let provider = crate::provider::get_official_provider(distro)
+        .ok_or_else(|| Error::UnsupportedDistro(distro.as_str().to_owned()))?;
+    let result =
+        download_distro(distro, version, arch, dest_dir, options, &mut on_progress).await?;
+
+    // Fetch and verify checksum if available.
+    if let Some(checksum_url) = provider.checksum_url(version, arch) {
+        info!(url = %checksum_url, "fetching checksum");
+        on_progress(ProgressEvent::Verifying);
+
+        let verified = async {
+            let checksum_data = download_url(&checksum_url, options).await?;
+            let checksum_text = String::from_utf8_lossy(&checksum_data);
+            let expected = provider.parse_checksum(&checksum_text, &result.filename)?;
+            verify_hash(&expected, &result, provider.hash_algorithm())
+        }
+        .await;
+
+        if let Err(e) = verified {
+            let _ = tokio::fs::remove_file(&result.path).await;
+            return Err(e);
+        }
+        info!(algorithm = ?provider.hash_algorithm(), "checksum verified");
+    }
+
+    Ok(result)
+}
+
+/// Downloads a URL into a file, hashing the bytes as they stream to disk.
+///
+/// Data is written to `<path>.part` and renamed to `path` once the body has
+/// been received completely, so a half-written file never appears under the
+/// final name. SHA-256 is always computed; pass [`HashAlgorithm::Sha512`] to
+/// also compute SHA-512 in the same pass.
+///
+/// If the download is interrupted, the `.part` file is kept together with
+/// the server's validator (`ETag` or `Last-Modified`). The next call for the
+/// same URL resumes with a `Range` / `If-Range` request and rebuilds the hash
+/// state from the bytes already on disk. When the server ignores the range or
+/// the resource has changed, the download restarts from byte zero.
+///
+/// Transient failures are retried according to `options.retry`; each retry
+/// resumes from the partial file left by the previous attempt.
+///
+/// With `options.connections` of 2 or more, the file is fetched in parallel
+/// byte ranges (see [`DownloadOptions::connections`]).
+///
+/// A `file://` URL is copied from the local filesystem, with the same
+/// hashing, size checks and progress events.
+pub async fn download_to_file<F>(
+    url: &str,
+    path: &Path,
+    algorithm: HashAlgorithm,
+    options: &DownloadOptions,
+    on_progress: F,
+) -> Result<DownloadResult, Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    download_file(url, path, algorithm, None, options, on_progress).await
+}
+
+/// [`download_to_file`] with the exact size from an index, if known.
+pub(crate) async fn download_file<F>(
+    url: &str,
+    path: &Path,
+    algorithm: HashAlgorithm,
+    expected_size: Option<u64>,
+    options: &DownloadOptions,
+    mut on_progress: F,
+) -> Result<DownloadResult, Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    let mut attempt = 1;
+    loop {
+        match fetch_to_file(
+            options,
+            url,
+            path,
+            algorithm,
+            expected_size,
+            &mut on_progress,
+        )
+        .await
+        {
+            Ok(result) => return Ok(result),
+            Err(e) => match options.retry.delay_for(attempt, &e) {
+                Some(delay) => {
+                    warn!(url = %url, error = %e, attempt, ?delay, "download failed, retrying");
+                    cancellable(&options.cancel, async {
+                        tokio::time::sleep(delay).await;
+                        Ok(())
+                    })
+                    .await?;
+                    attempt += 1;
+                }
+                None => return Err(e),
+            },
+        }
+    }
+}
+
+/// A single attempt of [`download_to_file`].
+async fn fetch_to_file<F>(
+    options: &DownloadOptions,
+    url: &str,
+    path: &Path,
+    algorithm: HashAlgorithm,
+    expected_size: Option<u64>,
+    mut on_progress: F,
+) -> Result<DownloadResult, Error>
+where
+    F: FnMut(ProgressEvent),
+{
+    let limits = SizeLimits {
+        expected: expected_size,
+        max: options.max_bytes,
+    };
+    if let Some(source) = file_path(url) {
+        return local::copy_to_file(&source, url, path, algorithm, limits, options, on_progress)
+            .await;
+    }
+
+    let part_path = part_path(path);
+    let meta_path = resume_meta_path(path);
+    let mut resume = ResumeState::load(&part_path, &meta_path, url).await;
+
+    // A resumable single-stream download takes precedence over starting a
+    // fresh parallel one.
+    if resume.is_none() && options.connections > 1 {
+        if let Some(result) =
+            chunked::fetch(options, url, path, algorithm, limits, &mut on_progress).await?
+        {
+            return Ok(result);
+        }
+    }
+
+    let response = loop {
+        let mut request = options.client.get(url);
+        if let Some(state) = &resume {
+            debug!(offset = state.offset, "resuming partial download");
+            request = request
+                .header(reqwest::header::RANGE, format!("bytes={}-", state.offset))
+                .header(reqwest::header::IF_RANGE, &state.validator);
+        }
+
+        let response = cancellable(&options.cancel, async { Ok(request.send().await?) }).await?;
+        if resume.is_some() && response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            // The partial file does not fit the remote resource; start over.
+            debug!("range not satisfiable, discarding partial download");
+            discard_partial(&part_path, &meta_path).await;
+            resume = None;
+            continue;
+        }
+        let response = check_status(response)?;
+        if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+            let start = content_range_start(response.headers());
+            match &resume {
+                Some(state) if start == Some(state.offset) => {}
+                Some(_) => {
+                    // A range other than the one asked for cannot be appended
+                    // to the partial file; start over without a range.
+                    debug!(
+                        ?start,
+                        "unexpected content range, discarding partial download"
+                    );
+                    discard_partial(&part_path, &meta_path).await;
+                    resume = None;
+                    continue;
+                }
+                None => {
+                    return Err(Error::HttpStatus {
+                        url: url.to_owned(),
+                        status: response.status().as_u16(),
+                        retry_after: None,
+                    });
+                }
+            }
+        }
+        break response;
+    };
+
+    // Past the loop, a partial response always continues the partial file;
+    // anything else restarts from byte zero.
+    let resumed = resume.is_some() && response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+
+    let offset = match &resume {
+        Some(state) if resumed => state.offset,
+        _ => 0,
+    };
+    let announced = response.content_length().map(|len| len + offset);
+    if let Some(size) = announced {
+        if let Err(e) = limits.check_announced(url, size) {
+            discard_partial(&part_path, &meta_path).await;
+            return Err(e);
+        }
+    }
+
+    let (mut file, mut hasher) = if resumed {
+        let hasher = StreamHasher::from_file(&part_path, algorithm).await?;
+        let file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&part_path)
+            .await?;
+        (file, hasher)
+    } else {
+        if resume.is_some() {
+            debug!("server ignored range request, restarting download");
+        }
+        match ResumeMeta::from_headers(url, response.headers()) {
+            Some(meta) => meta.save(&meta_path).await?,
+            None => {
+                let _ = tokio::fs::remove_file(&meta_path).await;
+            }
+        }
+        let file = tokio::fs::File::create(&part_path).await?;
+        (file, StreamHasher::new(algorithm))
+    };
+
+    let progress = TransferProgress::new(offset, announced.or(expected_size));
+
+    let streamed = async {
+        stream_body(response, &mut file, options, |chunk| {
+            limits.check_received(url, hasher.size + chunk.len() as u64)?;
+            hasher.update(chunk);
+            on_progress(progress.event(hasher.size));
+            Ok(())
+        })
+        .await?;
+        limits.check_complete(url, hasher.size)
+    }
+    .await;
+    if let Err(e) = streamed {
+        // Oversized or truncated data is useless; after any other failure
+        // the partial file and its validator stay on disk so the next
+        // attempt can resume.
+        if matches!(e, Error::SizeMismatch { .. } | Error::TooLarge { .. }) {
+            drop(file);
+            discard_partial(&part_path, &meta_path).await;
+        }
+        return Err(e);
+    }
+
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&part_path, path).await?;
+    let _ = tokio::fs::remove_file(&meta_path).await;
+
+    Ok(DownloadResult::new(url, path, hasher.finish()))
+}
+
+/// Downloads a URL into an arbitrary [`AsyncWrite`] sink, hashing the bytes
+/// as they stream through.
+///
+/// The writer is flushed but not closed. SHA-256 is always computed; pass
+/// [`HashAlgorithm::Sha512`] to also compute SHA-512. Since a sink cannot be
+/// rewound, failures are not retried.
+pub async fn download_to_writer<W, F>(
+    url: &str,
+    writer: &mut W,
+    algorithm: HashAlgorithm,
+    options: &DownloadOptions,
+    on_progress: F,
+) -> Result<StreamDigest, Error>
+where
+    W: AsyncWrite + Unpin,
+    F: FnMut(ProgressEvent),
+{
+    let limits = SizeLimits {
+        expected: None,
+        max: options.max_bytes,
+    };
+    fetch_to_writer(url, writer, algorithm, limits, options, on_progress).await
+}
+
+/// [`download_to_writer`] with explicit size limits.
+async fn fetch_to_writer<W, F>(
+    url: &str,
+    writer: &mut W,
+    algorithm: HashAlgorithm,
+    limits: SizeLimits,
+    options: &DownloadOptions,
+    mut on_progress: F,
+) -> Result<StreamDigest, Error>
+where
+    W: AsyncWrite + Unpin,
+    F: FnMut(ProgressEvent),
+{
+    if let Some(source) = file_path(url) {
+        return local::copy(
+            &source,
+            url,
+            writer,
+            algorithm,
+            limits,
+            options,
+            on_progress,
+        )
+        .await;
+    }
+    let request = options.client.get(url);
+    let response = cancellable(&options.cancel, async { Ok(request.send().await?) }).await?;
+    let response = check_status(response)?;
+    if let Some(size) = response.content_length() {
+        limits.check_announced(url, size)?;
+    }
+    let progress = TransferProgress::new(0, response.content_length());
+
+    let mut hasher = StreamHasher::new(algorithm);
+    stream_body(response, writer, options, |chunk| {
+        limits.check_received(url, hasher.size + chunk.len() as u64)?;
+        hasher.update(chunk);
+        on_progress(progress.event(hasher.size));
+        Ok(())
+    })
+    .await?;
+    limits.check_complete(url, hasher.size)?;
+
+    Ok(hasher.finish())
+}
+
+/// Copies a response body into `writer`, passing every chunk to `on_chunk`
+/// before it is written. An error from `on_chunk` aborts the copy.
+///
+/// Reads are paced by `options.rate_limit`. Stops with [`Error::Cancelled`]
+/// between chunks once `options.cancel` fires; the bytes written so far stay
+/// in `writer`.
+async fn stream_body<W, F>(
+    response: reqwest::Response,
+    writer: &mut W,
+    options: &DownloadOptions,
+    mut on_chunk: F,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+    F: FnMut(&[u8]) -> Result<(), Error>,
+{
+    let cancel = &options.cancel;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = cancellable(cancel, async { Ok(stream.next().await) }).await? {
+        let chunk = chunk?;
+        on_chunk(&chunk)?;
+        writer.write_all(&chunk).await?;
+        cancellable(cancel, async {
+            options.rate_limit.throttle(chunk.len() as u64).await;
+            Ok(())
+        })
+        .await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Runs `fut` to completion unless `cancel` fires first, in which case the
+/// future is dropped and [`Error::Cancelled`] is returned.
+pub(crate) async fn cancellable<T>(
+    cancel: &CancellationToken,
+    fut: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    cancel
+        .run_until_cancelled(fut)
+        .await
+        .unwrap_or(Err(Error::Cancelled))
+}
+
+// ---------------------------------------------------------------------------
+// Resume support
+// ---------------------------------------------------------------------------
+
+/// Validator recorded next to a `.part` file so an interrupted download can
+/// be resumed safely with `If-Range`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct ResumeMeta {
+    /// URL the partial data was fetched from.
+    url: String,
+    /// Strong `ETag` of the resource, if the server sent one.
+    etag: Option<String>,
+    /// `Last-Modified` of the resource, if the server sent one.
+    last_modified: Option<String>,
+}
+
+impl ResumeMeta {
+    /// Captures the validators from a full (`200 OK`) response.
+    ///
+    /// Returns `None` when the server sent no usable validator, in which case
+    /// an interrupted download cannot be resumed.
+    fn from_headers(url: &str, headers: &reqwest::header::HeaderMap) -> Option<Self> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        // `If-Range` requires a strong validator; weak ETags are ignored.
+        let etag = header(reqwest::header::ETAG).filter(|tag| !tag.starts_with("W/"));
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+        if etag.is_none() && last_modified.is_none() {
+            return None;
+        }
+        Some(Self {
+            url: url.to_owned(),
+            etag,
+            last_modified,
+        })
+    }
+
+    /// Returns the value to send in `If-Range` (ETag preferred).
+    fn validator(&self) -> Option<&str> {
+        self.etag.as_deref().or(self.last_modified.as_deref())
+    }
+
+    async fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_vec(self).map_err(std::io::Error::other)?;
+        tokio::fs::write(path, json).await?;
+        Ok(())
+    }
+}
+
+/// A partial download that can be continued.
+#[derive(Debug)]
+struct ResumeState {
+    /// Number of bytes already on disk.
+    offset: u64,
+    /// Value for the `If-Range` header.
+    validator: String,
+}
+
+impl ResumeState {
+    /// Loads resume state for `url`, or `None` if there is nothing usable to
+    /// resume from.
+    async fn load(part_path: &Path, meta_path: &Path, url: &str) -> Option<Self> {
+        let offset = tokio::fs::metadata(part_path).await.ok()?.len();
+        if offset == 0 {
+            return None;
+        }
+        let meta: ResumeMeta =
+            serde_json::from_slice(&tokio::fs::read(meta_path).await.ok()?).ok()?;
+        if meta.url != url {
+            return None;
+        }
+        Some(Self {
+            offset,
+            validator: meta.validator()?.to_owned(),
+        })
+    }
+}
+
+impl StreamHasher {
+    /// Rebuilds hash state from the bytes already in a partial file.
+    async fn from_file(path: &Path, algorithm: HashAlgorithm) -> Result<Self, Error> {
+        use tokio::io::AsyncReadExt;
+
+        let mut hasher = Self::new(algorithm);
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(hasher)
+    }
+}
+
+/// Parses the first byte position of a `Content-Range: bytes <start>-<end>/<len>`
+/// header.
+fn content_range_start(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    let value = headers.get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+    let range = value.strip_prefix("bytes ")?;
+    let (start, _) = range.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+/// Returns the path of the validator file kept next to a `.part` file.
+fn resume_meta_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".part.meta");
+    path.with_file_name(name)
+}
+
+/// Downloads a small resource (e.g. a checksum file) into memory, retrying
+/// transient failures.
+///
+/// The body is capped at 16 MiB (or `options.max_bytes`, if lower) so a
+/// misbehaving server cannot exhaust memory.
+pub(crate) async fn download_url(url: &str, options: &DownloadOptions) -> Result<Vec<u8>, Error> {
+    let limits = SizeLimits {
+        expected: None,
+        max: Some(
+            options
+                .max_bytes
+                .map_or(MAX_IN_MEMORY_SIZE, |max| max.min(MAX_IN_MEMORY_SIZE)),
+        ),
+    };
+    let mut attempt = 1;
+    loop {
+        let mut data = Vec::new();
+        let fetched = fetch_to_writer(
+            url,
+            &mut data,
+            HashAlgorithm::Sha256,
+            limits,
+            options,
+            |_| {},
+        )
+        .await;
+        match fetched {
+            Ok(_) => return Ok(data),
+            Err(e) => match options.retry.delay_for(attempt, &e) {
+                Some(delay) => {
+                    cancellable(&options.cancel, async {
+                        tokio::time::sleep(delay).await;
+                        Ok(())
+                    })
+                    .await?;
+                    attempt += 1;
+                }
+                None => return Err(e),
+            },
+        }
+    }
+}
+
+/// Removes a partial download and its validator so the next attempt starts
+/// from scratch.
+async fn discard_partial(part_path: &Path, meta_path: &Path) {
+    let _ = tokio::fs::remove_file(part_path).await;
+    let _ = tokio::fs::remove_file(meta_path).await;
+}
+
+/// Returns the temporary path used while `path` is being downloaded.
+pub(crate) fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_result(dir: &Path, data: &[u8], filename: &str) -> DownloadResult {
+        let path = dir.join(filename);
+        std::fs::write(&path, data).unwrap();
+        DownloadResult {
+            path,
+            sha256: hex::encode(Sha256::digest(data)),
+            sha512: None,
+            size: data.len() as u64,
+            filename: filename.to_owned(),
+            serial: None,
+            combined_sha256: None,
+        }
+    }
+
+    #[test]
+    fn stream_hasher_matches_one_shot_digest() {
+        let mut hasher = StreamHasher::new(HashAlgorithm::Sha512);
+        hasher.update(b"hello ");
+        hasher.update(b"world");
+        let digest = hasher.finish();
+
+        assert_eq!(digest.sha256, hex::encode(Sha256::digest(b"hello world")));
+        assert_eq!(
+            digest.sha512.as_deref(),
+            Some(hex::encode(Sha512::digest(b"hello world")).as_str())
+        );
+        assert_eq!(digest.size, 11);
+    }
+
+    #[test]
+    fn stream_hasher_skips_sha512_when_not_requested() {
+        let mut hasher = StreamHasher::new(HashAlgorithm::Sha256);
+        hasher.update(b"data");
+        assert!(hasher.finish().sha512.is_none());
+    }
+
+    #[test]
+    fn download_result_sha512_from_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = write_result(dir.path(), b"hello world", "test.tar.xz");
+
+        let expected = hex::encode(Sha512::digest(b"hello world"));
+        assert_eq!(result.digest(HashAlgorithm::Sha512).unwrap(), expected);
+        // SHA-512 and SHA-256 must differ.
+        assert_ne!(result.sha256, expected);
+    }
+
+    #[test]
+    fn hash_algorithm_dispatch() {
+        // Verify that the dispatch logic selects the correct hash for each
+        // algorithm variant, preferring digests computed while streaming.
+        let dir = tempfile::tempdir().unwrap();
+        let mut result = write_result(dir.path(), b"test payload", "rootfs.tar.xz");
+
+        assert_eq!(result.digest(HashAlgorithm::Sha256).unwrap(), result.sha256);
+
+        result.sha512 = Some("precomputed".to_owned());
+        assert_eq!(result.digest(HashAlgorithm::Sha512).unwrap(), "precomputed");
+    }
+
+    #[test]
+    fn verify_hash_success_sha256() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = write_result(dir.path(), b"verify ok", "rootfs.tar.gz");
+        assert!(verify_hash(&result.sha256, &result, HashAlgorithm::Sha256).is_ok());
+    }
+
+    #[test]
+    fn verify_hash_mismatch_returns_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = write_result(dir.path(), b"verify mismatch", "rootfs.tar.xz");
+
+        let err = verify_hash("deadbeef", &result, HashAlgorithm::Sha256).unwrap_err();
+        match err {
+            Error::ChecksumMismatch { expected, actual } => {
+                assert_eq!(expected, "deadbeef");
+                assert_eq!(actual, result.sha256);
+            }
+            _ => panic!("unexpected error variant"),
+        }
+    }
+
+    #[test]
+    fn part_path_appends_suffix() {
+        assert_eq!(
+            part_path(Path::new("/cache/alpine/rootfs.tar.xz")),
+            Path::new("/cache/alpine/rootfs.tar.xz.part")
+        );
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> reqwest::header::HeaderMap {
+        let mut map = reqwest::header::HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn content_range_start_parses() {
+        let h = headers(&[("content-range", "bytes 1024-2047/4096")]);
+        assert_eq!(content_range_start(&h), Some(1024));
+        let h = headers(&[("content-range", "bytes */4096")]);
+        assert_eq!(content_range_start(&h), None);
+        assert_eq!(content_range_start(&headers(&[])), None);
+    }
+
+    #[test]
+    fn resume_meta_prefers_strong_etag() {
+        let h = headers(&[
+            ("etag", "\"abc\""),
+            ("last-modified", "Wed, 18 Feb 2026 07:42:00 GMT"),
+        ]);
+        let meta = ResumeMeta::from_headers("https://x/rootfs.tar.xz", &h).unwrap();
+        assert_eq!(meta.validator(), Some("\"abc\""));
+
+        // Weak ETags cannot be used with If-Range.
+        let h = headers(&[
+            ("etag", "W/\"abc\""),
+            ("last-modified", "Wed, 18 Feb 2026 07:42:00 GMT"),
+        ]);
+        let meta = ResumeMeta::from_headers("https://x/rootfs.tar.xz", &h).unwrap();
+        assert_eq!(meta.validator(), Some("Wed, 18 Feb 2026 07:42:00 GMT"));
+
+        assert!(ResumeMeta::from_headers("https://x", &headers(&[])).is_none());
+    }
+
+    #[test]
+    fn size_limits_check_announced_and_received() {
+        let url = "https://example.com/rootfs.tar.xz";
+        let limits = SizeLimits {
+            expected: Some(100),
+            max: None,
+        };
+        assert!(limits.check_announced(url, 100).is_ok());
+        assert!(matches!(
+            limits.check_announced(url, 99),
+            Err(Error::SizeMismatch {
+                expected: 100,
+                actual: 99,
+                ..
+            })
+        ));
+        assert!(limits.check_received(url, 60).is_ok());
+        assert!(matches!(
+            limits.check_received(url, 101),
+            Err(Error::SizeMismatch { actual: 101, .. })
+        ));
+        assert!(limits.check_complete(url, 100).is_ok());
+        assert!(limits.check_complete(url, 60).is_err());
+    }
+
+    #[test]
+    fn size_limits_enforce_max_bytes() {
+        let url = "https://example.com/rootfs.tar.gz";
+        let limits = SizeLimits {
+            expected: None,
+            max: Some(1024),
+        };
+        assert!(limits.check_announced(url, 1024).is_ok());
+        assert!(matches!(
+            limits.check_announced(url, 1025),
+            Err(Error::TooLarge { limit: 1024, .. })
+        ));
+        assert!(matches!(
+            limits.check_received(url, 4096),
+            Err(Error::TooLarge { .. })
+        ));
+        // Without an expected size, any final size within the cap is fine.
+        assert!(limits.check_complete(url, 10).is_ok());
+    }
+
+    #[test]
+    fn pre_cancelled_download_returns_cancelled() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rootfs.tar.xz");
+        let options = DownloadOptions::default();
+        options.cancel.cancel();
+
+        let err = rt
+            .block_on(download_to_file(
+                "http://127.0.0.1:9/rootfs.tar.xz",
+                &path,
+                HashAlgorithm::Sha256,
+                &options,
+                |_| {},
+            ))
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert!(!err.is_transient());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn mismatched_content_range_restarts_download() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let body: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rootfs.tar.xz", listener.local_addr().unwrap());
+        let served = body.clone();
+        let server = std::thread::spawn(move || {
+            let mut ranged = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut range = false;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    range |= line.to_ascii_lowercase().starts_with("range:");
+                }
+                ranged.push(range);
+                // The ranged request is answered with the wrong range.
+                let (status, extra, data) = if range {
+                    (
+                        "206 Partial Content",
+                        "Content-Range: bytes 0-99/4096\r\n",
+                        &served[..100],
+                    )
+                } else {
+                    ("200 OK", "", &served[..])
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nETag: \"v1\"\r\n{extra}Connection: close\r\n\r\n",
+                    data.len()
+                )
+                .unwrap();
+                stream.write_all(data).unwrap();
+            }
+            ranged
+        });
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rootfs.tar.xz");
+        std::fs::write(part_path(&path), &body[..1000]).unwrap();
+        std::fs::write(
+            resume_meta_path(&path),
+            serde_json::json!({ "url": url, "etag": "\"v1\"", "last_modified": null }).to_string(),
+        )
+        .unwrap();
+
+        let result = rt
+            .block_on(download_to_file(
+                &url,
+                &path,
+                HashAlgorithm::Sha256,
+                &DownloadOptions::default(),
+                |_| {},
+            ))
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+        assert_eq!(result.sha256, hex::encode(Sha256::digest(&body)));
+        assert!(!part_path(&path).exists());
+        assert_eq!(server.join().unwrap(), [true, false]);
+    }
+
+    #[test]
+    fn auto_without_candidates_is_official() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let options = DownloadOptions {
+            mirror_probe: MirrorProbe {
+                candidates: vec![],
+                ..MirrorProbe::default()
+            },
+            ..DownloadOptions::default()
+        };
+        let mirrors = rt
+            .block_on(expand_auto(&[Mirror::Auto, Mirror::Tuna], &options))
+            .unwrap();
+        assert!(matches!(mirrors[..], [Mirror::Official, Mirror::Tuna]));
+    }
+
+    #[test]
+    fn auto_probe_stops_when_cancelled() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        // A candidate that never answers: only cancellation can end the probe.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = DownloadOptions {
+            mirror_probe: MirrorProbe {
+                candidates: vec![Mirror::Custom(format!(
+                    "http://{}",
+                    listener.local_addr().unwrap()
+                ))],
+                timeout: std::time::Duration::from_secs(60),
+                ..MirrorProbe::default()
+            },
+            ..DownloadOptions::default()
+        };
+        let cancel = options.cancel.clone();
+        let err = rt
+            .block_on(async {
+                tokio::spawn(async move {
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    cancel.cancel();
+                });
+                expand_auto(&[Mirror::Auto], &options).await
+            })
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+    }
+
+    #[test]
+    fn empty_mirror_set_is_an_error() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        // Reachable through the public fields or deserialization.
+        let mirrors: MirrorSet = serde_json::from_str(r#"{"index": []}"#).unwrap();
+
+        let err = rt
+            .block_on(LxcClient::new(mirrors.clone()).fetch_index())
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)), "{err}");
+        let err = rt
+            .block_on(download_from_lxc(
+                Distro::Alpine,
+                &Version::new("3.21"),
+                Arch::X86_64,
+                &Variant::default(),
+                mirrors,
+                dest.path(),
+                &DownloadOptions::default(),
+                |_| {},
+            ))
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)), "{err}");
+    }
+
+    #[test]
+    fn downloads_from_local_mirror() {
+        use crate::publish::{PublishImage, Publisher};
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let src = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let data = vec![7u8; 300 * 1024];
+        let image = src.path().join("rootfs.tar.xz");
+        std::fs::write(&image, &data).unwrap();
+        Publisher::new(root.path())
+            .publish(&[PublishImage {
+                path: image,
+                distro: Distro::Ubuntu,
+                version: Version::new("24.04"),
+                arch: Arch::X86_64,
+                variant: Variant::default(),
+                serial: "20260218_07:42".to_owned(),
+                kind: ItemKind::Rootfs,
+            }])
+            .unwrap();
+
+        let mirror = Mirror::local(root.path()).unwrap();
+        assert_eq!(mirror.local_path().unwrap(), root.path());
+        let download = |mirror: Mirror| {
+            let mut events = Vec::new();
+            let result = rt.block_on(download_from_lxc(
+                Distro::Ubuntu,
+                &Version::new("24.04"),
+                Arch::X86_64,
+                &Variant::default(),
+                mirror,
+                dest.path(),
+                &DownloadOptions::default(),
+                |event| events.push(event),
+            ));
+            (result, events)
+        };
+
+        let (result, events) = download(mirror.clone());
+        let result = result.unwrap();
+        assert_eq!(result.sha256, hex::encode(Sha256::digest(&data)));
+        assert_eq!(std::fs::read(&result.path).unwrap(), data);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            ProgressEvent::Downloading { downloaded, .. } if *downloaded == data.len() as u64
+        )));
+        assert!(events.iter().any(|e| matches!(e, ProgressEvent::Verifying)));
+
+        // A file changed on the local mirror fails verification.
+        let item = root
+            .path()
+            .join("images/ubuntu/noble/amd64/default/20260218_07:42/rootfs.tar.xz");
+        std::fs::write(&item, vec![8u8; data.len()]).unwrap();
+        let (result, _) = download(mirror);
+        assert!(matches!(result, Err(Error::ChecksumMismatch { .. })));
+        assert!(!dest.path().join("rootfs.tar.xz").exists());
+    }
+
+    #[test]
+    fn resume_state_requires_matching_url() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rootfs.tar.xz");
+        let part = part_path(&path);
+        let meta_path = resume_meta_path(&path);
+        std::fs::write(&part, b"partial").unwrap();
+
+        let meta = ResumeMeta {
+            url: "https://a/rootfs.tar.xz".to_owned(),
+            etag: Some("\"v1\"".to_owned()),
+            last_modified: None,
+        };
+        rt.block_on(meta.save(&meta_path)).unwrap();
+
+        let state = rt
+            .block_on(ResumeState::load(
+                &part,
+                &meta_path,
+                "https://a/rootfs.tar.xz",
+            ))
+            .unwrap();
+        assert_eq!(state.offset, 7);
+        assert_eq!(state.validator, "\"v1\"");
+
+        assert!(
+            rt.block_on(ResumeState::load(
+                &part,
+                &meta_path,
+                "https://b/rootfs.tar.xz"
+            ))
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn stream_hasher_resumes_from_partial_file() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let part = dir.path().join("rootfs.tar.xz.part");
+        std::fs::write(&part, b"hello ").unwrap();
+
+        let mut hasher = rt
+            .block_on(StreamHasher::from_file(&part, HashAlgorithm::Sha512))
+            .unwrap();
+        assert_eq!(hasher.size, 6);
+        hasher.update(b"world");
+        let digest = hasher.finish();
+
+        assert_eq!(digest.sha256, hex::encode(Sha256::digest(b"hello world")));
+        assert_eq!(
+            digest.sha512.unwrap(),
+            hex::encode(Sha512::digest(b"hello world"))
+        );
+    }
+
+    #[test]
+    fn url_filename_last_segment() {
+        assert_eq!(
+            url_filename("https://example.com/a/b/rootfs.tar.xz", "x"),
+            "rootfs.tar.xz"
+        );
+        assert_eq!(
+            url_filename("https://example.com/a/", "fallback"),
+            "fallback"
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use crate::ItemKind;
+
 /// Errors from distro download and verification.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -14,10 +16,21 @@ pub enum Error {
         version: String,
     },
 
-    /// An HTTP request failed (network error or non-2xx status).
+    /// An HTTP request failed (connection, timeout, or body error).
     #[error("HTTP request failed: {0}")]
     Http(#[from] reqwest::Error),
 
+    /// The server answered with a non-2xx status.
+    #[error("HTTP {status} from {url}")]
+    HttpStatus {
+        /// Requested URL.
+        url: String,
+        /// HTTP status code.
+        status: u16,
+        /// Delay requested by a `Retry-After` header, if any.
+        retry_after: Option<std::time::Duration>,
+    },
+
     /// Downloaded data does not match the expected checksum.
     #[error("SHA256 mismatch: expected {expected}, got {actual}")]
     ChecksumMismatch {
@@ -27,17 +40,80 @@ pub enum Error {
         actual: String,
     },
 
+    /// The server announced or sent a different number of bytes than the
+    /// index lists for the file.
+    #[error("size mismatch for {url}: expected {expected} bytes, got {actual}")]
+    SizeMismatch {
+        /// Requested URL.
+        url: String,
+        /// Size listed in the index.
+        expected: u64,
+        /// Size from `Content-Length`, or the bytes received when the body
+        /// was aborted.
+        actual: u64,
+    },
+
+    /// The download is larger than the configured
+    /// [`max_bytes`](crate::DownloadOptions::max_bytes).
+    #[error("{url} exceeds the maximum download size of {limit} bytes")]
+    TooLarge {
+        /// Requested URL.
+        url: String,
+        /// The configured maximum, in bytes.
+        limit: u64,
+    },
+
+    /// A configuration value (HTTP header, proxy URL, certificate, ...) is
+    /// invalid.
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+
     /// The checksum file could not be parsed or the target filename was not found.
     #[error("failed to parse checksum file")]
     ChecksumParse,
 
+    /// A Simplestreams document could not be parsed.
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// Offline mode was requested but no index is cached for the mirror, or
+    /// only one older than [`IndexCache::max_stale`](crate::IndexCache::max_stale).
+    #[error("no cached index for {url} (offline)")]
+    IndexNotCached {
+        /// Index URL that was not in the cache.
+        url: String,
+    },
+
+    /// The Simplestreams index does not list the requested content id.
+    #[error("stream {content_id} not found in {url}")]
+    StreamNotFound {
+        /// Requested content id (e.g. `"images"`).
+        content_id: String,
+        /// URL of the `index.json` / `index.sjson` that was searched.
+        url: String,
+    },
+
+    /// An OpenPGP key could not be imported into a
+    /// [`Keyring`](crate::Keyring).
+    #[error("invalid OpenPGP key: {0}")]
+    InvalidKey(String),
+
+    /// A signed Simplestreams document failed OpenPGP verification.
+    #[error("signature verification failed for {url}: {reason}")]
+    SignatureInvalid {
+        /// URL of the `.sjson` document.
+        url: String,
+        /// Why verification failed.
+        reason: String,
+    },
+
     /// A filesystem I/O operation failed.
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
     /// The requested distro/version/arch combination was not found in the
     /// Simplestreams index.
-    #[error("product not found: {distro} {version} ({arch})")]
+    #[error("product not found: {distro} {version} ({arch}, {variant})")]
     ProductNotFound {
         /// Distribution name.
         distro: String,
@@ -45,12 +121,85 @@ pub enum Error {
         version: String,
         /// Target architecture.
         arch: String,
+        /// Requested image variant.
+        variant: String,
+    },
+
+    /// No product for the architecture lists the requested LXC alias.
+    #[error("alias not found: {alias} ({arch})")]
+    AliasNotFound {
+        /// Requested alias (e.g. `"ubuntu/noble/cloud"`).
+        alias: String,
+        /// Target architecture.
+        arch: String,
+    },
+
+    /// The requested build serial is not listed for the product.
+    #[error("build {serial} not found for {product_key}")]
+    SerialNotFound {
+        /// The Simplestreams product key (e.g. `"alpine:3.21:amd64:default"`).
+        product_key: String,
+        /// Requested build serial.
+        serial: String,
     },
 
+    /// A fallback mirror lists a different SHA256 for the image than the
+    /// mirror it was resolved from first.
+    #[error("mirror {mirror} lists SHA256 {actual}, expected {expected}")]
+    MirrorMismatch {
+        /// Mirror that disagreed.
+        mirror: String,
+        /// SHA256 from the first mirror that resolved the image.
+        expected: String,
+        /// SHA256 from this mirror's index.
+        actual: String,
+    },
+
+    /// The operation was cancelled through its
+    /// [`CancellationToken`](crate::CancellationToken).
+    #[error("operation cancelled")]
+    Cancelled,
+
     /// A product exists in the index but has no rootfs download.
     #[error("rootfs not found in product: {product_key}")]
     RootfsNotFound {
         /// The Simplestreams product key (e.g. `"alpine:3.21:amd64:default"`).
         product_key: String,
     },
+
+    /// A VCDIFF delta is malformed or uses an unsupported feature.
+    #[error("invalid delta: {0}")]
+    InvalidDelta(String),
+
+    /// A product exists in the index but has no item of the requested kind.
+    #[error("{kind} not found in product: {product_key}")]
+    ItemNotFound {
+        /// The Simplestreams product key (e.g. `"alpine:3.21:amd64:default"`).
+        product_key: String,
+        /// Requested item kind.
+        kind: ItemKind,
+    },
+
+    /// An index lists a file path that is absolute, leaves the mirror root
+    /// (e.g. `../`), or does not end in a usable file name.
+    #[error("unsafe item path in index: {path}")]
+    UnsafePath {
+        /// The path as listed in the index.
+        path: String,
+    },
+}
+
+impl Error {
+    /// Returns `true` for failures that may succeed when retried: connection
+    /// errors, timeouts, interrupted bodies, and `408` / `429` / `5xx`
+    /// responses.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Http(e) => {
+                e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() || e.is_decode()
+            }
+            Self::HttpStatus { status, .. } => matches!(status, 408 | 429 | 500..=599),
+            _ => false,
+        }
+    }
 }
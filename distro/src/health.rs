@@ -0,0 +1,416 @@
+//! Mirror freshness checks.
+//!
+//! [`MirrorHealth`] fetches the index of a reference mirror and of the
+//! mirrors to check, and compares the products of interest: the newest
+//! build serial on each mirror, how far it lags behind the reference, and
+//! whether any build they share lists different checksums.
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::download::DownloadOptions;
+use crate::lxc::{LxcClient, Product, SimplestreamsIndex, catalog_entry, days_from_date};
+use crate::mirror::Mirror;
+use crate::{CatalogFilter, Error, ItemKind};
+
+/// Compares the indexes of several mirrors against a reference mirror.
+#[derive(Debug, Clone)]
+pub struct MirrorHealth {
+    reference: Mirror,
+    mirrors: Vec<Mirror>,
+    filters: Vec<CatalogFilter>,
+    kind: ItemKind,
+}
+
+/// Outcome of [`MirrorHealth::check`].
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    /// The mirror the others were compared against.
+    pub reference: Mirror,
+    /// One entry per checked mirror, in the order they were added.
+    pub mirrors: Vec<MirrorStatus>,
+}
+
+/// State of one mirror relative to the reference.
+#[derive(Debug, Clone)]
+pub struct MirrorStatus {
+    /// The mirror checked.
+    pub mirror: Mirror,
+    /// Why the mirror's index could not be fetched. `products` is empty
+    /// then.
+    pub error: Option<String>,
+    /// The products of interest, sorted by product key.
+    pub products: Vec<ProductStatus>,
+}
+
+/// State of one product on a mirror relative to the reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProductStatus {
+    /// Simplestreams product key (e.g. `"alpine:3.21:amd64:default"`).
+    pub product_key: String,
+    /// Newest serial on the reference mirror.
+    pub reference_serial: String,
+    /// Newest serial on this mirror, or `None` if it does not list the
+    /// product.
+    pub serial: Option<String>,
+    /// Reference builds newer than this mirror's newest.
+    pub builds_behind: usize,
+    /// Time between this mirror's newest build and the reference's, taken
+    /// from the serials (`YYYYMMDD_HH:MM`). `None` if the product is missing
+    /// or a serial has another format.
+    pub lag: Option<Duration>,
+    /// Serials listed by both mirrors for which an item has a different
+    /// SHA256, oldest first.
+    pub mismatched_serials: Vec<String>,
+}
+
+impl MirrorHealth {
+    /// Creates a check against `reference` (typically [`Mirror::Official`])
+    /// with no mirrors to compare yet.
+    ///
+    /// By default every product whose distro and architecture this crate
+    /// supports is compared, by builds that publish a rootfs.
+    pub fn new(reference: Mirror) -> Self {
+        Self {
+            reference,
+            mirrors: Vec::new(),
+            filters: Vec::new(),
+            kind: ItemKind::Rootfs,
+        }
+    }
+
+    /// Adds a mirror to compare against the reference.
+    pub fn with_mirror(mut self, mirror: Mirror) -> Self {
+        self.mirrors.push(mirror);
+        self
+    }
+
+    /// Adds several mirrors to compare, e.g. [`Mirror::presets`] minus the
+    /// reference.
+    pub fn with_mirrors(mut self, mirrors: impl IntoIterator<Item = Mirror>) -> Self {
+        self.mirrors.extend(mirrors);
+        self
+    }
+
+    /// Adds a filter. A product is compared if it matches any filter.
+    pub fn with_filter(mut self, filter: CatalogFilter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Sets the item kind a build must publish to count (default: the
+    /// rootfs).
+    pub fn with_kind(mut self, kind: ItemKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Fetches every index concurrently and compares them.
+    ///
+    /// Indexes are fetched with the HTTP client, retry policy, index cache
+    /// and keyring of `options`. An index cache younger than its `max_age`
+    /// hides newer builds, so use a short one or none for this check.
+    /// Fails if the reference index cannot be fetched; a mirror that cannot
+    /// be fetched is reported with its error.
+    pub async fn check(&self, options: &DownloadOptions) -> Result<HealthReport, Error> {
+        let fetches = std::iter::once(&self.reference)
+            .chain(&self.mirrors)
+            .map(|mirror| fetch_index(mirror, options));
+        let mut indexes = futures::future::join_all(fetches).await.into_iter();
+        let reference = indexes.next().expect("the reference is fetched")?;
+
+        let mirrors = self
+            .mirrors
+            .iter()
+            .zip(indexes)
+            .map(|(mirror, index)| match index {
+                Ok(index) => self.compare(&reference, mirror.clone(), &index),
+                Err(e) => {
+                    warn!(mirror = %mirror, error = %e, "cannot fetch mirror index");
+                    MirrorStatus {
+                        mirror: mirror.clone(),
+                        error: Some(e.to_string()),
+                        products: Vec::new(),
+                    }
+                }
+            })
+            .collect();
+        Ok(HealthReport {
+            reference: self.reference.clone(),
+            mirrors,
+        })
+    }
+
+    /// Compares a pre-fetched mirror index against a pre-fetched reference
+    /// index.
+    pub fn compare(
+        &self,
+        reference: &SimplestreamsIndex,
+        mirror: Mirror,
+        index: &SimplestreamsIndex,
+    ) -> MirrorStatus {
+        let mut products: Vec<ProductStatus> = reference
+            .products
+            .iter()
+            .filter(|(key, product)| {
+                catalog_entry(key, product).is_some_and(|entry| {
+                    self.filters.is_empty() || self.filters.iter().any(|f| f.matches(&entry))
+                })
+            })
+            .filter_map(|(key, product)| {
+                self.compare_product(key, product, index.products.get(key))
+            })
+            .collect();
+        products.sort_by(|a, b| a.product_key.cmp(&b.product_key));
+        debug!(mirror = %mirror, products = products.len(), "compared mirror index");
+        MirrorStatus {
+            mirror,
+            error: None,
+            products,
+        }
+    }
+
+    fn compare_product(
+        &self,
+        key: &str,
+        reference: &Product,
+        product: Option<&Product>,
+    ) -> Option<ProductStatus> {
+        let reference_serials: Vec<&str> = reference
+            .builds_with(self.kind)
+            .map(|(serial, _)| serial)
+            .collect();
+        let reference_serial = *reference_serials.first()?;
+        let newest = product.and_then(|p| p.builds_with(self.kind).next().map(|(s, _)| s));
+        let builds_behind = match newest {
+            Some(newest) => reference_serials.iter().filter(|s| **s > newest).count(),
+            None => reference_serials.len(),
+        };
+        let lag = newest.and_then(|newest| {
+            let lag = serial_time(reference_serial)? - serial_time(newest)?;
+            Some(Duration::from_secs(lag.max(0) as u64))
+        });
+        Some(ProductStatus {
+            product_key: key.to_owned(),
+            reference_serial: reference_serial.to_owned(),
+            serial: newest.map(str::to_owned),
+            builds_behind,
+            lag,
+            mismatched_serials: product.map_or_else(Vec::new, |p| mismatched_serials(reference, p)),
+        })
+    }
+}
+
+impl HealthReport {
+    /// Returns the mirrors that could not be fetched, lack a product, lag
+    /// more than `max_lag` behind the reference on any product, or list
+    /// different checksums.
+    pub fn unhealthy(&self, max_lag: Duration) -> impl Iterator<Item = &MirrorStatus> {
+        self.mirrors
+            .iter()
+            .filter(move |status| !status.is_healthy(max_lag))
+    }
+}
+
+impl MirrorStatus {
+    /// Returns the largest lag over all products, if any is known.
+    pub fn max_lag(&self) -> Option<Duration> {
+        self.products.iter().filter_map(|p| p.lag).max()
+    }
+
+    /// Returns whether any product lists a different checksum than the
+    /// reference for the same serial.
+    pub fn has_mismatch(&self) -> bool {
+        self.products
+            .iter()
+            .any(|p| !p.mismatched_serials.is_empty())
+    }
+
+    /// Returns whether the mirror was fetched, lists every product, is at
+    /// most `max_lag` behind, and agrees with the reference on checksums.
+    pub fn is_healthy(&self, max_lag: Duration) -> bool {
+        self.error.is_none()
+            && !self.has_mismatch()
+            && self
+                .products
+                .iter()
+                .all(|p| p.serial.is_some() && p.lag.is_none_or(|lag| lag <= max_lag))
+    }
+}
+
+/// Fetches a mirror's index with the client settings of `options`.
+async fn fetch_index(
+    mirror: &Mirror,
+    options: &DownloadOptions,
+) -> Result<SimplestreamsIndex, Error> {
+    let mut client = LxcClient::new(mirror.clone())
+        .with_http_client(options.client.clone())
+        .with_retry(options.retry.clone())
+        .with_cancellation(options.cancel.clone());
+    if let Some(cache) = &options.index_cache {
+        client = client.with_index_cache(cache.clone());
+    }
+    if let Some(keyring) = &options.keyring {
+        client = client.with_keyring(keyring.clone());
+    }
+    client.fetch_index().await
+}
+
+/// Returns the serials both products list where an item present in both has
+/// a different SHA256.
+fn mismatched_serials(reference: &Product, product: &Product) -> Vec<String> {
+    let mut serials = BTreeSet::new();
+    for (serial, build) in &product.versions {
+        let Some(reference_build) = reference.versions.get(serial) else {
+            continue;
+        };
+        let differs = build.items.iter().any(|(name, item)| {
+            reference_build
+                .items
+                .get(name)
+                .is_some_and(|reference_item| reference_item.sha256 != item.sha256)
+        });
+        if differs {
+            serials.insert(serial.clone());
+        }
+    }
+    serials.into_iter().collect()
+}
+
+/// Converts a build serial (`YYYYMMDD` or `YYYYMMDD_HH:MM`) to seconds since
+/// the Unix epoch.
+fn serial_time(serial: &str) -> Option<i64> {
+    let (date, time) = serial.split_once('_').unwrap_or((serial, "00:00"));
+    if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let days = days_from_date(&format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]))?;
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    Some(days * 86_400 + hours * 3_600 + minutes * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Distro, Version};
+
+    /// Builds an index with one product per `(key, [(serial, sha256)])`.
+    fn index(products: &[(&str, &[(&str, &str)])]) -> SimplestreamsIndex {
+        let products: serde_json::Map<String, serde_json::Value> = products
+            .iter()
+            .map(|(key, builds)| {
+                let parts: Vec<&str> = key.split(':').collect();
+                let versions: serde_json::Map<String, serde_json::Value> = builds
+                    .iter()
+                    .map(|(serial, sha256)| {
+                        let item = serde_json::json!({
+                            "ftype": "root.tar.xz",
+                            "sha256": sha256,
+                            "size": 1,
+                            "path": format!("images/{}/{serial}/rootfs.tar.xz", parts.join("/")),
+                        });
+                        let build = serde_json::json!({ "items": { "root.tar.xz": item } });
+                        (serial.to_string(), build)
+                    })
+                    .collect();
+                let product = serde_json::json!({
+                    "arch": parts[2],
+                    "os": parts[0],
+                    "release": parts[1],
+                    "release_title": parts[1],
+                    "variant": parts[3],
+                    "versions": versions,
+                });
+                (key.to_string(), product)
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({ "products": products })).unwrap()
+    }
+
+    const ALPINE: &str = "alpine:3.21:amd64:default";
+    const DEBIAN: &str = "debian:bookworm:amd64:default";
+
+    fn reference() -> SimplestreamsIndex {
+        index(&[
+            (
+                ALPINE,
+                &[
+                    ("20260216_13:00", "a16"),
+                    ("20260217_13:00", "a17"),
+                    ("20260218_07:00", "a18"),
+                ],
+            ),
+            (DEBIAN, &[("20260218_05:24", "d18")]),
+        ])
+    }
+
+    #[test]
+    fn reports_lag_and_mismatches() {
+        let health = MirrorHealth::new(Mirror::Official);
+        let mirror = index(&[(
+            ALPINE,
+            &[("20260216_13:00", "tampered"), ("20260217_13:00", "a17")],
+        )]);
+        let status = health.compare(&reference(), Mirror::Tuna, &mirror);
+
+        let alpine = &status.products[0];
+        assert_eq!(alpine.product_key, ALPINE);
+        assert_eq!(alpine.reference_serial, "20260218_07:00");
+        assert_eq!(alpine.serial.as_deref(), Some("20260217_13:00"));
+        assert_eq!(alpine.builds_behind, 1);
+        assert_eq!(alpine.lag, Some(Duration::from_secs(18 * 3600)));
+        assert_eq!(alpine.mismatched_serials, ["20260216_13:00"]);
+
+        // The mirror does not list Debian at all.
+        let debian = &status.products[1];
+        assert_eq!(debian.serial, None);
+        assert_eq!(debian.builds_behind, 1);
+        assert_eq!(debian.lag, None);
+
+        assert!(status.has_mismatch());
+        assert_eq!(status.max_lag(), Some(Duration::from_secs(18 * 3600)));
+        assert!(!status.is_healthy(Duration::from_secs(86_400)));
+    }
+
+    #[test]
+    fn current_mirror_is_healthy() {
+        let health = MirrorHealth::new(Mirror::Official).with_filter(CatalogFilter {
+            distro: Some(Distro::Alpine),
+            version: Some(Version::new("3.21")),
+            ..CatalogFilter::default()
+        });
+        let status = health.compare(&reference(), Mirror::Ustc, &reference());
+        assert_eq!(status.products.len(), 1);
+        assert_eq!(status.products[0].lag, Some(Duration::ZERO));
+        assert_eq!(status.products[0].builds_behind, 0);
+        assert!(status.is_healthy(Duration::ZERO));
+
+        let report = HealthReport {
+            reference: Mirror::Official,
+            mirrors: vec![
+                status,
+                MirrorStatus {
+                    mirror: Mirror::Bfsu,
+                    error: Some("unreachable".to_owned()),
+                    products: Vec::new(),
+                },
+            ],
+        };
+        let unhealthy: Vec<String> = report
+            .unhealthy(Duration::from_secs(3600))
+            .map(|status| status.mirror.to_string())
+            .collect();
+        assert_eq!(unhealthy, ["bfsu"]);
+    }
+
+    #[test]
+    fn serial_times() {
+        assert_eq!(serial_time("19700102_01:30"), Some(86_400 + 5_400));
+        assert_eq!(serial_time("19700102"), Some(86_400));
+        assert_eq!(serial_time("2026-02-18"), None);
+        assert_eq!(serial_time("20260218_0742"), None);
+    }
+}
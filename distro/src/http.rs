@@ -0,0 +1,204 @@
+//! Shared HTTP client configuration.
+//!
+//! Every network operation in this crate goes through an [`HttpClient`]
+//! built from an [`HttpConfig`]. Cloning an `HttpClient` is cheap and shares
+//! the underlying connection pool, so one client can serve index fetches and
+//! downloads across many calls.
+
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::Error;
+use crate::retry::parse_retry_after;
+
+/// Default `User-Agent` sent with every request.
+pub const DEFAULT_USER_AGENT: &str = "arcbox/0.1";
+
+/// Settings for the HTTP client used by downloads and index fetches.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Timeout for establishing a TCP/TLS connection.
+    pub connect_timeout: Option<Duration>,
+    /// Timeout between two successful reads of a response body. A stalled
+    /// transfer fails with a transient error and is retried/resumed.
+    pub read_timeout: Option<Duration>,
+    /// Explicit HTTP(S) proxy URL (e.g. `http://proxy.corp:3128`).
+    ///
+    /// When `None`, the `HTTP_PROXY` / `HTTPS_PROXY` / `NO_PROXY`
+    /// environment variables are honored.
+    pub proxy: Option<String>,
+    /// Comma-separated hosts that bypass [`proxy`](Self::proxy), in
+    /// `NO_PROXY` syntax. Falls back to the `NO_PROXY` environment variable.
+    pub no_proxy: Option<String>,
+    /// Additional trusted root certificates, PEM-encoded. Each entry may hold
+    /// a bundle of several certificates (e.g. a corporate CA chain).
+    pub ca_certificates: Vec<Vec<u8>>,
+    /// `User-Agent` header value.
+    pub user_agent: String,
+    /// Extra headers sent with every request.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Some(Duration::from_secs(30)),
+            read_timeout: Some(Duration::from_secs(60)),
+            proxy: None,
+            no_proxy: None,
+            ca_certificates: Vec::new(),
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Reads a PEM certificate bundle from disk and adds it to
+    /// [`ca_certificates`](Self::ca_certificates).
+    pub fn add_ca_file(&mut self, path: impl AsRef<std::path::Path>) -> Result<(), Error> {
+        self.ca_certificates.push(std::fs::read(path)?);
+        Ok(())
+    }
+
+    /// Builds a client from this configuration.
+    pub fn build(&self) -> Result<HttpClient, Error> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::InvalidConfig(format!("invalid header name: {name}")))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| Error::InvalidConfig(format!("invalid value for header {name}")))?;
+            headers.append(name, value);
+        }
+
+        let mut builder = reqwest::Client::builder()
+            .user_agent(&self.user_agent)
+            .default_headers(headers);
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            let no_proxy = match &self.no_proxy {
+                Some(list) => reqwest::NoProxy::from_string(list),
+                None => reqwest::NoProxy::from_env(),
+            };
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| Error::InvalidConfig(format!("invalid proxy URL: {e}")))?
+                .no_proxy(no_proxy);
+            builder = builder.proxy(proxy);
+        }
+        for pem in &self.ca_certificates {
+            for cert in reqwest::Certificate::from_pem_bundle(pem)
+                .map_err(|e| Error::InvalidConfig(format!("invalid CA certificate: {e}")))?
+            {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        Ok(HttpClient {
+            inner: builder.build()?,
+        })
+    }
+}
+
+/// A configured HTTP client with a shared connection pool.
+///
+/// Create one with [`HttpConfig::build`] and pass clones of it to
+/// [`DownloadOptions`](crate::DownloadOptions) and
+/// [`LxcClient`](crate::lxc::LxcClient) to reuse connections across calls.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    inner: reqwest::Client,
+}
+
+impl HttpClient {
+    /// Starts a `GET` request.
+    pub(crate) fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.inner.get(url)
+    }
+
+    /// Starts a `HEAD` request.
+    pub(crate) fn head(&self, url: &str) -> reqwest::RequestBuilder {
+        self.inner.head(url)
+    }
+}
+
+impl Default for HttpClient {
+    /// Builds a client from [`HttpConfig::default`].
+    fn default() -> Self {
+        HttpConfig::default()
+            .build()
+            .expect("failed to build HTTP client")
+    }
+}
+
+/// Turns a non-2xx response into [`Error::HttpStatus`], keeping the
+/// `Retry-After` delay for the retry policy.
+pub(crate) fn check_status(response: reqwest::Response) -> Result<reqwest::Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after);
+    Err(Error::HttpStatus {
+        url: response.url().to_string(),
+        status: status.as_u16(),
+        retry_after,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_builds() {
+        assert!(HttpConfig::default().build().is_ok());
+    }
+
+    #[test]
+    fn proxy_and_headers_build() {
+        let config = HttpConfig {
+            proxy: Some("http://proxy.corp.example:3128".to_owned()),
+            no_proxy: Some("localhost,.corp.example".to_owned()),
+            user_agent: "my-app/2.0".to_owned(),
+            headers: vec![("Authorization".to_owned(), "Bearer token".to_owned())],
+            ..HttpConfig::default()
+        };
+        assert!(config.build().is_ok());
+    }
+
+    #[test]
+    fn invalid_header_rejected() {
+        let config = HttpConfig {
+            headers: vec![("bad header".to_owned(), "x".to_owned())],
+            ..HttpConfig::default()
+        };
+        assert!(matches!(config.build(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn invalid_proxy_rejected() {
+        let config = HttpConfig {
+            proxy: Some("not a url".to_owned()),
+            ..HttpConfig::default()
+        };
+        assert!(matches!(config.build(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn add_ca_file_missing_is_io_error() {
+        let mut config = HttpConfig::default();
+        let err = config.add_ca_file("/nonexistent/ca.pem").unwrap_err();
+        assert!(matches!(err, Error::Io(_)));
+    }
+}
@@ -5,24 +5,49 @@
 //! This crate provides:
 //! - Distribution registry with version and URL resolution
 //! - Architecture detection
-//! - HTTP download with progress callbacks and SHA256 verification
+//! - Streaming HTTP download to disk with progress callbacks and SHA256 verification
+//! - Size enforcement against the index and an optional `max_bytes` cap
+//! - Optional parallel ranged downloads over several connections
 //! - LXC Images (Simplestreams) unified source for all distros
-//! - Mirror selection (official, TUNA, USTC, custom R2)
+//! - Rootfs, LXD metadata, squashfs, and VM disk items ([`ItemKind`])
+//! - Catalog of the images a mirror offers ([`CatalogEntry`])
+//! - LXC alias resolution (`ubuntu/noble/cloud`) and end-of-life detection
+//! - Delta updates from an earlier build via VCDIFF ([`DeltaBase`])
+//! - On-disk index cache with conditional revalidation and offline mode
+//! - OpenPGP verification of signed Simplestreams indexes ([`Keyring`])
+//! - Simplestreams mirror publishing from local images ([`Publisher`])
+//! - Selective mirroring of an upstream server ([`MirrorSync`])
+//! - Mirror selection (official, TUNA, USTC, custom R2), or the fastest one
+//!   by probing ([`MirrorProbe`])
+//! - Trusted index mirror with checksum-verified download mirrors
+//!   ([`MirrorSet`])
+//! - Mirrors from strings, the `ARCBOX_MIRROR` environment variable, or a
+//!   TOML file ([`MirrorSet::load`])
+//! - Local directory and `file://` mirrors for air-gapped hosts
+//! - Staleness and checksum-agreement checks across mirrors
+//!   ([`MirrorHealth`])
+//! - Retries with exponential backoff and mirror failover
+//! - Configurable, shareable HTTP client (timeouts, proxy, CA certificates)
+//! - Structured progress events (index fetch, download rate/ETA, verification)
+//! - Cancellation via [`CancellationToken`], keeping resumable partial state
+//! - Shared, runtime-adjustable bandwidth limit ([`RateLimit`])
 //!
 //! # Example
 //!
 //! ```no_run
-//! use distro::{Distro, Version, Arch, download_distro};
+//! use std::path::Path;
+//! use distro::{Distro, Version, Arch, DownloadOptions, download_distro};
 //!
 //! # async fn example() -> Result<(), distro::Error> {
-//! let bytes = download_distro(
+//! let result = download_distro(
 //!     Distro::Alpine,
 //!     &Version::new("3.20"),
 //!     Arch::current(),
-//!     |downloaded, total| {
-//!         eprintln!("{downloaded}/{total} bytes");
-//!     },
+//!     Path::new("/tmp"),
+//!     &DownloadOptions::default(),
+//!     |event| eprintln!("{event:?}"),
 //! ).await?;
+//! println!("{} ({} bytes)", result.path.display(), result.size);
 //! # Ok(())
 //! # }
 //! ```
@@ -30,14 +55,36 @@
 mod arch;
 mod download;
 mod error;
+pub mod health;
+pub mod http;
 pub mod lxc;
 pub mod mirror;
+pub mod probe;
+mod progress;
 pub mod provider;
+pub mod publish;
+pub mod rate_limit;
+pub mod retry;
+pub mod sync;
+mod vcdiff;
 
 pub use arch::Arch;
-pub use download::{download_distro, download_from_lxc, download_with_verification, DownloadResult};
+pub use download::{
+    DeltaBase, DownloadOptions, DownloadResult, StreamDigest, download_distro, download_from_lxc,
+    download_to_file, download_to_writer, download_with_verification,
+};
 pub use error::Error;
-pub use mirror::Mirror;
+pub use health::{HealthReport, MirrorHealth, MirrorStatus, ProductStatus};
+pub use http::{HttpClient, HttpConfig};
+pub use lxc::{CatalogEntry, CatalogFilter, IndexCache, Keyring};
+pub use mirror::{MIRROR_ENV, Mirror, MirrorSet};
+pub use probe::{MirrorProbe, ProbeResult};
+pub use progress::ProgressEvent;
+pub use publish::{PublishImage, Publisher};
+pub use rate_limit::RateLimit;
+pub use retry::RetryPolicy;
+pub use sync::{MirrorSync, SyncReport};
+pub use tokio_util::sync::CancellationToken;
 
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -147,40 +194,53 @@ impl Distro {
         }
     }
 
+    /// Returns the distribution whose LXC Images name is `name`
+    /// (e.g. `"rockylinux"` → [`Distro::Rocky`]).
+    pub fn from_lxc_name(name: &str) -> Option<Distro> {
+        Self::all().iter().copied().find(|d| d.lxc_name() == name)
+    }
+
     /// Maps a user-facing version to the LXC release name.
     ///
     /// For most distros the version is used as-is, but some distros use
     /// codenames in LXC (e.g. Ubuntu "24.04" → "noble").
     pub fn lxc_release(&self, version: &Version) -> String {
+        self.codenames()
+            .iter()
+            .find(|(v, _)| *v == version.as_str())
+            .map_or(version.as_str(), |(_, codename)| codename)
+            .to_owned()
+    }
+
+    /// Maps an LXC release name back to the user-facing version
+    /// (e.g. Ubuntu "noble" → "24.04"). The inverse of [`lxc_release`].
+    ///
+    /// [`lxc_release`]: Distro::lxc_release
+    pub fn version_from_lxc_release(&self, release: &str) -> Version {
+        self.codenames()
+            .iter()
+            .find(|(_, codename)| *codename == release)
+            .map_or(Version::new(release), |(v, _)| Version::new(v))
+    }
+
+    /// Version ↔ LXC codename pairs for distros that publish codenames.
+    fn codenames(&self) -> &'static [(&'static str, &'static str)] {
         match self {
-            Self::Ubuntu => match version.as_str() {
-                "20.04" => "focal".to_owned(),
-                "22.04" => "jammy".to_owned(),
-                "24.04" => "noble".to_owned(),
-                "24.10" => "oracular".to_owned(),
-                "25.04" => "plucky".to_owned(),
-                other => other.to_owned(),
-            },
-            Self::Debian => match version.as_str() {
-                "10" => "buster".to_owned(),
-                "11" => "bullseye".to_owned(),
-                "12" => "bookworm".to_owned(),
-                "13" => "trixie".to_owned(),
-                other => other.to_owned(),
-            },
-            Self::Devuan => match version.as_str() {
-                "4" => "chimaera".to_owned(),
-                "5" => "daedalus".to_owned(),
-                "6" => "excalibur".to_owned(),
-                other => other.to_owned(),
-            },
-            Self::OpenSuse => match version.as_str() {
-                "15.6" => "15.6".to_owned(),
-                "16.0" => "16.0".to_owned(),
-                "tumbleweed" => "tumbleweed".to_owned(),
-                other => other.to_owned(),
-            },
-            _ => version.as_str().to_owned(),
+            Self::Ubuntu => &[
+                ("20.04", "focal"),
+                ("22.04", "jammy"),
+                ("24.04", "noble"),
+                ("24.10", "oracular"),
+                ("25.04", "plucky"),
+            ],
+            Self::Debian => &[
+                ("10", "buster"),
+                ("11", "bullseye"),
+                ("12", "bookworm"),
+                ("13", "trixie"),
+            ],
+            Self::Devuan => &[("4", "chimaera"), ("5", "daedalus"), ("6", "excalibur")],
+            _ => &[],
         }
     }
 
@@ -241,6 +301,109 @@ impl From<&str> for Version {
     }
 }
 
+/// An LXC image variant (e.g. `"default"`, `"cloud"`, `"openrc"`).
+///
+/// Every LXC product publishes `default`; many also publish `cloud` (with
+/// cloud-init), and some publish others such as `openrc` / `systemd`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Variant(String);
+
+impl Variant {
+    /// Creates a variant from its LXC name (e.g. `"cloud"`).
+    pub fn new(variant: &str) -> Self {
+        Self(variant.to_owned())
+    }
+
+    /// The `cloud` variant, with cloud-init preinstalled.
+    pub fn cloud() -> Self {
+        Self::new("cloud")
+    }
+
+    /// Returns the variant as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns `true` for the `default` variant.
+    pub fn is_default(&self) -> bool {
+        self.0 == "default"
+    }
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Self::new("default")
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for Variant {
+    fn from(s: &str) -> Self {
+        Self::new(s)
+    }
+}
+
+/// A kind of file published for each LXC image build.
+///
+/// Besides the rootfs tarball, the LXC image server publishes LXD/Incus
+/// metadata, a squashfs rootfs, and a VM disk image for most products.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ItemKind {
+    /// Root filesystem tarball (`rootfs.tar.xz`).
+    #[default]
+    Rootfs,
+    /// LXD/Incus image metadata (`lxd.tar.xz`).
+    LxdMetadata,
+    /// Root filesystem as a squashfs image (`rootfs.squashfs`).
+    Squashfs,
+    /// Bootable VM disk image (`disk.qcow2`).
+    DiskKvm,
+}
+
+impl ItemKind {
+    /// Returns the identifier used in cache paths (e.g. `"squashfs"`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Rootfs => "rootfs",
+            Self::LxdMetadata => "lxd-metadata",
+            Self::Squashfs => "squashfs",
+            Self::DiskKvm => "disk-kvm",
+        }
+    }
+
+    /// Returns the Simplestreams `ftype` of this item (e.g. `"disk-kvm.img"`).
+    pub fn ftype(&self) -> &'static str {
+        match self {
+            Self::Rootfs => "root.tar.xz",
+            Self::LxdMetadata => "lxd.tar.xz",
+            Self::Squashfs => "squashfs",
+            Self::DiskKvm => "disk-kvm.img",
+        }
+    }
+
+    /// Returns all item kinds.
+    pub fn all() -> &'static [ItemKind] {
+        &[
+            Self::Rootfs,
+            Self::LxdMetadata,
+            Self::Squashfs,
+            Self::DiskKvm,
+        ]
+    }
+}
+
+impl fmt::Display for ItemKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Parse a distro spec string like "alpine:3.20" or "ubuntu".
 ///
 /// If no version is specified, the default version for that distro is used.
@@ -327,6 +490,41 @@ mod tests {
         assert_eq!(Distro::Fedora.lxc_release(&Version::new("41")), "41");
     }
 
+    #[test]
+    fn lxc_release_round_trip() {
+        for distro in Distro::all() {
+            for version in ["24.04", "12", "5", "3.21", "tumbleweed"] {
+                let release = distro.lxc_release(&Version::new(version));
+                assert_eq!(distro.version_from_lxc_release(&release).as_str(), version);
+            }
+        }
+        assert_eq!(
+            Distro::Ubuntu.version_from_lxc_release("noble").as_str(),
+            "24.04"
+        );
+        assert_eq!(
+            Distro::Ubuntu.version_from_lxc_release("questing").as_str(),
+            "questing"
+        );
+    }
+
+    #[test]
+    fn from_lxc_name() {
+        for distro in Distro::all() {
+            assert_eq!(Distro::from_lxc_name(distro.lxc_name()), Some(*distro));
+        }
+        assert_eq!(Distro::from_lxc_name("amazonlinux"), None);
+    }
+
+    #[test]
+    fn item_kind_names() {
+        assert_eq!(ItemKind::DiskKvm.ftype(), "disk-kvm.img");
+        for kind in ItemKind::all() {
+            let json = serde_json::to_string(kind).unwrap();
+            assert_eq!(json, format!("\"{kind}\""));
+        }
+    }
+
     #[test]
     fn all_distros_count() {
         assert_eq!(Distro::all().len(), 16);
@@ -0,0 +1,234 @@
+//! On-disk cache for Simplestreams indexes.
+//!
+//! Each index URL is stored as `<key>.json` with a `<key>.meta.json` sidecar
+//! holding its `ETag`, `Last-Modified`, and fetch time, where `<key>` is
+//! derived from the URL. A fresh entry is used without any network request;
+//! a stale one is revalidated with a conditional request.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+/// On-disk cache of Simplestreams indexes, one entry per mirror index URL.
+#[derive(Debug, Clone)]
+pub struct IndexCache {
+    /// Directory holding the cached indexes.
+    pub dir: PathBuf,
+    /// Age below which a cached index is used without contacting the mirror.
+    /// Older entries are revalidated with `If-None-Match` /
+    /// `If-Modified-Since`; `Duration::ZERO` revalidates on every fetch.
+    pub max_age: Duration,
+    /// Never contact the mirror: resolve from the cached index, as old as
+    /// `max_stale` allows, or fail with [`Error::IndexNotCached`].
+    pub offline: bool,
+    /// Use a stale cached index, up to `max_stale` old, when the mirror
+    /// cannot be reached.
+    pub stale_if_error: bool,
+    /// Oldest index `offline` and `stale_if_error` may use; `None` accepts
+    /// any age. A mirror deletes old builds, so an old index can name files
+    /// that no longer exist. A stale index is always logged with its age.
+    pub max_stale: Option<Duration>,
+}
+
+impl IndexCache {
+    /// Creates a cache in `dir` with a one-hour `max_age`, online, falling
+    /// back to stale entries of any age on network errors.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_age: Duration::from_secs(60 * 60),
+            offline: false,
+            stale_if_error: true,
+            max_stale: None,
+        }
+    }
+
+    /// Loads the cached index for `url`, if any.
+    pub(crate) async fn load(&self, url: &str) -> Option<CachedIndex> {
+        let (body_path, meta_path) = self.paths(url);
+        let meta: IndexMeta =
+            serde_json::from_slice(&tokio::fs::read(&meta_path).await.ok()?).ok()?;
+        if meta.url != url {
+            return None;
+        }
+        let body = tokio::fs::read(&body_path).await.ok()?;
+        Some(CachedIndex { body, meta })
+    }
+
+    /// Returns `true` if `cached` may be used without revalidation.
+    pub(crate) fn is_fresh(&self, cached: &CachedIndex) -> bool {
+        cached.meta.age() < self.max_age
+    }
+
+    /// Returns `true` if `cached` is young enough to be used in place of a
+    /// fresh index (see [`max_stale`](Self::max_stale)).
+    pub(crate) fn is_usable_stale(&self, cached: &CachedIndex) -> bool {
+        self.max_stale.is_none_or(|max| cached.meta.age() <= max)
+    }
+
+    /// Stores a freshly downloaded index.
+    pub(crate) async fn store(
+        &self,
+        url: &str,
+        body: &[u8],
+        etag: Option<String>,
+        last_modified: Option<String>,
+    ) -> Result<(), Error> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let (body_path, meta_path) = self.paths(url);
+        let meta = IndexMeta {
+            url: url.to_owned(),
+            etag,
+            last_modified,
+            fetched_at: unix_now(),
+        };
+        write_atomic(&body_path, body).await?;
+        write_atomic(&meta_path, &serde_json::to_vec(&meta)?).await
+    }
+
+    /// Marks a cached index as fresh again after a `304 Not Modified`.
+    pub(crate) async fn touch(&self, cached: &mut CachedIndex) -> Result<(), Error> {
+        cached.meta.fetched_at = unix_now();
+        let (_, meta_path) = self.paths(&cached.meta.url);
+        write_atomic(&meta_path, &serde_json::to_vec(&cached.meta)?).await
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key = &hex::encode(Sha256::digest(url.as_bytes()))[..16];
+        (
+            self.dir.join(format!("{key}.json")),
+            self.dir.join(format!("{key}.meta.json")),
+        )
+    }
+}
+
+/// A cached index body with its validators.
+#[derive(Debug)]
+pub(crate) struct CachedIndex {
+    pub(crate) body: Vec<u8>,
+    pub(crate) meta: IndexMeta,
+}
+
+impl CachedIndex {
+    /// Time since the index was last fetched or revalidated.
+    pub(crate) fn age(&self) -> Duration {
+        self.meta.age()
+    }
+}
+
+/// Sidecar metadata for a cached index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct IndexMeta {
+    /// URL the index was fetched from.
+    pub(crate) url: String,
+    /// `ETag` from the last full response.
+    pub(crate) etag: Option<String>,
+    /// `Last-Modified` from the last full response.
+    pub(crate) last_modified: Option<String>,
+    /// Unix timestamp (seconds) of the last fetch or revalidation.
+    fetched_at: u64,
+}
+
+impl IndexMeta {
+    fn age(&self) -> Duration {
+        Duration::from_secs(unix_now().saturating_sub(self.fetched_at))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Writes `data` to a temporary file and renames it over `path`.
+async fn write_atomic(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://images.linuxcontainers.org/streams/v1/images.json";
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn store_and_load_roundtrip() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = IndexCache::new(dir.path());
+
+        rt.block_on(async {
+            assert!(cache.load(URL).await.is_none());
+            cache
+                .store(URL, b"{}", Some("\"abc\"".to_owned()), None)
+                .await
+                .unwrap();
+
+            let cached = cache.load(URL).await.unwrap();
+            assert_eq!(cached.body, b"{}");
+            assert_eq!(cached.meta.etag.as_deref(), Some("\"abc\""));
+            assert!(cache.is_fresh(&cached));
+
+            // Other mirrors have their own entries.
+            assert!(
+                cache
+                    .load("https://other/streams/v1/images.json")
+                    .await
+                    .is_none()
+            );
+        });
+    }
+
+    #[test]
+    fn freshness_follows_max_age() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = IndexCache::new(dir.path());
+
+        rt.block_on(async {
+            cache.store(URL, b"{}", None, None).await.unwrap();
+            let mut cached = cache.load(URL).await.unwrap();
+            cached.meta.fetched_at -= 120;
+
+            cache.max_age = Duration::from_secs(60);
+            assert!(!cache.is_fresh(&cached));
+
+            cache.touch(&mut cached).await.unwrap();
+            let cached = cache.load(URL).await.unwrap();
+            assert!(cache.is_fresh(&cached));
+        });
+    }
+
+    #[test]
+    fn stale_use_follows_max_stale() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = IndexCache::new(dir.path());
+
+        rt.block_on(async {
+            cache.store(URL, b"{}", None, None).await.unwrap();
+            let mut cached = cache.load(URL).await.unwrap();
+            cached.meta.fetched_at -= 120;
+            assert!(cache.is_usable_stale(&cached));
+
+            cache.max_stale = Some(Duration::from_secs(60));
+            assert!(!cache.is_usable_stale(&cached));
+        });
+    }
+}
@@ -0,0 +1,2271 @@
+//! LXC Images Simplestreams client.
+//!
+//! Provides a unified interface to download rootfs images for all supported
+//! distributions from [images.linuxcontainers.org](https://images.linuxcontainers.org)
+//! or compatible mirrors.
+//!
+//! The Simplestreams protocol starts from `streams/v1/index.json`, which
+//! lists the product streams of a mirror by content id. The `images` stream
+//! (`images.json`) lists all available products with their download paths and
+//! SHA256 checksums. With a [`Keyring`], the OpenPGP-signed `.sjson` variants
+//! are fetched and verified instead. An [`IndexCache`] keeps the last
+//! documents of each mirror on disk.
+
+mod cache;
+mod pgp;
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::download::cancellable;
+use crate::http::{HttpClient, check_status};
+use crate::mirror::{Mirror, MirrorSet, file_path, local_error, no_index_mirror};
+use crate::retry::RetryPolicy;
+use crate::{Arch, Distro, Error, ItemKind, Variant, Version};
+
+pub use cache::IndexCache;
+pub use pgp::Keyring;
+
+/// Content id of the LXC images product stream.
+const DEFAULT_CONTENT_ID: &str = "images";
+
+/// Upper bound for a Simplestreams document. The official `images.json` is a
+/// few MiB; anything far beyond that is not an index.
+const MAX_INDEX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Resolved image info from the Simplestreams index.
+#[derive(Debug, Clone)]
+pub struct ResolvedImage {
+    /// Simplestreams product key (e.g. `"alpine:3.21:amd64:default"`).
+    pub product_key: String,
+    /// Kind of item that was resolved.
+    pub kind: ItemKind,
+    /// Full download URL.
+    pub url: String,
+    /// Path of the file relative to the mirror root, to build its URL on
+    /// another mirror with [`Mirror::image_url`].
+    pub path: String,
+    /// Expected SHA256 hash of the file.
+    pub sha256: String,
+    /// File size in bytes.
+    pub size: u64,
+    /// Filename (e.g. "rootfs.tar.xz").
+    pub filename: String,
+    /// Build serial the image comes from (e.g. `"20260218_07:42"`).
+    pub serial: String,
+    /// SHA256 of the build's `lxd.tar.xz` followed by this item, as listed
+    /// in the index (`combined_*_sha256`). This is the LXD image
+    /// fingerprint; `None` for the metadata item itself or when unlisted.
+    pub combined_sha256: Option<String>,
+    /// Human-readable release title (e.g. `"Ubuntu 24.04 LTS"`).
+    pub release_title: String,
+    /// End-of-life date of the release (`YYYY-MM-DD`), if listed.
+    pub supported_eol: Option<String>,
+    /// Whether the release is past its end of life, see
+    /// [`Product::is_end_of_life`].
+    pub end_of_life: bool,
+}
+
+/// One step of a delta chain from [`LxcClient::resolve_delta_chain`]: a
+/// `.vcdiff` file that turns one build's item into the next one's.
+#[derive(Debug, Clone)]
+pub struct ResolvedDelta {
+    /// Build the delta applies to.
+    pub base_serial: String,
+    /// Build the delta produces.
+    pub serial: String,
+    /// Full download URL of the `.vcdiff` file.
+    pub url: String,
+    /// Expected SHA256 of the `.vcdiff` file.
+    pub sha256: String,
+    /// Size of the `.vcdiff` file in bytes.
+    pub size: u64,
+    /// Expected SHA256 of the item the delta produces.
+    pub target_sha256: String,
+}
+
+/// An image available on a mirror, as listed by [`LxcClient::catalog`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CatalogEntry {
+    /// Distribution.
+    pub distro: Distro,
+    /// User-facing version (LXC codenames are mapped back, e.g. `"24.04"`).
+    pub version: Version,
+    /// Architecture.
+    pub arch: Arch,
+    /// Image variant.
+    pub variant: Variant,
+    /// Newest build serial that has a rootfs.
+    pub serial: String,
+    /// Size of that build's rootfs in bytes.
+    pub size: u64,
+    /// Human-readable release title from the index (e.g. `"24.04 LTS"`).
+    pub release_title: String,
+    /// LXC aliases of the product (e.g. `"ubuntu/noble"`).
+    pub aliases: Vec<String>,
+    /// Whether the release is past its end of life.
+    pub end_of_life: bool,
+}
+
+impl CatalogEntry {
+    fn sort_key(&self) -> (&str, &str, &str, &str) {
+        (
+            self.distro.as_str(),
+            self.version.as_str(),
+            self.arch.lxc_name(),
+            self.variant.as_str(),
+        )
+    }
+}
+
+/// Filter for [`LxcClient::catalog`]. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct CatalogFilter {
+    /// Only list this distribution.
+    pub distro: Option<Distro>,
+    /// Only list this version (user-facing, e.g. `"24.04"`).
+    pub version: Option<Version>,
+    /// Only list this architecture.
+    pub arch: Option<Arch>,
+    /// Only list this variant.
+    pub variant: Option<Variant>,
+}
+
+impl CatalogFilter {
+    /// Returns `true` if `entry` passes every set field.
+    pub fn matches(&self, entry: &CatalogEntry) -> bool {
+        self.distro.is_none_or(|d| d == entry.distro)
+            && self.version.as_ref().is_none_or(|v| *v == entry.version)
+            && self.arch.is_none_or(|a| a == entry.arch)
+            && self.variant.as_ref().is_none_or(|v| *v == entry.variant)
+    }
+}
+
+/// Client for the LXC Images Simplestreams API.
+pub struct LxcClient {
+    mirrors: MirrorSet,
+    http: HttpClient,
+    retry: RetryPolicy,
+    index_cache: Option<IndexCache>,
+    keyring: Option<Keyring>,
+    content_id: String,
+    cancel: CancellationToken,
+}
+
+impl LxcClient {
+    /// Creates a new client backed by the given mirror or [`MirrorSet`],
+    /// using a default [`HttpClient`].
+    ///
+    /// The index is fetched from the set's index mirrors in order; file URLs
+    /// point at its first download mirror, if any, and otherwise at the
+    /// mirror the index came from.
+    pub fn new(mirrors: impl Into<MirrorSet>) -> Self {
+        Self {
+            mirrors: mirrors.into(),
+            http: HttpClient::default(),
+            retry: RetryPolicy::default(),
+            index_cache: None,
+            keyring: None,
+            content_id: DEFAULT_CONTENT_ID.to_owned(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Uses a shared, configured HTTP client (proxy, timeouts, CA bundle, ...).
+    pub fn with_http_client(mut self, http: HttpClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Sets the retry policy used for index fetches.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Keeps fetched indexes in an on-disk [`IndexCache`].
+    pub fn with_index_cache(mut self, cache: IndexCache) -> Self {
+        self.index_cache = Some(cache);
+        self
+    }
+
+    /// Requires signed indexes: fetches `index.sjson` and the signed product
+    /// stream, and verifies both against `keyring` before trusting any
+    /// checksum in them.
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    /// Selects the product stream by content id (default `"images"`).
+    pub fn with_content_id(mut self, content_id: impl Into<String>) -> Self {
+        self.content_id = content_id.into();
+        self
+    }
+
+    /// Aborts index fetches, including retry back-off, with
+    /// [`Error::Cancelled`] once `cancel` fires.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Returns the URL of the Simplestreams index this client starts from:
+    /// `index.sjson` with a keyring, `index.json` otherwise.
+    pub fn index_url(&self) -> String {
+        self.index_url_of(self.mirrors.primary())
+    }
+
+    fn index_url_of(&self, mirror: &Mirror) -> String {
+        let url = mirror.index_url();
+        match self.keyring {
+            Some(_) => signed_path(&url),
+            None => url,
+        }
+    }
+
+    /// Returns the first index mirror this client talks to.
+    pub fn mirror(&self) -> &Mirror {
+        self.mirrors.primary()
+    }
+
+    /// Returns the mirrors this client talks to.
+    pub fn mirrors(&self) -> &MirrorSet {
+        &self.mirrors
+    }
+
+    /// Returns the download URL of `path`, a file listed in `index`.
+    ///
+    /// Files come from the first download mirror, if any, and otherwise from
+    /// the mirror `index` was fetched from.
+    pub fn item_url(&self, index: &SimplestreamsIndex, path: &str) -> String {
+        self.mirrors
+            .downloads
+            .first()
+            .or(index.source.as_ref())
+            .unwrap_or(self.mirrors.primary())
+            .image_url(path)
+    }
+
+    /// Resolves the download URL and SHA256 for a rootfs image.
+    ///
+    /// `serial` pins a specific build (e.g. `"20260218_07:42"`); `None`
+    /// selects the newest complete build.
+    pub async fn resolve(
+        &self,
+        distro: Distro,
+        version: &Version,
+        arch: Arch,
+        variant: &Variant,
+        serial: Option<&str>,
+    ) -> Result<ResolvedImage, Error> {
+        self.resolve_item(distro, version, arch, variant, serial, ItemKind::Rootfs)
+            .await
+    }
+
+    /// Resolves the download URL and SHA256 for an item of `kind`, such as
+    /// the squashfs rootfs or the VM disk image.
+    pub async fn resolve_item(
+        &self,
+        distro: Distro,
+        version: &Version,
+        arch: Arch,
+        variant: &Variant,
+        serial: Option<&str>,
+        kind: ItemKind,
+    ) -> Result<ResolvedImage, Error> {
+        let index = self.fetch_index().await?;
+        self.resolve_item_from_index(&index, distro, version, arch, variant, serial, kind)
+    }
+
+    /// Resolves an item of `kind` by LXC alias (e.g. `"alpine/edge"` or
+    /// `"ubuntu/noble/cloud"`).
+    ///
+    /// See [`resolve_alias_from_index`](Self::resolve_alias_from_index).
+    pub async fn resolve_alias(
+        &self,
+        alias: &str,
+        arch: Arch,
+        serial: Option<&str>,
+        kind: ItemKind,
+    ) -> Result<ResolvedImage, Error> {
+        let index = self.fetch_index().await?;
+        self.resolve_alias_from_index(&index, alias, arch, serial, kind)
+    }
+
+    /// Fetches and parses the product stream (`images.json`).
+    ///
+    /// The stream is located through `index.json`. Mirrors without one fall
+    /// back to `streams/v1/images.json`, unless a [`Keyring`] is set: signed
+    /// indexes are then required and verified, and no unsigned data is used.
+    ///
+    /// Transient failures are retried according to the client's
+    /// [`RetryPolicy`]. With an [`IndexCache`], a fresh cached document is
+    /// used without any network request and a stale one is revalidated.
+    ///
+    /// With several index mirrors, each is tried in order until one yields
+    /// an index; the last error is returned if none does.
+    pub async fn fetch_index(&self) -> Result<SimplestreamsIndex, Error> {
+        for (i, mirror) in self.mirrors.index.iter().enumerate() {
+            match self.fetch_index_from(mirror).await {
+                Ok(index) => return Ok(index),
+                Err(e) if i + 1 < self.mirrors.index.len() => {
+                    warn!(mirror = %mirror, error = %e, "mirror unavailable, trying next");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(no_index_mirror())
+    }
+
+    async fn fetch_index_from(&self, mirror: &Mirror) -> Result<SimplestreamsIndex, Error> {
+        let stream_url = self.locate_stream(mirror).await?;
+        let mut index: SimplestreamsIndex = self.fetch_document(&stream_url).await?;
+        index.source = Some(mirror.clone());
+        debug!(mirror = %mirror, products = index.products.len(), "index loaded");
+        Ok(index)
+    }
+
+    /// Returns the URL of `mirror`'s product stream for the client's
+    /// content id.
+    async fn locate_stream(&self, mirror: &Mirror) -> Result<String, Error> {
+        let index_url = self.index_url_of(mirror);
+        let streams: StreamsIndex = match self.fetch_document(&index_url).await {
+            Ok(streams) => streams,
+            Err(e) if is_not_found(&e) && self.keyring.is_none() => {
+                debug!(url = %index_url, "no index.json, using images.json");
+                return Ok(mirror.streams_url());
+            }
+            Err(e) => return Err(e),
+        };
+
+        let entry = streams
+            .index
+            .get(&self.content_id)
+            .ok_or_else(|| Error::StreamNotFound {
+                content_id: self.content_id.clone(),
+                url: index_url.clone(),
+            })?;
+        let url = mirror.image_url(&entry.path);
+        Ok(match self.keyring {
+            Some(_) => signed_path(&url),
+            None => url,
+        })
+    }
+
+    /// Fetches and parses one Simplestreams document, through the cache if
+    /// configured, and verifies its signature if a keyring is set. Documents
+    /// of a local (`file://`) mirror are read directly and never cached.
+    async fn fetch_document<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        let body = match (file_path(url), &self.index_cache) {
+            (Some(path), _) => {
+                debug!(path = %path.display(), "reading local simplestreams index");
+                let size = tokio::fs::metadata(&path)
+                    .await
+                    .map_err(|e| local_error(&path, e))?
+                    .len();
+                check_index_size(url, size)?;
+                tokio::fs::read(&path)
+                    .await
+                    .map_err(|e| local_error(&path, e))?
+            }
+            (None, Some(cache)) => return self.fetch_index_cached(cache, url).await,
+            (None, None) => match self.fetch_index_with_retry(url, None).await? {
+                IndexResponse::Body { body, .. } => body,
+                IndexResponse::NotModified => unreachable!("unconditional request"),
+            },
+        };
+        self.decode(url, body)
+    }
+
+    /// Verifies the signature of a document, if a keyring is set, and parses
+    /// it.
+    fn decode<T: DeserializeOwned>(&self, url: &str, body: Vec<u8>) -> Result<T, Error> {
+        let Some(keyring) = &self.keyring else {
+            return Ok(serde_json::from_slice(&body)?);
+        };
+        let invalid = |reason: String| Error::SignatureInvalid {
+            url: url.to_owned(),
+            reason,
+        };
+        let document = String::from_utf8(body).map_err(|_| invalid("not UTF-8".to_owned()))?;
+        let text = keyring.verify_cleartext(&document).map_err(invalid)?;
+        debug!(url = %url, "signature verified");
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// [`fetch_document`](Self::fetch_document) through `cache`. A new body
+    /// is stored only once it has been verified and parsed.
+    async fn fetch_index_cached<T: DeserializeOwned>(
+        &self,
+        cache: &IndexCache,
+        url: &str,
+    ) -> Result<T, Error> {
+        let cached = cache.load(url).await;
+        if cache.offline {
+            let cached = cached.filter(|c| cache.is_usable_stale(c)).ok_or_else(|| {
+                Error::IndexNotCached {
+                    url: url.to_owned(),
+                }
+            })?;
+            if cache.is_fresh(&cached) {
+                debug!(url = %url, "offline, using cached index");
+            } else {
+                warn!(url = %url, age = ?cached.age(), "offline, using stale cached index");
+            }
+            return self.decode(url, cached.body);
+        }
+        let cached = match cached {
+            Some(c) if cache.is_fresh(&c) => {
+                debug!(url = %url, "using cached index");
+                return self.decode(url, c.body);
+            }
+            c => c,
+        };
+
+        let result = self
+            .fetch_index_with_retry(url, cached.as_ref().map(|c| &c.meta))
+            .await;
+        match (result, cached) {
+            (
+                Ok(IndexResponse::Body {
+                    body,
+                    etag,
+                    last_modified,
+                }),
+                _,
+            ) => {
+                let document = self.decode(url, body.clone())?;
+                if let Err(e) = cache.store(url, &body, etag, last_modified).await {
+                    warn!(error = %e, "failed to cache index");
+                }
+                Ok(document)
+            }
+            (Ok(IndexResponse::NotModified), Some(mut cached)) => {
+                debug!(url = %url, "index not modified");
+                if let Err(e) = cache.touch(&mut cached).await {
+                    warn!(error = %e, "failed to update cached index");
+                }
+                self.decode(url, cached.body)
+            }
+            (Ok(IndexResponse::NotModified), None) => {
+                unreachable!("304 without a conditional request")
+            }
+            (Err(e), Some(cached))
+                if cache.stale_if_error && e.is_transient() && cache.is_usable_stale(&cached) =>
+            {
+                warn!(
+                    error = %e,
+                    url = %url,
+                    age = ?cached.age(),
+                    "index fetch failed, using stale cached index"
+                );
+                self.decode(url, cached.body)
+            }
+            (Err(e), _) => Err(e),
+        }
+    }
+
+    async fn fetch_index_with_retry(
+        &self,
+        url: &str,
+        cached: Option<&cache::IndexMeta>,
+    ) -> Result<IndexResponse, Error> {
+        info!(url = %url, "fetching simplestreams index");
+
+        let mut attempt = 1;
+        loop {
+            match cancellable(&self.cancel, self.fetch_index_once(url, cached)).await {
+                Ok(response) => return Ok(response),
+                Err(e) => match self.retry.delay_for(attempt, &e) {
+                    Some(delay) => {
+                        warn!(error = %e, attempt, ?delay, "index fetch failed, retrying");
+                        cancellable(&self.cancel, async {
+                            tokio::time::sleep(delay).await;
+                            Ok(())
+                        })
+                        .await?;
+                        attempt += 1;
+                    }
+                    None => return Err(e),
+                },
+            }
+        }
+    }
+
+    async fn fetch_index_once(
+        &self,
+        url: &str,
+        cached: Option<&cache::IndexMeta>,
+    ) -> Result<IndexResponse, Error> {
+        let mut request = self.http.get(url);
+        if let Some(meta) = cached {
+            if let Some(etag) = &meta.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &meta.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let response = request.send().await?;
+        if cached.is_some() && response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(IndexResponse::NotModified);
+        }
+
+        let mut response = check_status(response)?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        let etag = header(reqwest::header::ETAG);
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+        if let Some(size) = response.content_length() {
+            check_index_size(url, size)?;
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            check_index_size(url, (body.len() + chunk.len()) as u64)?;
+            body.extend_from_slice(&chunk);
+        }
+        Ok(IndexResponse::Body {
+            body,
+            etag,
+            last_modified,
+        })
+    }
+
+    /// Resolves an image from a pre-fetched index.
+    ///
+    /// Only the requested `variant` is considered; a release that does not
+    /// publish it fails with [`Error::ProductNotFound`]. With `serial`, that
+    /// exact build is used; otherwise the newest build that has a rootfs is
+    /// picked, skipping incomplete newer builds.
+    pub fn resolve_from_index(
+        &self,
+        index: &SimplestreamsIndex,
+        distro: Distro,
+        version: &Version,
+        arch: Arch,
+        variant: &Variant,
+        serial: Option<&str>,
+    ) -> Result<ResolvedImage, Error> {
+        self.resolve_item_from_index(
+            index,
+            distro,
+            version,
+            arch,
+            variant,
+            serial,
+            ItemKind::Rootfs,
+        )
+    }
+
+    /// Resolves an item of `kind` from a pre-fetched index.
+    ///
+    /// Behaves like [`resolve_from_index`](Self::resolve_from_index), but
+    /// unpinned resolution picks the newest build that publishes `kind`. A
+    /// missing rootfs fails with [`Error::RootfsNotFound`], any other missing
+    /// item with [`Error::ItemNotFound`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn resolve_item_from_index(
+        &self,
+        index: &SimplestreamsIndex,
+        distro: Distro,
+        version: &Version,
+        arch: Arch,
+        variant: &Variant,
+        serial: Option<&str>,
+        kind: ItemKind,
+    ) -> Result<ResolvedImage, Error> {
+        let (product_key, product) = find_product(index, distro, version, arch, variant)?;
+        debug!(key = %product_key, "found product");
+        self.resolve_product_item(index, product_key, product, serial, kind)
+    }
+
+    /// Resolves an item of `kind` from a pre-fetched index by LXC alias.
+    ///
+    /// Aliases are matched case-insensitively against the product's
+    /// [`aliases`](Product::aliases) for `arch`. A name without a variant
+    /// (`"alpine/edge"`) selects the default variant. An alias no product
+    /// lists fails with [`Error::AliasNotFound`].
+    pub fn resolve_alias_from_index(
+        &self,
+        index: &SimplestreamsIndex,
+        alias: &str,
+        arch: Arch,
+        serial: Option<&str>,
+        kind: ItemKind,
+    ) -> Result<ResolvedImage, Error> {
+        let (product_key, product) = find_alias(index, alias, arch)?;
+        debug!(key = %product_key, alias, "found product by alias");
+        self.resolve_product_item(index, product_key.to_owned(), product, serial, kind)
+    }
+
+    /// Looks up the product an LXC alias refers to, e.g. to turn user input
+    /// such as `"ubuntu/noble/cloud"` into the distro, version and variant
+    /// that [`download_from_lxc`](crate::download_from_lxc) takes.
+    ///
+    /// Fails with [`Error::UnsupportedDistro`] when the alias names a
+    /// distribution this crate does not support, and with
+    /// [`Error::RootfsNotFound`] when the product has no complete build.
+    pub fn lookup_alias(
+        &self,
+        index: &SimplestreamsIndex,
+        alias: &str,
+        arch: Arch,
+    ) -> Result<CatalogEntry, Error> {
+        let (product_key, product) = find_alias(index, alias, arch)?;
+        let name = product_key.split(':').next().unwrap_or_default();
+        if Distro::from_lxc_name(name).is_none() {
+            return Err(Error::UnsupportedDistro(name.to_owned()));
+        }
+        catalog_entry(product_key, product).ok_or_else(|| Error::RootfsNotFound {
+            product_key: product_key.to_owned(),
+        })
+    }
+
+    /// Resolves an item of `kind` in a product found in the index.
+    fn resolve_product_item(
+        &self,
+        index: &SimplestreamsIndex,
+        product_key: String,
+        product: &Product,
+        serial: Option<&str>,
+        kind: ItemKind,
+    ) -> Result<ResolvedImage, Error> {
+        let not_found = || match kind {
+            ItemKind::Rootfs => Error::RootfsNotFound {
+                product_key: product_key.clone(),
+            },
+            kind => Error::ItemNotFound {
+                product_key: product_key.clone(),
+                kind,
+            },
+        };
+        let (serial, build, item) = match serial {
+            Some(serial) => {
+                let build = product
+                    .versions
+                    .get(serial)
+                    .ok_or_else(|| Error::SerialNotFound {
+                        product_key: product_key.clone(),
+                        serial: serial.to_owned(),
+                    })?;
+                let item = build.item(kind).ok_or_else(not_found)?;
+                (serial, build, item)
+            }
+            None => {
+                let (serial, item) = product.builds_with(kind).next().ok_or_else(not_found)?;
+                (serial, &product.versions[serial], item)
+            }
+        };
+        debug!(key = %product_key, serial, %kind, "selected build");
+
+        // The file name is joined to the caller's destination directory, so
+        // it must name a file in it on every platform.
+        let filename = match item.path.rsplit('/').next() {
+            Some(name) if !matches!(name, "" | "." | "..") && !name.contains('\\') => {
+                name.to_owned()
+            }
+            _ => {
+                return Err(Error::UnsafePath {
+                    path: item.path.clone(),
+                });
+            }
+        };
+        let combined_sha256 = build
+            .item(ItemKind::LxdMetadata)
+            .and_then(|metadata| metadata.combined_sha256_for(kind))
+            .map(str::to_owned);
+
+        Ok(ResolvedImage {
+            product_key,
+            kind,
+            url: self.item_url(index, &item.path),
+            path: item.path.clone(),
+            sha256: item.sha256.clone(),
+            size: item.size,
+            filename,
+            serial: serial.to_owned(),
+            combined_sha256,
+            release_title: product.title(),
+            supported_eol: product.supported_eol.clone(),
+            end_of_life: product.is_end_of_life(),
+        })
+    }
+
+    /// Lists the build serials of a product that have a rootfs, newest first.
+    pub fn list_serials(
+        &self,
+        index: &SimplestreamsIndex,
+        distro: Distro,
+        version: &Version,
+        arch: Arch,
+        variant: &Variant,
+    ) -> Result<Vec<String>, Error> {
+        let (_, product) = find_product(index, distro, version, arch, variant)?;
+        Ok(product
+            .complete_builds()
+            .map(|(serial, _)| serial.to_owned())
+            .collect())
+    }
+
+    /// Finds the deltas that update `image`'s item from build `from_serial`
+    /// to `image.serial`, oldest first.
+    ///
+    /// Returns `None` when the index has no chain of `.vcdiff` items between
+    /// the two builds, and an empty chain when `from_serial` already is the
+    /// resolved build.
+    pub fn resolve_delta_chain(
+        &self,
+        index: &SimplestreamsIndex,
+        image: &ResolvedImage,
+        from_serial: &str,
+    ) -> Option<Vec<ResolvedDelta>> {
+        let product = index.products.get(&image.product_key)?;
+        let chain = product.delta_chain(image.kind, from_serial, &image.serial)?;
+        chain
+            .into_iter()
+            .map(|(base_serial, serial, delta)| {
+                let target = product.versions.get(serial)?.item(image.kind)?;
+                Some(ResolvedDelta {
+                    base_serial: base_serial.to_owned(),
+                    serial: serial.to_owned(),
+                    url: self.item_url(index, &delta.path),
+                    sha256: delta.sha256.clone(),
+                    size: delta.size,
+                    target_sha256: target.sha256.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Lists the images on the mirror that match `filter`.
+    ///
+    /// See [`catalog_from_index`](Self::catalog_from_index).
+    pub async fn catalog(&self, filter: &CatalogFilter) -> Result<Vec<CatalogEntry>, Error> {
+        let index = self.fetch_index().await?;
+        Ok(self.catalog_from_index(&index, filter))
+    }
+
+    /// Lists the images in a pre-fetched index that match `filter`, sorted
+    /// by distro, version, arch and variant.
+    ///
+    /// Each product is reported with its newest complete build. Products
+    /// with no complete build, and those whose distro or architecture this
+    /// crate does not support, are left out.
+    pub fn catalog_from_index(
+        &self,
+        index: &SimplestreamsIndex,
+        filter: &CatalogFilter,
+    ) -> Vec<CatalogEntry> {
+        let mut entries: Vec<CatalogEntry> = index
+            .products
+            .iter()
+            .filter_map(|(key, product)| catalog_entry(key, product))
+            .filter(|entry| filter.matches(entry))
+            .collect();
+        entries.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+        entries
+    }
+}
+
+/// Builds the catalog entry of a product, keyed
+/// `{lxc_name}:{release}:{arch}:{variant}`.
+pub(crate) fn catalog_entry(key: &str, product: &Product) -> Option<CatalogEntry> {
+    let mut parts = key.splitn(4, ':');
+    let (name, release, arch, variant) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let distro = Distro::from_lxc_name(name)?;
+    let arch = Arch::from_lxc_name(arch)?;
+    let (serial, rootfs) = product.complete_builds().next()?;
+    Some(CatalogEntry {
+        distro,
+        version: distro.version_from_lxc_release(release),
+        arch,
+        variant: Variant::new(variant),
+        serial: serial.to_owned(),
+        size: rootfs.size,
+        release_title: product.release_title.clone(),
+        aliases: product.aliases(key),
+        end_of_life: product.is_end_of_life(),
+    })
+}
+
+/// Looks up the product for a distro/version/arch/variant in the index.
+fn find_product<'a>(
+    index: &'a SimplestreamsIndex,
+    distro: Distro,
+    version: &Version,
+    arch: Arch,
+    variant: &Variant,
+) -> Result<(String, &'a Product), Error> {
+    let key = format!(
+        "{}:{}:{}:{variant}",
+        distro.lxc_name(),
+        distro.lxc_release(version),
+        arch.lxc_name()
+    );
+    match index.products.get(&key) {
+        Some(product) => Ok((key, product)),
+        None => Err(Error::ProductNotFound {
+            distro: distro.as_str().to_owned(),
+            version: version.as_str().to_owned(),
+            arch: arch.lxc_name().to_owned(),
+            variant: variant.as_str().to_owned(),
+        }),
+    }
+}
+
+/// Looks up the product for `arch` that lists `alias`.
+fn find_alias<'a>(
+    index: &'a SimplestreamsIndex,
+    alias: &str,
+    arch: Arch,
+) -> Result<(&'a str, &'a Product), Error> {
+    let alias = alias.trim().trim_matches('/').to_lowercase();
+    index
+        .products
+        .iter()
+        .filter(|(_, product)| product.arch == arch.lxc_name())
+        .find(|(key, product)| product.aliases(key).contains(&alias))
+        .map(|(key, product)| (key.as_str(), product))
+        .ok_or_else(|| Error::AliasNotFound {
+            alias,
+            arch: arch.lxc_name().to_owned(),
+        })
+}
+
+/// Returns the signed (`.sjson`) variant of a `.json` document URL.
+fn signed_path(url: &str) -> String {
+    match url.strip_suffix(".json") {
+        Some(stem) => format!("{stem}.sjson"),
+        None => url.to_owned(),
+    }
+}
+
+/// Returns whether a document does not exist: HTTP 404, or a missing file
+/// on a local mirror.
+fn is_not_found(e: &Error) -> bool {
+    match e {
+        Error::HttpStatus { status, .. } => *status == 404,
+        Error::Io(e) => e.kind() == std::io::ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+/// Rejects a Simplestreams document larger than [`MAX_INDEX_SIZE`].
+fn check_index_size(url: &str, size: u64) -> Result<(), Error> {
+    if size > MAX_INDEX_SIZE {
+        return Err(Error::TooLarge {
+            url: url.to_owned(),
+            limit: MAX_INDEX_SIZE,
+        });
+    }
+    Ok(())
+}
+
+/// Outcome of a single index request.
+enum IndexResponse {
+    /// The full index, with its validators.
+    Body {
+        body: Vec<u8>,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+    /// `304 Not Modified`: the cached copy is current.
+    NotModified,
+}
+
+// ---------------------------------------------------------------------------
+// Simplestreams JSON types
+// ---------------------------------------------------------------------------
+
+/// Simplestreams `index.json`: the product streams a mirror serves.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamsIndex {
+    /// Document format (`"index:1.0"`).
+    #[serde(default)]
+    pub format: String,
+    /// Map from content id (e.g. `"images"`) to stream.
+    pub index: HashMap<String, StreamEntry>,
+}
+
+/// A product stream listed in `index.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamEntry {
+    /// Path of the stream document relative to the mirror root
+    /// (e.g. `"streams/v1/images.json"`).
+    pub path: String,
+    /// Data type (e.g. `"image-downloads"`).
+    #[serde(default)]
+    pub datatype: String,
+    /// Stream format (e.g. `"products:1.0"`).
+    #[serde(default)]
+    pub format: String,
+    /// Product keys in the stream.
+    #[serde(default)]
+    pub products: Vec<String>,
+}
+
+/// Top-level Simplestreams `images.json` structure.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimplestreamsIndex {
+    /// Content id of the stream (e.g. `"images"`).
+    #[serde(default)]
+    pub content_id: String,
+    /// Data type (e.g. `"image-downloads"`).
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub datatype: String,
+    /// Document format (`"products:1.0"`).
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub format: String,
+    /// Map from product key (e.g. `"alpine:3.21:amd64:default"`) to product.
+    pub products: HashMap<String, Product>,
+    /// Mirror the index was fetched from, set by [`LxcClient::fetch_index`].
+    #[serde(skip)]
+    pub source: Option<Mirror>,
+}
+
+/// A single product (distro + release + arch + variant).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Product {
+    /// Architecture string (e.g. `"amd64"`).
+    pub arch: String,
+    /// OS name (e.g. `"Alpine"`).
+    pub os: String,
+    /// Release identifier (e.g. `"3.21"`, `"noble"`).
+    pub release: String,
+    /// Human-readable release title (e.g. `"24.04 LTS"`).
+    #[serde(default)]
+    pub release_title: String,
+    /// Release codename (e.g. `"Noble Numbat"`), on Ubuntu streams.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_codename: Option<String>,
+    /// Image variant (e.g. `"default"`, `"cloud"`).
+    #[serde(default)]
+    pub variant: String,
+    /// LXC aliases, listed upstream as one comma-separated string (e.g.
+    /// `"alpine/3.21/default,alpine/3.21"`).
+    #[serde(
+        default,
+        rename = "aliases",
+        deserialize_with = "comma_list",
+        serialize_with = "join_comma_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub listed_aliases: Vec<String>,
+    /// Features the image needs from the host or instance (e.g.
+    /// `"secureboot": "false"`). Values are kept as strings.
+    #[serde(
+        default,
+        deserialize_with = "string_map",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub requirements: HashMap<String, String>,
+    /// Whether the release is still supported, if the index says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supported: Option<bool>,
+    /// End-of-life date of the release (`YYYY-MM-DD`), if listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supported_eol: Option<String>,
+    /// Map from build timestamp (e.g. `"20260218_07:42"`) to version data.
+    pub versions: HashMap<String, ProductVersion>,
+}
+
+impl Product {
+    /// Returns the LXC aliases of the product stored under `key`, lowercase.
+    ///
+    /// Products without listed aliases get the ones LXC would publish:
+    /// `{name}/{release}/{variant}`, plus `{name}/{release}` for the
+    /// default variant.
+    pub fn aliases(&self, key: &str) -> Vec<String> {
+        if !self.listed_aliases.is_empty() {
+            return self
+                .listed_aliases
+                .iter()
+                .map(|alias| alias.to_lowercase())
+                .collect();
+        }
+        let parts: Vec<&str> = key.split(':').collect();
+        let [name, release, _, variant] = parts[..] else {
+            return Vec::new();
+        };
+        let mut aliases = vec![format!("{name}/{release}/{variant}")];
+        if variant == Variant::default().as_str() {
+            aliases.push(format!("{name}/{release}"));
+        }
+        aliases
+    }
+
+    /// Returns `"{os} {release_title}"` (e.g. `"Ubuntu 24.04 LTS"`),
+    /// falling back to the release identifier when no title is listed.
+    pub fn title(&self) -> String {
+        let release = if self.release_title.is_empty() {
+            &self.release
+        } else {
+            &self.release_title
+        };
+        format!("{} {release}", self.os)
+    }
+
+    /// Returns `true` if the release is past its end of life: the index
+    /// marks it `supported: false`, or its `supported_eol` date has passed.
+    pub fn is_end_of_life(&self) -> bool {
+        self.is_end_of_life_at(SystemTime::now())
+    }
+
+    /// Like [`is_end_of_life`](Self::is_end_of_life), at time `now`.
+    pub fn is_end_of_life_at(&self, now: SystemTime) -> bool {
+        if self.supported == Some(false) {
+            return true;
+        }
+        let today = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| (d.as_secs() / 86_400) as i64);
+        self.supported_eol
+            .as_deref()
+            .and_then(days_from_date)
+            .is_some_and(|eol| eol <= today)
+    }
+
+    /// Returns the builds that have a rootfs item, newest serial first.
+    ///
+    /// Serials are timestamps (e.g. `"20260218_07:42"`), so they sort
+    /// chronologically. A build still being published may lack its rootfs;
+    /// such builds are skipped.
+    pub fn complete_builds(&self) -> impl Iterator<Item = (&str, &Item)> {
+        self.builds_with(ItemKind::Rootfs)
+    }
+
+    /// Finds the shortest chain of `.vcdiff` deltas that turns the `kind`
+    /// item of build `from` into the one of build `to`, as
+    /// `(base serial, serial, delta item)` steps, oldest first.
+    ///
+    /// Every intermediate build must publish the full item, so each step can
+    /// be verified against its SHA256.
+    pub fn delta_chain<'a>(
+        &'a self,
+        kind: ItemKind,
+        from: &'a str,
+        to: &'a str,
+    ) -> Option<Vec<(&'a str, &'a str, &'a Item)>> {
+        // Walk back from `to` breadth-first; `next[base]` is the first step
+        // of the shortest known chain from `base`.
+        let mut next: HashMap<&str, (&str, &Item)> = HashMap::new();
+        let mut queue = VecDeque::from([to]);
+        while let Some(serial) = queue.pop_front() {
+            if serial == from {
+                let mut chain = Vec::new();
+                let mut current = from;
+                while current != to {
+                    let (serial, delta) = next[current];
+                    chain.push((current, serial, delta));
+                    current = serial;
+                }
+                return Some(chain);
+            }
+            let Some(build) = self.versions.get(serial) else {
+                continue;
+            };
+            for (base, delta) in build.deltas(kind) {
+                let usable =
+                    base == from || self.versions.get(base).and_then(|b| b.item(kind)).is_some();
+                if base < serial && usable && !next.contains_key(base) {
+                    next.insert(base, (serial, delta));
+                    queue.push_back(base);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the builds that publish an item of `kind`, newest serial
+    /// first, with that item.
+    pub fn builds_with(&self, kind: ItemKind) -> impl Iterator<Item = (&str, &Item)> {
+        let mut serials: Vec<&String> = self.versions.keys().collect();
+        serials.sort_unstable_by(|a, b| b.cmp(a));
+        serials.into_iter().filter_map(move |serial| {
+            self.versions[serial]
+                .item(kind)
+                .map(|item| (serial.as_str(), item))
+        })
+    }
+}
+
+/// A specific build of a product.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProductVersion {
+    /// Published name of the build (e.g.
+    /// `"ubuntu-noble-24.04-amd64-server-20240423"`), on Ubuntu streams.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pubname: Option<String>,
+    /// Map from item key (e.g. `"root.tar.xz"`) to downloadable file.
+    pub items: HashMap<String, Item>,
+}
+
+impl ProductVersion {
+    /// Returns the rootfs tarball of this build, if published.
+    pub fn rootfs(&self) -> Option<&Item> {
+        self.item(ItemKind::Rootfs)
+    }
+
+    /// Returns the `.vcdiff` deltas that produce this build's `kind` item,
+    /// with the serial of the build each applies to.
+    pub fn deltas(&self, kind: ItemKind) -> impl Iterator<Item = (&str, &Item)> {
+        let ftype = format!("{}.vcdiff", kind.ftype());
+        self.items.values().filter_map(move |item| {
+            let base = item.delta_base.as_deref()?;
+            (item.ftype == ftype).then_some((base, item))
+        })
+    }
+
+    /// Returns the item of `kind` in this build, if published.
+    pub fn item(&self, kind: ItemKind) -> Option<&Item> {
+        let by_ftype = self.items.values().find(|item| item.ftype == kind.ftype());
+        match kind {
+            // Some mirrors use a different ftype for the rootfs tarball.
+            ItemKind::Rootfs => by_ftype.or_else(|| {
+                self.items
+                    .values()
+                    .find(|item| item.path.ends_with("rootfs.tar.xz"))
+            }),
+            _ => by_ftype,
+        }
+    }
+}
+
+/// A downloadable file within a product version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Item {
+    /// File type identifier (e.g. `"root.tar.xz"`, `"lxd.tar.xz"`).
+    pub ftype: String,
+    /// SHA-256 hex digest.
+    pub sha256: String,
+    /// File size in bytes.
+    pub size: u64,
+    /// Relative path on the mirror (e.g. `"images/alpine/3.21/amd64/..."`).
+    pub path: String,
+    /// On `lxd.tar.xz`: SHA256 of this file followed by `rootfs.tar.xz`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub combined_sha256: Option<String>,
+    /// On `lxd.tar.xz`: same as `combined_sha256`, under its newer name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub combined_rootxz_sha256: Option<String>,
+    /// On `lxd.tar.xz`: SHA256 of this file followed by the squashfs rootfs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub combined_squashfs_sha256: Option<String>,
+    /// On `lxd.tar.xz`: SHA256 of this file followed by the VM disk image.
+    #[serde(
+        default,
+        rename = "combined_disk-kvm-img_sha256",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub combined_disk_kvm_img_sha256: Option<String>,
+    /// On `.vcdiff` deltas: serial of the build the delta applies to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta_base: Option<String>,
+}
+
+impl Item {
+    /// Returns the combined SHA256 of this metadata item followed by an
+    /// item of `kind`, if listed.
+    pub fn combined_sha256_for(&self, kind: ItemKind) -> Option<&str> {
+        match kind {
+            ItemKind::Rootfs => self
+                .combined_rootxz_sha256
+                .as_deref()
+                .or(self.combined_sha256.as_deref()),
+            ItemKind::Squashfs => self.combined_squashfs_sha256.as_deref(),
+            ItemKind::DiskKvm => self.combined_disk_kvm_img_sha256.as_deref(),
+            ItemKind::LxdMetadata => None,
+        }
+    }
+}
+
+/// Parses a comma-separated list such as Simplestreams `aliases`.
+fn comma_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let list = String::deserialize(deserializer)?;
+    Ok(list
+        .split(',')
+        .map(str::trim)
+        .filter(|alias| !alias.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Writes a list back as one comma-separated string.
+fn join_comma_list<S: Serializer>(list: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&list.join(","))
+}
+
+/// Parses a map of scalars, keeping each value as a string.
+fn string_map<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, String>, D::Error> {
+    let map = HashMap::<String, serde_json::Value>::deserialize(deserializer)?;
+    Ok(map
+        .into_iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(value) => (key, value),
+            value => (key, value.to_string()),
+        })
+        .collect())
+}
+
+/// Converts a `YYYY-MM-DD` date to days since the Unix epoch.
+pub(crate) fn days_from_date(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days from civil date, proleptic Gregorian calendar.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn mock_index() -> SimplestreamsIndex {
+        serde_json::from_str(MOCK_INDEX).unwrap()
+    }
+
+    const MOCK_INDEX: &str = r#"{
+            "products": {
+                "alpine:3.21:amd64:default": {
+                    "arch": "amd64",
+                    "os": "Alpine",
+                    "release": "3.21",
+                    "release_title": "3.21",
+                    "variant": "default",
+                    "versions": {
+                        "20260217_13:00": {
+                            "items": {
+                                "root.tar.xz": {
+                                    "ftype": "root.tar.xz",
+                                    "sha256": "aabbccdd",
+                                    "size": 3145728,
+                                    "path": "images/alpine/3.21/amd64/default/20260217_13:00/rootfs.tar.xz"
+                                },
+                                "lxd.tar.xz": {
+                                    "ftype": "lxd.tar.xz",
+                                    "sha256": "11223344",
+                                    "size": 440,
+                                    "path": "images/alpine/3.21/amd64/default/20260217_13:00/lxd.tar.xz",
+                                    "combined_sha256": "c0b1aedd",
+                                    "combined_rootxz_sha256": "c0b1aedd",
+                                    "combined_squashfs_sha256": "c0b15fa5"
+                                },
+                                "root.squashfs": {
+                                    "ftype": "squashfs",
+                                    "sha256": "5fa5fa5f",
+                                    "size": 2097152,
+                                    "path": "images/alpine/3.21/amd64/default/20260217_13:00/rootfs.squashfs"
+                                }
+                            }
+                        },
+                        "20260218_13:00": {
+                            "items": {
+                                "root.tar.xz": {
+                                    "ftype": "root.tar.xz",
+                                    "sha256": "eeff0011",
+                                    "size": 3200000,
+                                    "path": "images/alpine/3.21/amd64/default/20260218_13:00/rootfs.tar.xz"
+                                }
+                            }
+                        },
+                        "20260219_13:00": {
+                            "items": {
+                                "lxd.tar.xz": {
+                                    "ftype": "lxd.tar.xz",
+                                    "sha256": "55667788",
+                                    "size": 440,
+                                    "path": "images/alpine/3.21/amd64/default/20260219_13:00/lxd.tar.xz"
+                                }
+                            }
+                        }
+                    }
+                },
+                "alpine:3.21:amd64:cloud": {
+                    "arch": "amd64",
+                    "os": "Alpine",
+                    "release": "3.21",
+                    "release_title": "3.21",
+                    "variant": "cloud",
+                    "versions": {
+                        "20260218_13:00": {
+                            "items": {
+                                "root.tar.xz": {
+                                    "ftype": "root.tar.xz",
+                                    "sha256": "c10dc10d",
+                                    "size": 3400000,
+                                    "path": "images/alpine/3.21/amd64/cloud/20260218_13:00/rootfs.tar.xz"
+                                }
+                            }
+                        }
+                    }
+                },
+                "ubuntu:noble:arm64:default": {
+                    "arch": "arm64",
+                    "os": "Ubuntu",
+                    "release": "noble",
+                    "release_title": "24.04 LTS",
+                    "release_codename": "Noble Numbat",
+                    "variant": "default",
+                    "aliases": "ubuntu/noble/default,ubuntu/noble,Ubuntu/24.04",
+                    "requirements": { "secureboot": "false", "nesting": true },
+                    "supported": true,
+                    "supported_eol": "2029-05-31",
+                    "versions": {
+                        "20260218_07:42": {
+                            "pubname": "ubuntu-noble-24.04-arm64-default-20260218_07:42",
+                            "items": {
+                                "root.tar.xz": {
+                                    "ftype": "root.tar.xz",
+                                    "sha256": "ubuntuhash",
+                                    "size": 314572800,
+                                    "path": "images/ubuntu/noble/arm64/default/20260218_07:42/rootfs.tar.xz"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }"#;
+
+    const MOCK_STREAMS: &str = r#"{
+            "format": "index:1.0",
+            "index": {
+                "images": {
+                    "datatype": "image-downloads",
+                    "format": "products:1.0",
+                    "path": "streams/v1/images.json",
+                    "products": ["alpine:3.21:amd64:default", "ubuntu:noble:arm64:default"]
+                }
+            }
+        }"#;
+
+    const TEST_KEY: &str = include_str!("testdata/test-key.asc");
+    const SIGNED_STREAMS: &str = include_str!("testdata/index.sjson");
+    const SIGNED_INDEX: &str = include_str!("testdata/images.sjson");
+
+    /// Caches the unsigned `index.json` and `images.json` of `mirror`.
+    async fn store_documents(cache: &IndexCache, mirror: &Mirror) {
+        for (url, body) in [
+            (mirror.index_url(), MOCK_STREAMS),
+            (mirror.streams_url(), MOCK_INDEX),
+        ] {
+            cache
+                .store(&url, body.as_bytes(), None, None)
+                .await
+                .unwrap();
+        }
+    }
+
+    /// Caches the signed `index.sjson` and `images.sjson` of `mirror`.
+    async fn store_signed_documents(cache: &IndexCache, mirror: &Mirror, index: &str) {
+        let base = mirror.base_url();
+        for (url, body) in [
+            (format!("{base}/streams/v1/index.sjson"), SIGNED_STREAMS),
+            (format!("{base}/streams/v1/images.sjson"), index),
+        ] {
+            cache
+                .store(&url, body.as_bytes(), None, None)
+                .await
+                .unwrap();
+        }
+    }
+
+    /// A mirror nothing listens on; any request fails to connect.
+    fn unreachable_mirror() -> Mirror {
+        Mirror::Custom("http://127.0.0.1:9".to_owned())
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn resolve_alpine() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let result = client
+            .resolve_from_index(
+                &index,
+                Distro::Alpine,
+                &Version::new("3.21"),
+                Arch::X86_64,
+                &Variant::default(),
+                None,
+            )
+            .unwrap();
+
+        // Should pick the latest complete build (20260218); the incomplete
+        // 20260219 build has no rootfs yet.
+        assert_eq!(result.sha256, "eeff0011");
+        assert_eq!(result.serial, "20260218_13:00");
+        assert_eq!(result.size, 3200000);
+        assert_eq!(result.filename, "rootfs.tar.xz");
+        assert!(result.url.contains("20260218_13:00"));
+    }
+
+    #[test]
+    fn rejects_unusable_file_names() {
+        let client = LxcClient::new(Mirror::Official);
+        for path in [
+            "images/alpine/",
+            "images/alpine/.",
+            "images/alpine/..",
+            "..",
+            "images/alpine\\..\\rootfs",
+        ] {
+            let mut index = mock_index();
+            index
+                .products
+                .get_mut("alpine:3.21:amd64:default")
+                .unwrap()
+                .versions
+                .get_mut("20260218_13:00")
+                .unwrap()
+                .items
+                .get_mut("root.tar.xz")
+                .unwrap()
+                .path = path.to_owned();
+            let err = client
+                .resolve_from_index(
+                    &index,
+                    Distro::Alpine,
+                    &Version::new("3.21"),
+                    Arch::X86_64,
+                    &Variant::default(),
+                    Some("20260218_13:00"),
+                )
+                .unwrap_err();
+            assert!(
+                matches!(&err, Error::UnsafePath { path: p } if p == path),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_ubuntu_codename() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        // "24.04" should map to "noble" for the product key lookup
+        let result = client
+            .resolve_from_index(
+                &index,
+                Distro::Ubuntu,
+                &Version::new("24.04"),
+                Arch::Aarch64,
+                &Variant::default(),
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(result.sha256, "ubuntuhash");
+        assert!(result.url.contains("ubuntu/noble/arm64"));
+    }
+
+    #[test]
+    fn resolve_not_found() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let result = client.resolve_from_index(
+            &index,
+            Distro::Fedora,
+            &Version::new("41"),
+            Arch::X86_64,
+            &Variant::default(),
+            None,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn resolve_explicit_variant() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let result = client
+            .resolve_from_index(
+                &index,
+                Distro::Alpine,
+                &Version::new("3.21"),
+                Arch::X86_64,
+                &Variant::cloud(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(result.sha256, "c10dc10d");
+        assert!(result.url.contains("/cloud/"));
+
+        // A variant the release does not publish is not substituted.
+        let err = client
+            .resolve_from_index(
+                &index,
+                Distro::Ubuntu,
+                &Version::new("24.04"),
+                Arch::Aarch64,
+                &Variant::cloud(),
+                None,
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::ProductNotFound { variant, .. } if variant == "cloud"));
+    }
+
+    #[test]
+    fn resolve_pinned_serial() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let resolve = |serial| {
+            client.resolve_from_index(
+                &index,
+                Distro::Alpine,
+                &Version::new("3.21"),
+                Arch::X86_64,
+                &Variant::default(),
+                Some(serial),
+            )
+        };
+
+        let result = resolve("20260217_13:00").unwrap();
+        assert_eq!(result.sha256, "aabbccdd");
+        assert_eq!(result.serial, "20260217_13:00");
+        assert!(result.url.contains("20260217_13:00"));
+
+        assert!(matches!(
+            resolve("20260101_00:00"),
+            Err(Error::SerialNotFound { .. })
+        ));
+        // A pinned build without a rootfs is an error, not skipped.
+        assert!(matches!(
+            resolve("20260219_13:00"),
+            Err(Error::RootfsNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn list_serials_newest_first() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let serials = client
+            .list_serials(
+                &index,
+                Distro::Alpine,
+                &Version::new("3.21"),
+                Arch::X86_64,
+                &Variant::default(),
+            )
+            .unwrap();
+        assert_eq!(serials, ["20260218_13:00", "20260217_13:00"]);
+    }
+
+    #[test]
+    fn resolve_item_kinds() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let resolve = |serial, kind| {
+            client.resolve_item_from_index(
+                &index,
+                Distro::Alpine,
+                &Version::new("3.21"),
+                Arch::X86_64,
+                &Variant::default(),
+                serial,
+                kind,
+            )
+        };
+
+        let rootfs = resolve(Some("20260217_13:00"), ItemKind::Rootfs).unwrap();
+        assert_eq!(rootfs.kind, ItemKind::Rootfs);
+        assert_eq!(rootfs.combined_sha256.as_deref(), Some("c0b1aedd"));
+
+        // The newest build publishing squashfs, not the newest rootfs build.
+        let squashfs = resolve(None, ItemKind::Squashfs).unwrap();
+        assert_eq!(squashfs.serial, "20260217_13:00");
+        assert_eq!(squashfs.filename, "rootfs.squashfs");
+        assert_eq!(squashfs.sha256, "5fa5fa5f");
+        assert_eq!(squashfs.combined_sha256.as_deref(), Some("c0b15fa5"));
+
+        let metadata = resolve(None, ItemKind::LxdMetadata).unwrap();
+        assert_eq!(metadata.serial, "20260219_13:00");
+        assert_eq!(metadata.filename, "lxd.tar.xz");
+        assert_eq!(metadata.combined_sha256, None);
+
+        // Builds without a combined hash report none.
+        let rootfs = resolve(None, ItemKind::Rootfs).unwrap();
+        assert_eq!(rootfs.combined_sha256, None);
+    }
+
+    #[test]
+    fn resolve_item_not_published() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let result = client.resolve_item_from_index(
+            &index,
+            Distro::Alpine,
+            &Version::new("3.21"),
+            Arch::X86_64,
+            &Variant::default(),
+            None,
+            ItemKind::DiskKvm,
+        );
+        assert!(matches!(
+            result,
+            Err(Error::ItemNotFound {
+                kind: ItemKind::DiskKvm,
+                ..
+            })
+        ));
+    }
+
+    /// Squashfs builds 01–04; 02 and 03 each have a delta from the previous
+    /// build, 04 only from 02.
+    fn delta_index() -> SimplestreamsIndex {
+        let item = |serial: &str, base: Option<&str>| {
+            let (key, ftype, file) = match base {
+                Some(base) => (
+                    format!("delta-{base}"),
+                    "squashfs.vcdiff",
+                    format!("delta-{base}.vcdiff"),
+                ),
+                None => (
+                    "root.squashfs".to_owned(),
+                    "squashfs",
+                    "rootfs.squashfs".to_owned(),
+                ),
+            };
+            let mut item = serde_json::json!({
+                "ftype": ftype,
+                "sha256": format!("{serial}-{file}"),
+                "size": 10,
+                "path": format!("images/alpine/3.21/amd64/default/{serial}/{file}"),
+            });
+            if let Some(base) = base {
+                item["delta_base"] = base.into();
+            }
+            (key, item)
+        };
+        let build = |serial: &str, bases: &[&str]| {
+            let items: serde_json::Map<_, _> = std::iter::once(item(serial, None))
+                .chain(bases.iter().map(|base| item(serial, Some(base))))
+                .collect();
+            serde_json::json!({ "items": items })
+        };
+        serde_json::from_value(serde_json::json!({
+            "products": {
+                "alpine:3.21:amd64:default": {
+                    "arch": "amd64",
+                    "os": "Alpine",
+                    "release": "3.21",
+                    "versions": {
+                        "01": build("01", &[]),
+                        "02": build("02", &["01"]),
+                        "03": build("03", &["02"]),
+                        "04": build("04", &["02"]),
+                    }
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn delta_chain_follows_builds() {
+        let index = delta_index();
+        let product = &index.products["alpine:3.21:amd64:default"];
+        let steps = |from, to| {
+            product
+                .delta_chain(ItemKind::Squashfs, from, to)
+                .map(|chain| {
+                    chain
+                        .iter()
+                        .map(|(base, serial, _)| (*base, *serial))
+                        .collect::<Vec<_>>()
+                })
+        };
+
+        assert_eq!(steps("01", "03"), Some(vec![("01", "02"), ("02", "03")]));
+        assert_eq!(steps("01", "04"), Some(vec![("01", "02"), ("02", "04")]));
+        assert_eq!(steps("04", "04"), Some(vec![]));
+        assert_eq!(steps("03", "04"), None);
+        assert_eq!(steps("00", "04"), None);
+        assert!(product.delta_chain(ItemKind::Rootfs, "01", "02").is_none());
+    }
+
+    #[test]
+    fn resolve_delta_chain_urls() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = delta_index();
+        let image = client
+            .resolve_item_from_index(
+                &index,
+                Distro::Alpine,
+                &Version::new("3.21"),
+                Arch::X86_64,
+                &Variant::default(),
+                None,
+                ItemKind::Squashfs,
+            )
+            .unwrap();
+        assert_eq!(image.serial, "04");
+
+        let chain = client.resolve_delta_chain(&index, &image, "01").unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[0].target_sha256, "02-rootfs.squashfs");
+        assert_eq!(chain[1].sha256, "04-delta-02.vcdiff");
+        assert_eq!(chain[1].target_sha256, image.sha256);
+        assert_eq!(
+            chain[1].url,
+            "https://images.linuxcontainers.org/images/alpine/3.21/amd64/default/04/delta-02.vcdiff"
+        );
+    }
+
+    #[test]
+    fn catalog_lists_latest_builds() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let catalog = client.catalog_from_index(&index, &CatalogFilter::default());
+        assert_eq!(catalog.len(), 3);
+
+        let alpine = &catalog[0];
+        assert_eq!(alpine.distro, Distro::Alpine);
+        assert_eq!(alpine.variant, Variant::cloud());
+        assert_eq!(catalog[1].variant, Variant::default());
+        assert_eq!(catalog[1].serial, "20260218_13:00");
+        assert_eq!(catalog[1].size, 3200000);
+
+        let ubuntu = &catalog[2];
+        assert_eq!(ubuntu.distro, Distro::Ubuntu);
+        assert_eq!(ubuntu.version.as_str(), "24.04");
+        assert_eq!(ubuntu.arch, Arch::Aarch64);
+        assert_eq!(ubuntu.release_title, "24.04 LTS");
+    }
+
+    #[test]
+    fn catalog_filter() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let filter = CatalogFilter {
+            distro: Some(Distro::Alpine),
+            variant: Some(Variant::default()),
+            ..Default::default()
+        };
+        let catalog = client.catalog_from_index(&index, &filter);
+        assert_eq!(catalog.len(), 1);
+        assert_eq!(catalog[0].serial, "20260218_13:00");
+
+        let filter = CatalogFilter {
+            version: Some(Version::new("24.04")),
+            arch: Some(Arch::Aarch64),
+            ..Default::default()
+        };
+        let catalog = client.catalog_from_index(&index, &filter);
+        assert_eq!(catalog.len(), 1);
+        assert_eq!(catalog[0].distro, Distro::Ubuntu);
+    }
+
+    #[test]
+    fn catalog_skips_unsupported_products() {
+        let index: SimplestreamsIndex = serde_json::from_str(
+            r#"{
+                "products": {
+                    "alpine:3.21:armhf:default": {
+                        "arch": "armhf", "os": "Alpine", "release": "3.21",
+                        "versions": { "20260218_13:00": { "items": { "root.tar.xz": {
+                            "ftype": "root.tar.xz", "sha256": "aa", "size": 1,
+                            "path": "images/alpine/3.21/armhf/default/20260218_13:00/rootfs.tar.xz"
+                        } } } }
+                    },
+                    "amazonlinux:2023:amd64:default": {
+                        "arch": "amd64", "os": "Amazonlinux", "release": "2023",
+                        "versions": { "20260218_13:00": { "items": { "root.tar.xz": {
+                            "ftype": "root.tar.xz", "sha256": "bb", "size": 1,
+                            "path": "images/amazonlinux/2023/amd64/default/20260218_13:00/rootfs.tar.xz"
+                        } } } }
+                    },
+                    "debian:trixie:amd64:default": {
+                        "arch": "amd64", "os": "Debian", "release": "trixie",
+                        "versions": { "20260218_13:00": { "items": {} } }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let client = LxcClient::new(Mirror::Official);
+        assert!(
+            client
+                .catalog_from_index(&index, &CatalogFilter::default())
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn catalog_entry_serializes() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let filter = CatalogFilter {
+            distro: Some(Distro::Ubuntu),
+            ..Default::default()
+        };
+        let catalog = client.catalog_from_index(&index, &filter);
+        let json = serde_json::to_value(&catalog[0]).unwrap();
+        assert_eq!(json["distro"], "ubuntu");
+        assert_eq!(json["version"], "24.04");
+        assert_eq!(json["arch"], "aarch64");
+        assert_eq!(json["variant"], "default");
+        assert_eq!(json["serial"], "20260218_07:42");
+    }
+
+    #[test]
+    fn parses_product_metadata() {
+        let index = mock_index();
+        let ubuntu = &index.products["ubuntu:noble:arm64:default"];
+        assert_eq!(
+            ubuntu.aliases("ubuntu:noble:arm64:default"),
+            ["ubuntu/noble/default", "ubuntu/noble", "ubuntu/24.04"]
+        );
+        assert_eq!(ubuntu.requirements["secureboot"], "false");
+        assert_eq!(ubuntu.requirements["nesting"], "true");
+        assert_eq!(ubuntu.supported, Some(true));
+        assert_eq!(ubuntu.supported_eol.as_deref(), Some("2029-05-31"));
+        assert_eq!(ubuntu.release_codename.as_deref(), Some("Noble Numbat"));
+        assert_eq!(ubuntu.title(), "Ubuntu 24.04 LTS");
+        assert_eq!(
+            ubuntu.versions["20260218_07:42"].pubname.as_deref(),
+            Some("ubuntu-noble-24.04-arm64-default-20260218_07:42")
+        );
+
+        // Without listed aliases, the LXC naming scheme is assumed.
+        let alpine = &index.products["alpine:3.21:amd64:cloud"];
+        assert!(alpine.listed_aliases.is_empty());
+        assert_eq!(
+            alpine.aliases("alpine:3.21:amd64:cloud"),
+            ["alpine/3.21/cloud"]
+        );
+        assert!(alpine.requirements.is_empty());
+        assert_eq!(alpine.supported, None);
+    }
+
+    #[test]
+    fn end_of_life() {
+        let at = |date: &str| {
+            UNIX_EPOCH + Duration::from_secs(days_from_date(date).unwrap() as u64 * 86_400)
+        };
+        let index = mock_index();
+        let ubuntu = &index.products["ubuntu:noble:arm64:default"];
+        assert!(!ubuntu.is_end_of_life_at(at("2029-05-30")));
+        assert!(ubuntu.is_end_of_life_at(at("2029-05-31")));
+
+        let alpine = &index.products["alpine:3.21:amd64:default"];
+        assert!(!alpine.is_end_of_life_at(at("2100-01-01")));
+
+        let unsupported: Product = serde_json::from_str(
+            r#"{ "arch": "amd64", "os": "Ubuntu", "release": "focal",
+                 "supported": false, "versions": {} }"#,
+        )
+        .unwrap();
+        assert!(unsupported.is_end_of_life_at(at("2020-01-01")));
+
+        assert_eq!(days_from_date("1970-01-01"), Some(0));
+        assert_eq!(days_from_date("2000-03-01"), Some(11_017));
+        assert_eq!(days_from_date("2029-13-01"), None);
+        assert_eq!(days_from_date("soon"), None);
+    }
+
+    #[test]
+    fn resolve_by_alias() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let resolve = |alias, arch| {
+            client.resolve_alias_from_index(&index, alias, arch, None, ItemKind::Rootfs)
+        };
+
+        let ubuntu = resolve("ubuntu/24.04", Arch::Aarch64).unwrap();
+        assert_eq!(ubuntu.product_key, "ubuntu:noble:arm64:default");
+        assert_eq!(ubuntu.release_title, "Ubuntu 24.04 LTS");
+        assert_eq!(ubuntu.supported_eol.as_deref(), Some("2029-05-31"));
+
+        let cloud = resolve("alpine/3.21/cloud", Arch::X86_64).unwrap();
+        assert_eq!(cloud.product_key, "alpine:3.21:amd64:cloud");
+        let default = resolve(" Alpine/3.21/ ", Arch::X86_64).unwrap();
+        assert_eq!(default.product_key, "alpine:3.21:amd64:default");
+
+        let err = resolve("alpine/edge", Arch::X86_64).unwrap_err();
+        assert!(matches!(err, Error::AliasNotFound { alias, .. } if alias == "alpine/edge"));
+        let err = resolve("ubuntu/noble", Arch::X86_64).unwrap_err();
+        assert!(matches!(err, Error::AliasNotFound { arch, .. } if arch == "amd64"));
+    }
+
+    #[test]
+    fn lookup_alias_entry() {
+        let client = LxcClient::new(Mirror::Official);
+        let index = mock_index();
+        let entry = client
+            .lookup_alias(&index, "ubuntu/noble/default", Arch::Aarch64)
+            .unwrap();
+        assert_eq!(entry.distro, Distro::Ubuntu);
+        assert_eq!(entry.version.as_str(), "24.04");
+        assert_eq!(entry.variant, Variant::default());
+        assert!(!entry.end_of_life);
+
+        let entry = client
+            .lookup_alias(&index, "alpine/3.21/cloud", Arch::X86_64)
+            .unwrap();
+        assert_eq!(entry.variant, Variant::cloud());
+        assert_eq!(entry.aliases, ["alpine/3.21/cloud"]);
+    }
+
+    #[test]
+    fn product_key_format() {
+        let distro = Distro::Rocky;
+        let version = Version::new("9");
+        let arch = Arch::X86_64;
+        let key = format!(
+            "{}:{}:{}:default",
+            distro.lxc_name(),
+            distro.lxc_release(&version),
+            arch.lxc_name()
+        );
+        assert_eq!(key, "rockylinux:9:amd64:default");
+    }
+
+    #[test]
+    fn mirror_url_in_resolved() {
+        let client = LxcClient::new(Mirror::Tuna);
+        let index = mock_index();
+        let result = client
+            .resolve_from_index(
+                &index,
+                Distro::Alpine,
+                &Version::new("3.21"),
+                Arch::X86_64,
+                &Variant::default(),
+                None,
+            )
+            .unwrap();
+        assert!(
+            result
+                .url
+                .starts_with("https://mirrors.tuna.tsinghua.edu.cn/lxc-images/")
+        );
+    }
+
+    #[test]
+    fn fresh_cached_index_skips_network() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let mirror = unreachable_mirror();
+        let cache = IndexCache::new(dir.path());
+
+        rt.block_on(async {
+            store_documents(&cache, &mirror).await;
+            let client = LxcClient::new(mirror)
+                .with_retry(RetryPolicy::none())
+                .with_index_cache(cache);
+            let resolved = client
+                .resolve(
+                    Distro::Alpine,
+                    &Version::new("3.21"),
+                    Arch::X86_64,
+                    &Variant::default(),
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(resolved.sha256, "eeff0011");
+        });
+    }
+
+    #[test]
+    fn mirror_set_index_failover_and_download_mirror() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let first = unreachable_mirror();
+        let second = Mirror::Custom("http://127.0.0.1:9/second".to_owned());
+        let mut cache = IndexCache::new(dir.path());
+        cache.offline = true;
+
+        rt.block_on(async {
+            // Only the second mirror's index is available.
+            store_documents(&cache, &second).await;
+            let resolve = |client: LxcClient| async move {
+                client
+                    .resolve(
+                        Distro::Alpine,
+                        &Version::new("3.21"),
+                        Arch::X86_64,
+                        &Variant::default(),
+                        None,
+                    )
+                    .await
+                    .unwrap()
+            };
+
+            let set = MirrorSet::new([first.clone(), second.clone()]);
+            let client = LxcClient::new(set.clone()).with_index_cache(cache.clone());
+            let resolved = resolve(client).await;
+            assert_eq!(resolved.sha256, "eeff0011");
+            assert_eq!(resolved.url, second.image_url(&resolved.path));
+
+            // Files come from the download mirror; the checksum still comes
+            // from the index mirror.
+            let client = LxcClient::new(set.with_download_mirror(Mirror::Tuna))
+                .with_index_cache(cache.clone());
+            let resolved = resolve(client).await;
+            assert_eq!(resolved.sha256, "eeff0011");
+            assert_eq!(resolved.url, Mirror::Tuna.image_url(&resolved.path));
+
+            // With no mirror left to try, the last error is returned.
+            let client = LxcClient::new(first).with_index_cache(cache);
+            let err = client.fetch_index().await.unwrap_err();
+            assert!(matches!(err, Error::IndexNotCached { .. }));
+        });
+    }
+
+    #[test]
+    fn offline_requires_cached_index() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let mirror = unreachable_mirror();
+        let mut cache = IndexCache::new(dir.path());
+        cache.offline = true;
+        cache.max_age = Duration::ZERO;
+
+        rt.block_on(async {
+            let client = LxcClient::new(mirror.clone()).with_index_cache(cache.clone());
+            let err = client.fetch_index().await.unwrap_err();
+            assert!(matches!(err, Error::IndexNotCached { .. }));
+
+            // Any cached copy is used offline, however old.
+            store_documents(&cache, &mirror).await;
+            let index = client.fetch_index().await.unwrap();
+            assert_eq!(index.products.len(), 3);
+        });
+    }
+
+    #[test]
+    fn stale_index_used_when_mirror_unreachable() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let mirror = unreachable_mirror();
+        let mut cache = IndexCache::new(dir.path());
+        cache.max_age = Duration::ZERO;
+
+        rt.block_on(async {
+            store_documents(&cache, &mirror).await;
+
+            let client = LxcClient::new(mirror.clone())
+                .with_retry(RetryPolicy::none())
+                .with_index_cache(cache.clone());
+            assert!(client.fetch_index().await.is_ok());
+
+            cache.stale_if_error = false;
+            let client = LxcClient::new(mirror)
+                .with_retry(RetryPolicy::none())
+                .with_index_cache(cache);
+            assert!(client.fetch_index().await.unwrap_err().is_transient());
+        });
+    }
+
+    #[test]
+    fn index_fetch_is_capped_and_cancellable() {
+        let rt = runtime();
+        let root = tempfile::tempdir().unwrap();
+        let streams = root.path().join("streams/v1");
+        std::fs::create_dir_all(&streams).unwrap();
+        std::fs::File::create(streams.join("index.json"))
+            .unwrap()
+            .set_len(MAX_INDEX_SIZE + 1)
+            .unwrap();
+
+        rt.block_on(async {
+            let client = LxcClient::new(Mirror::local(root.path()).unwrap());
+            let err = client.fetch_index().await.unwrap_err();
+            assert!(matches!(err, Error::TooLarge { .. }), "{err}");
+
+            // Cancelling interrupts the back-off between retries.
+            let cancel = CancellationToken::new();
+            let client = LxcClient::new(unreachable_mirror())
+                .with_retry(RetryPolicy {
+                    initial_backoff: Duration::from_secs(3600),
+                    max_backoff: Duration::from_secs(3600),
+                    ..RetryPolicy::default()
+                })
+                .with_cancellation(cancel.clone());
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                cancel.cancel();
+            });
+            let fetched = tokio::time::timeout(Duration::from_secs(10), client.fetch_index());
+            let err = fetched.await.unwrap().unwrap_err();
+            assert!(matches!(err, Error::Cancelled), "{err}");
+        });
+    }
+
+    /// Serves `bodies` as `200 OK` responses, one per connection and in
+    /// order, and returns the mirror and the paths requested.
+    fn serve(bodies: Vec<&'static str>) -> (Mirror, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mirror = Mirror::Custom(format!("http://{}", listener.local_addr().unwrap()));
+        let server = std::thread::spawn(move || {
+            let mut paths = Vec::new();
+            for body in bodies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                paths.push(line.split(' ').nth(1).unwrap_or_default().to_owned());
+                while line != "\r\n" {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+            paths
+        });
+        (mirror, server)
+    }
+
+    #[test]
+    fn invalid_index_is_not_cached() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = IndexCache::new(dir.path());
+        let (mirror, server) = serve(vec!["{\"format\": ", MOCK_STREAMS, MOCK_INDEX]);
+
+        rt.block_on(async {
+            let client = LxcClient::new(mirror.clone())
+                .with_retry(RetryPolicy::none())
+                .with_index_cache(cache.clone());
+            let err = client.fetch_index().await.unwrap_err();
+            assert!(matches!(err, Error::Json(_)), "{err}");
+            assert!(cache.load(&mirror.index_url()).await.is_none());
+
+            // The next fetch goes to the mirror again and caches what parses.
+            let index = client.fetch_index().await.unwrap();
+            assert_eq!(index.products.len(), 3);
+            assert!(cache.load(&mirror.index_url()).await.is_some());
+            assert!(cache.load(&mirror.streams_url()).await.is_some());
+        });
+        assert_eq!(
+            server.join().unwrap(),
+            [
+                "/streams/v1/index.json",
+                "/streams/v1/index.json",
+                "/streams/v1/images.json"
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_index_lists_product_streams() {
+        let streams: StreamsIndex = serde_json::from_str(MOCK_STREAMS).unwrap();
+        let entry = &streams.index["images"];
+        assert_eq!(entry.path, "streams/v1/images.json");
+        assert_eq!(entry.datatype, "image-downloads");
+        assert_eq!(entry.products.len(), 2);
+    }
+
+    #[test]
+    fn signed_urls() {
+        let client = LxcClient::new(Mirror::Official);
+        assert_eq!(
+            client.index_url(),
+            "https://images.linuxcontainers.org/streams/v1/index.json"
+        );
+        let client = client.with_keyring(Keyring::from_armored(TEST_KEY).unwrap());
+        assert_eq!(
+            client.index_url(),
+            "https://images.linuxcontainers.org/streams/v1/index.sjson"
+        );
+        assert_eq!(signed_path("a/images.json"), "a/images.sjson");
+    }
+
+    #[test]
+    fn resolves_from_verified_signed_index() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let mirror = unreachable_mirror();
+        let mut cache = IndexCache::new(dir.path());
+        cache.offline = true;
+
+        rt.block_on(async {
+            store_signed_documents(&cache, &mirror, SIGNED_INDEX).await;
+            let client = LxcClient::new(mirror)
+                .with_index_cache(cache)
+                .with_keyring(Keyring::from_armored(TEST_KEY).unwrap());
+            let index = client.fetch_index().await.unwrap();
+            assert_eq!(index.content_id, "images");
+            let resolved = client
+                .resolve_from_index(
+                    &index,
+                    Distro::Alpine,
+                    &Version::new("3.21"),
+                    Arch::X86_64,
+                    &Variant::default(),
+                    None,
+                )
+                .unwrap();
+            assert_eq!(resolved.sha256, "eeff0011");
+        });
+    }
+
+    #[test]
+    fn rejects_tampered_signed_index() {
+        let rt = runtime();
+        let dir = tempfile::tempdir().unwrap();
+        let mirror = unreachable_mirror();
+        let mut cache = IndexCache::new(dir.path());
+        cache.offline = true;
+
+        rt.block_on(async {
+            let tampered = SIGNED_INDEX.replace("eeff0011", "deadbeef");
+            store_signed_documents(&cache, &mirror, &tampered).await;
+            let client = LxcClient::new(mirror.clone())
+                .with_index_cache(cache.clone())
+                .with_keyring(Keyring::from_armored(TEST_KEY).unwrap());
+            let err = client.fetch_index().await.unwrap_err();
+            assert!(matches!(err, Error::SignatureInvalid { .. }), "{err}");
+
+            // Unsigned documents are not accepted in place of signed ones.
+            store_signed_documents(&cache, &mirror, MOCK_INDEX).await;
+            let err = client.fetch_index().await.unwrap_err();
+            assert!(matches!(err, Error::SignatureInvalid { .. }), "{err}");
+        });
+    }
+}
@@ -0,0 +1,852 @@
+//! Minimal OpenPGP support for signed Simplestreams documents.
+//!
+//! Covers what `.sjson` files need and nothing more: ASCII-armored public
+//! keys, version 4 RSA signatures over SHA-2 hashes, and the cleartext
+//! signature framework (RFC 4880 §7). Key certifications, expiry, and
+//! revocation are not evaluated; a key in the [`Keyring`] is trusted as is.
+
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+
+use crate::Error;
+
+const TAG_SIGNATURE: u8 = 2;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+
+/// Signature type of a cleartext-signed document (canonical text).
+const SIG_CANONICAL_TEXT: u8 = 0x01;
+
+const SUBPACKET_ISSUER: u8 = 16;
+const SUBPACKET_ISSUER_FINGERPRINT: u8 = 33;
+
+/// Set of trusted OpenPGP public keys for verifying signed indexes.
+///
+/// Only RSA keys that may sign are used; encrypt-only RSA keys and other key
+/// algorithms in an imported key block are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct Keyring {
+    keys: Vec<PublicKey>,
+}
+
+#[derive(Debug, Clone)]
+struct PublicKey {
+    fingerprint: [u8; 20],
+    key: RsaPublicKey,
+}
+
+impl Keyring {
+    /// Creates an empty keyring.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a keyring from ASCII-armored public key blocks.
+    pub fn from_armored(armored: &str) -> Result<Self, Error> {
+        let mut keyring = Self::new();
+        keyring.add_armored(armored)?;
+        Ok(keyring)
+    }
+
+    /// Reads a keyring file, either ASCII-armored or binary (as written by
+    /// `gpg --export`).
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let data = std::fs::read(path)?;
+        let mut keyring = Self::new();
+        match std::str::from_utf8(&data) {
+            Ok(text) if text.contains("-----BEGIN PGP PUBLIC KEY BLOCK-----") => {
+                keyring.add_armored(text)?;
+            }
+            _ => keyring.add_binary(&data)?,
+        }
+        Ok(keyring)
+    }
+
+    /// Adds every RSA key and subkey from ASCII-armored public key blocks.
+    pub fn add_armored(&mut self, armored: &str) -> Result<(), Error> {
+        let blocks = dearmor_all(armored, "PGP PUBLIC KEY BLOCK").map_err(Error::InvalidKey)?;
+        if blocks.is_empty() {
+            return Err(Error::InvalidKey("no public key block found".to_owned()));
+        }
+        for block in blocks {
+            self.add_binary(&block)?;
+        }
+        Ok(())
+    }
+
+    fn add_binary(&mut self, data: &[u8]) -> Result<(), Error> {
+        let before = self.keys.len();
+        for packet in packets(data).map_err(Error::InvalidKey)? {
+            if matches!(packet.tag, TAG_PUBLIC_KEY | TAG_PUBLIC_SUBKEY) {
+                if let Some(key) = PublicKey::parse(packet.body).map_err(Error::InvalidKey)? {
+                    self.keys.push(key);
+                }
+            }
+        }
+        if self.keys.len() == before {
+            return Err(Error::InvalidKey(
+                "no supported (RSA) public key found".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the keyring holds no keys.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the upper-case hex fingerprints of all keys and subkeys.
+    pub fn fingerprints(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .map(|k| hex::encode_upper(k.fingerprint))
+            .collect()
+    }
+
+    /// Verifies a cleartext-signed document and returns the signed text.
+    ///
+    /// Anything outside the signed section is discarded. At least one
+    /// signature must verify against a key in this keyring.
+    pub(crate) fn verify_cleartext(&self, document: &str) -> Result<String, String> {
+        let signed = CleartextMessage::parse(document)?;
+        let signatures = packets(&signed.signature)?
+            .into_iter()
+            .filter(|p| p.tag == TAG_SIGNATURE);
+
+        // Signers may add signatures this keyring cannot check (other
+        // versions or algorithms); skip those as long as one verifies.
+        let mut last_error = "no signature packet".to_owned();
+        for packet in signatures {
+            let verified = Signature::parse(packet.body).and_then(|signature| {
+                self.verify_signature(&signature, signed.canonical_text.as_bytes())
+            });
+            match verified {
+                Ok(()) => return Ok(signed.text),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn verify_signature(&self, signature: &Signature, text: &[u8]) -> Result<(), String> {
+        if signature.sig_type != SIG_CANONICAL_TEXT {
+            return Err(format!(
+                "unexpected signature type {:#04x}",
+                signature.sig_type
+            ));
+        }
+        let (scheme, digest) = signature.digest(text)?;
+        if digest[..2] != signature.hash_prefix {
+            return Err("signature does not match the signed text".to_owned());
+        }
+
+        let candidates: Vec<&PublicKey> = self
+            .keys
+            .iter()
+            .filter(|k| signature.issued_by(k))
+            .collect();
+        if candidates.is_empty() {
+            return Err(match signature.issuer_key_id {
+                Some(id) => format!("signed by unknown key {}", hex::encode_upper(id)),
+                None => "signed by unknown key".to_owned(),
+            });
+        }
+        for key in candidates {
+            // The MPI drops leading zero bytes; RSA verification wants the
+            // signature padded to the modulus size.
+            let size = key.key.size();
+            if signature.value.len() > size {
+                continue;
+            }
+            let mut padded = vec![0; size - signature.value.len()];
+            padded.extend_from_slice(&signature.value);
+            if key.key.verify(scheme.clone(), &digest, &padded).is_ok() {
+                return Ok(());
+            }
+        }
+        Err("bad signature".to_owned())
+    }
+}
+
+impl PublicKey {
+    /// Parses a v4 public key packet body. Returns `None` for keys that are
+    /// not RSA signing keys (RSA encrypt-only is algorithm 2).
+    fn parse(body: &[u8]) -> Result<Option<Self>, String> {
+        let mut r = Reader::new(body);
+        let version = r.u8()?;
+        if version != 4 {
+            return Ok(None);
+        }
+        r.take(4)?; // creation time
+        let algorithm = r.u8()?;
+        if !matches!(algorithm, 1 | 3) {
+            return Ok(None);
+        }
+        let n = BigUint::from_bytes_be(r.mpi()?);
+        let e = BigUint::from_bytes_be(r.mpi()?);
+        let key = RsaPublicKey::new(n, e).map_err(|e| format!("invalid RSA key: {e}"))?;
+
+        let mut hasher = Sha1::new();
+        hasher.update([0x99]);
+        hasher.update((body.len() as u16).to_be_bytes());
+        hasher.update(body);
+        Ok(Some(Self {
+            fingerprint: hasher.finalize().into(),
+            key,
+        }))
+    }
+
+    fn key_id(&self) -> &[u8] {
+        &self.fingerprint[12..]
+    }
+}
+
+/// A parsed v4 RSA signature packet.
+#[derive(Debug)]
+struct Signature {
+    sig_type: u8,
+    hash_algorithm: u8,
+    /// Version through hashed subpackets, as covered by the hash.
+    hashed: Vec<u8>,
+    issuer_key_id: Option<[u8; 8]>,
+    issuer_fingerprint: Option<[u8; 20]>,
+    hash_prefix: [u8; 2],
+    value: Vec<u8>,
+}
+
+impl Signature {
+    fn parse(body: &[u8]) -> Result<Self, String> {
+        let mut r = Reader::new(body);
+        let version = r.u8()?;
+        if version != 4 {
+            return Err(format!("unsupported signature version {version}"));
+        }
+        let sig_type = r.u8()?;
+        let public_key_algorithm = r.u8()?;
+        if !matches!(public_key_algorithm, 1 | 3) {
+            return Err(format!(
+                "unsupported signature algorithm {public_key_algorithm}"
+            ));
+        }
+        let hash_algorithm = r.u8()?;
+        let hashed_len = r.u16()? as usize;
+        let hashed_subpackets = r.take(hashed_len)?;
+        let hashed = body[..6 + hashed_len].to_vec();
+        let unhashed_len = r.u16()? as usize;
+        let unhashed_subpackets = r.take(unhashed_len)?;
+        let hash_prefix = [r.u8()?, r.u8()?];
+        let value = r.mpi()?.to_vec();
+
+        let mut signature = Self {
+            sig_type,
+            hash_algorithm,
+            hashed,
+            issuer_key_id: None,
+            issuer_fingerprint: None,
+            hash_prefix,
+            value,
+        };
+        for area in [hashed_subpackets, unhashed_subpackets] {
+            for (kind, data) in subpackets(area)? {
+                match kind {
+                    SUBPACKET_ISSUER => signature.issuer_key_id = data.try_into().ok(),
+                    SUBPACKET_ISSUER_FINGERPRINT if data.first() == Some(&4) => {
+                        signature.issuer_fingerprint = data[1..].try_into().ok();
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(signature)
+    }
+
+    fn issued_by(&self, key: &PublicKey) -> bool {
+        match (&self.issuer_fingerprint, &self.issuer_key_id) {
+            (Some(fingerprint), _) => *fingerprint == key.fingerprint,
+            (None, Some(id)) => id[..] == *key.key_id(),
+            (None, None) => true,
+        }
+    }
+
+    /// Hashes `text` with the signature trailer (RFC 4880 §5.2.4).
+    fn digest(&self, text: &[u8]) -> Result<(Pkcs1v15Sign, Vec<u8>), String> {
+        fn hash<D: Digest>(text: &[u8], hashed: &[u8]) -> Vec<u8> {
+            let mut hasher = D::new();
+            hasher.update(text);
+            hasher.update(hashed);
+            hasher.update([0x04, 0xff]);
+            hasher.update((hashed.len() as u32).to_be_bytes());
+            hasher.finalize().to_vec()
+        }
+        let hashed = &self.hashed;
+        Ok(match self.hash_algorithm {
+            8 => (Pkcs1v15Sign::new::<Sha256>(), hash::<Sha256>(text, hashed)),
+            9 => (Pkcs1v15Sign::new::<Sha384>(), hash::<Sha384>(text, hashed)),
+            10 => (Pkcs1v15Sign::new::<Sha512>(), hash::<Sha512>(text, hashed)),
+            11 => (Pkcs1v15Sign::new::<Sha224>(), hash::<Sha224>(text, hashed)),
+            other => return Err(format!("unsupported hash algorithm {other}")),
+        })
+    }
+}
+
+/// The parts of a `-----BEGIN PGP SIGNED MESSAGE-----` document.
+#[derive(Debug)]
+struct CleartextMessage {
+    /// Signed text with dash-escaping removed and `\n` line endings.
+    text: String,
+    /// Signed text in canonical form, as hashed by the signer.
+    canonical_text: String,
+    /// Decoded signature packets.
+    signature: Vec<u8>,
+}
+
+impl CleartextMessage {
+    fn parse(document: &str) -> Result<Self, String> {
+        let mut lines = document.lines().map(|l| l.trim_end_matches('\r'));
+        lines
+            .by_ref()
+            .find(|l| l.trim_end() == "-----BEGIN PGP SIGNED MESSAGE-----")
+            .ok_or("not a cleartext-signed document")?;
+        // Armor headers ("Hash: SHA256") end at the first empty line.
+        for line in lines.by_ref() {
+            if line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut text_lines = Vec::new();
+        let mut signed_end = false;
+        for line in lines.by_ref() {
+            if line.trim_end() == "-----BEGIN PGP SIGNATURE-----" {
+                signed_end = true;
+                break;
+            }
+            text_lines.push(line.strip_prefix("- ").unwrap_or(line));
+        }
+        if !signed_end {
+            return Err("missing signature block".to_owned());
+        }
+
+        let armored: Vec<&str> = std::iter::once("-----BEGIN PGP SIGNATURE-----")
+            .chain(lines)
+            .collect();
+        let signature = dearmor_all(&armored.join("\n"), "PGP SIGNATURE")?
+            .into_iter()
+            .next()
+            .ok_or("missing signature block")?;
+
+        let canonical_text = text_lines
+            .iter()
+            .map(|l| l.trim_end_matches([' ', '\t']))
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        Ok(Self {
+            text: text_lines.join("\n"),
+            canonical_text,
+            signature,
+        })
+    }
+}
+
+/// Decodes every armored block of the given kind in `text`.
+fn dearmor_all(text: &str, kind: &str) -> Result<Vec<Vec<u8>>, String> {
+    let begin = format!("-----BEGIN {kind}-----");
+    let end = format!("-----END {kind}-----");
+    let mut blocks = Vec::new();
+    let mut lines = text.lines().map(str::trim);
+
+    while lines.by_ref().any(|l| l == begin) {
+        let block: Vec<&str> = lines.by_ref().take_while(|&l| l != end).collect();
+        // Armor headers ("Version: ...") are separated from the data by an
+        // empty line.
+        let data_start = block
+            .iter()
+            .position(|l| l.is_empty())
+            .filter(|&i| block[..i].iter().all(|l| l.contains(": ")))
+            .map_or(0, |i| i + 1);
+
+        let mut base64 = String::new();
+        let mut checksum = None;
+        for line in &block[data_start..] {
+            match line.strip_prefix('=') {
+                Some(crc) => checksum = Some(crc),
+                None => base64.push_str(line),
+            }
+        }
+        let data = BASE64
+            .decode(base64)
+            .map_err(|e| format!("invalid armor: {e}"))?;
+        if let Some(checksum) = checksum {
+            let expected = BASE64
+                .decode(checksum)
+                .map_err(|e| format!("invalid armor checksum: {e}"))?;
+            if expected[..] != crc24(&data).to_be_bytes()[1..] {
+                return Err("armor checksum mismatch".to_owned());
+            }
+        }
+        blocks.push(data);
+    }
+    Ok(blocks)
+}
+
+/// OpenPGP armor checksum (RFC 4880 §6.1).
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0x00b7_04ce;
+    for &byte in data {
+        crc ^= u32::from(byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x0100_0000 != 0 {
+                crc ^= 0x0186_4cfb;
+            }
+        }
+    }
+    crc & 0x00ff_ffff
+}
+
+#[derive(Debug)]
+struct Packet<'a> {
+    tag: u8,
+    body: &'a [u8],
+}
+
+/// Splits binary OpenPGP data into packets.
+fn packets(data: &[u8]) -> Result<Vec<Packet<'_>>, String> {
+    let mut r = Reader::new(data);
+    let mut packets = Vec::new();
+    while !r.is_empty() {
+        let header = r.u8()?;
+        if header & 0x80 == 0 {
+            return Err("invalid packet header".to_owned());
+        }
+        let (tag, len) = if header & 0x40 != 0 {
+            let tag = header & 0x3f;
+            let len = match r.u8()? {
+                first @ 0..=191 => first as usize,
+                first @ 192..=223 => ((first as usize - 192) << 8) + r.u8()? as usize + 192,
+                255 => r.u32()? as usize,
+                _ => return Err("partial packet lengths are not supported".to_owned()),
+            };
+            (tag, len)
+        } else {
+            let tag = (header >> 2) & 0x0f;
+            let len = match header & 0x03 {
+                0 => r.u8()? as usize,
+                1 => r.u16()? as usize,
+                2 => r.u32()? as usize,
+                _ => r.remaining(),
+            };
+            (tag, len)
+        };
+        packets.push(Packet {
+            tag,
+            body: r.take(len)?,
+        });
+    }
+    Ok(packets)
+}
+
+/// Splits a signature subpacket area into `(type, data)` pairs.
+fn subpackets(area: &[u8]) -> Result<Vec<(u8, &[u8])>, String> {
+    let mut r = Reader::new(area);
+    let mut subpackets = Vec::new();
+    while !r.is_empty() {
+        let len = match r.u8()? {
+            first @ 0..=191 => first as usize,
+            first @ 192..=254 => ((first as usize - 192) << 8) + r.u8()? as usize + 192,
+            255 => r.u32()? as usize,
+        };
+        let body = r.take(len)?;
+        let (&kind, data) = body.split_first().ok_or("empty subpacket")?;
+        // The high bit only marks the subpacket as critical.
+        subpackets.push((kind & 0x7f, data));
+    }
+    Ok(subpackets)
+}
+
+/// Bounds-checked big-endian reader.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.data.len() {
+            return Err("truncated packet".to_owned());
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Reads a multiprecision integer (bit count + big-endian bytes).
+    fn mpi(&mut self) -> Result<&'a [u8], String> {
+        let bits = self.u16()? as usize;
+        self.take(bits.div_ceil(8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_KEY: &str = include_str!("testdata/test-key.asc");
+    const OTHER_KEY: &str = include_str!("testdata/other-key.asc");
+    const SIGNED_MESSAGE: &str = include_str!("testdata/message.txt.asc");
+
+    #[test]
+    fn imports_armored_key() {
+        let keyring = Keyring::from_armored(TEST_KEY).unwrap();
+        assert_eq!(
+            keyring.fingerprints(),
+            ["58803024E30F86454680E655258C6CEF8F86647D"]
+        );
+        assert!(Keyring::from_armored("not a key").is_err());
+    }
+
+    #[test]
+    fn verifies_cleartext_signature() {
+        let keyring = Keyring::from_armored(TEST_KEY).unwrap();
+        let text = keyring.verify_cleartext(SIGNED_MESSAGE).unwrap();
+        // Dash-escaping is removed; trailing whitespace is kept in the text.
+        assert_eq!(
+            text,
+            "first line\n-dashed line  \n--- also dashed\n\ntrailing tab\t\nlast line"
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_text() {
+        let keyring = Keyring::from_armored(TEST_KEY).unwrap();
+        let tampered = SIGNED_MESSAGE.replace("last line", "last line!");
+        let err = keyring.verify_cleartext(&tampered).unwrap_err();
+        assert!(err.contains("does not match") || err.contains("bad signature"));
+    }
+
+    #[test]
+    fn rejects_unknown_key() {
+        let keyring = Keyring::from_armored(OTHER_KEY).unwrap();
+        let err = keyring.verify_cleartext(SIGNED_MESSAGE).unwrap_err();
+        assert!(err.contains("unknown key"), "{err}");
+    }
+
+    /// Private half of a throwaway RSA key, for signatures the tests can
+    /// bend in ways `gpg` never would.
+    const SIGNING_KEY: &str = include_str!("testdata/signing-key.hex");
+
+    const TEXT: &str = "{\"format\": \"products:1.0\"}\nsecond line";
+
+    struct Signer {
+        key: rsa::RsaPrivateKey,
+        public: PublicKey,
+    }
+
+    impl Signer {
+        fn new() -> Self {
+            let part = |name: &str| {
+                let hex = SIGNING_KEY
+                    .lines()
+                    .find_map(|l| l.strip_prefix(name)?.strip_prefix(' '))
+                    .unwrap();
+                BigUint::from_bytes_be(&hex::decode(hex).unwrap())
+            };
+            let (n, e) = (part("n"), part("e"));
+            let key = rsa::RsaPrivateKey::from_components(
+                n.clone(),
+                e.clone(),
+                part("d"),
+                vec![part("p"), part("q")],
+            )
+            .unwrap();
+            let mut body = vec![4, 0, 0, 0, 0, 1];
+            body.extend(mpi(&n.to_bytes_be()));
+            body.extend(mpi(&e.to_bytes_be()));
+            let public = PublicKey::parse(&body).unwrap().unwrap();
+            Self { key, public }
+        }
+
+        fn keyring(&self) -> Keyring {
+            Keyring {
+                keys: vec![self.public.clone()],
+            }
+        }
+
+        /// Signs `text` and returns the signature packet body without its
+        /// trailing MPI, and the signature value.
+        fn sign(&self, text: &str, edit: impl FnOnce(&mut SignatureParts)) -> (Vec<u8>, Vec<u8>) {
+            let mut fingerprint = vec![33, 4];
+            fingerprint.extend(self.public.fingerprint);
+            let mut parts = SignatureParts {
+                version: 4,
+                algorithm: 1,
+                hashed: subpacket(&fingerprint),
+                unhashed: Vec::new(),
+                trailer_len: None,
+            };
+            edit(&mut parts);
+
+            let mut head = vec![parts.version, SIG_CANONICAL_TEXT, parts.algorithm, 8];
+            head.extend((parts.hashed.len() as u16).to_be_bytes());
+            head.extend(&parts.hashed);
+            let trailer_len = parts.trailer_len.unwrap_or(head.len() as u32);
+            let digest = Sha256::new()
+                .chain_update(text.replace('\n', "\r\n"))
+                .chain_update(&head)
+                .chain_update([0x04, 0xff])
+                .chain_update(trailer_len.to_be_bytes())
+                .finalize();
+            let value = self
+                .key
+                .sign(Pkcs1v15Sign::new::<Sha256>(), &digest)
+                .unwrap();
+
+            head.extend((parts.unhashed.len() as u16).to_be_bytes());
+            head.extend(&parts.unhashed);
+            head.extend(&digest[..2]);
+            (head, value)
+        }
+    }
+
+    /// Fields of a generated signature packet that a test may change.
+    struct SignatureParts {
+        version: u8,
+        algorithm: u8,
+        /// Hashed subpacket area.
+        hashed: Vec<u8>,
+        /// Unhashed subpacket area.
+        unhashed: Vec<u8>,
+        /// Length of the hashed data written into the hash trailer, if not
+        /// the real one.
+        trailer_len: Option<u32>,
+    }
+
+    fn subpacket(data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![data.len() as u8];
+        packet.extend(data);
+        packet
+    }
+
+    fn mpi(value: &[u8]) -> Vec<u8> {
+        let value = &value[value.iter().take_while(|&&b| b == 0).count()..];
+        let bits = value.len() * 8 - value.first().map_or(8, |b| b.leading_zeros() as usize);
+        let mut mpi = (bits as u16).to_be_bytes().to_vec();
+        mpi.extend(value);
+        mpi
+    }
+
+    /// Frames `body` as a new-format packet with the given tag.
+    fn packet(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0xc0 | tag];
+        match body.len() {
+            len @ 0..=191 => packet.push(len as u8),
+            len => {
+                let len = len - 192;
+                packet.extend([(len >> 8) as u8 + 192, len as u8]);
+            }
+        }
+        packet.extend(body);
+        packet
+    }
+
+    /// Wraps signature packet bodies and `text` into a cleartext-signed
+    /// document.
+    fn cleartext(text: &str, bodies: &[&[u8]]) -> String {
+        let packets: Vec<u8> = bodies
+            .iter()
+            .flat_map(|body| packet(TAG_SIGNATURE, body))
+            .collect();
+        let crc = BASE64.encode(&crc24(&packets).to_be_bytes()[1..]);
+        format!(
+            "-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA256\n\n{text}\n\
+             -----BEGIN PGP SIGNATURE-----\n\n{}\n={crc}\n-----END PGP SIGNATURE-----\n",
+            BASE64.encode(&packets)
+        )
+    }
+
+    fn signed(signer: &Signer, text: &str, edit: impl FnOnce(&mut SignatureParts)) -> String {
+        let (mut body, value) = signer.sign(text, edit);
+        body.extend(mpi(&value));
+        cleartext(text, &[&body])
+    }
+
+    #[test]
+    fn verifies_generated_signature() {
+        let signer = Signer::new();
+        let document = signed(&signer, TEXT, |_| {});
+        assert_eq!(signer.keyring().verify_cleartext(&document).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn rejects_tampered_cleartext() {
+        let signer = Signer::new();
+        let keyring = signer.keyring();
+        let document = signed(&signer, TEXT, |_| {});
+        for tampered in [
+            document.replace("products:1.0", "products:1.1"),
+            document.replace("second line", "second line\nthird line"),
+            document.replace("second line\n", "second line\n\n"),
+        ] {
+            let err = keyring.verify_cleartext(&tampered).unwrap_err();
+            assert!(
+                err.contains("does not match") || err.contains("bad signature"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_issuer() {
+        let signer = Signer::new();
+        let keyring = signer.keyring();
+        let other = Keyring::from_armored(TEST_KEY).unwrap().keys.remove(0);
+
+        // An issuer key id alone that names another key.
+        let mut issuer = vec![16];
+        issuer.extend(other.key_id());
+        let document = signed(&signer, TEXT, |parts| {
+            parts.hashed = Vec::new();
+            parts.unhashed = subpacket(&issuer);
+        });
+        let err = keyring.verify_cleartext(&document).unwrap_err();
+        assert!(err.contains(&hex::encode_upper(other.key_id())), "{err}");
+
+        // An issuer fingerprint that names another key.
+        let mut fingerprint = vec![33, 4];
+        fingerprint.extend(other.fingerprint);
+        let document = signed(&signer, TEXT, |parts| {
+            parts.hashed = subpacket(&fingerprint);
+        });
+        let err = keyring.verify_cleartext(&document).unwrap_err();
+        assert!(err.contains("unknown key"), "{err}");
+    }
+
+    #[test]
+    fn rejects_malformed_signature_mpi() {
+        let signer = Signer::new();
+        let keyring = signer.keyring();
+        let (head, value) = signer.sign(TEXT, |_| {});
+
+        // Truncated: the bit count promises more bytes than follow.
+        let mut body = head.clone();
+        body.extend(mpi(&value));
+        body.truncate(body.len() - 10);
+        let err = keyring
+            .verify_cleartext(&cleartext(TEXT, &[&body]))
+            .unwrap_err();
+        assert!(err.contains("truncated"), "{err}");
+
+        // Oversized: a value wider than the modulus.
+        let mut body = head;
+        body.extend(mpi(&[value.as_slice(), &[0; 8]].concat()));
+        let err = keyring
+            .verify_cleartext(&cleartext(TEXT, &[&body]))
+            .unwrap_err();
+        assert!(err.contains("bad signature"), "{err}");
+    }
+
+    #[test]
+    fn rejects_unsupported_signature_packets() {
+        let signer = Signer::new();
+        let keyring = signer.keyring();
+
+        let document = signed(&signer, TEXT, |parts| parts.version = 3);
+        let err = keyring.verify_cleartext(&document).unwrap_err();
+        assert!(err.contains("unsupported signature version 3"), "{err}");
+
+        // DSA (17) and EdDSA (22) are not supported.
+        for algorithm in [17, 22] {
+            let document = signed(&signer, TEXT, |parts| parts.algorithm = algorithm);
+            let err = keyring.verify_cleartext(&document).unwrap_err();
+            assert!(err.contains("unsupported signature algorithm"), "{err}");
+        }
+    }
+
+    #[test]
+    fn skips_unsupported_signature_packets() {
+        let signer = Signer::new();
+        let keyring = signer.keyring();
+        let (mut good, value) = signer.sign(TEXT, |_| {});
+        good.extend(mpi(&value));
+
+        for edit in [
+            (|parts: &mut SignatureParts| parts.version = 3) as fn(&mut SignatureParts),
+            |parts| parts.algorithm = 17,
+        ] {
+            let (mut other, value) = signer.sign(TEXT, edit);
+            other.extend(mpi(&value));
+            for bodies in [[&other, &good], [&good, &other]] {
+                let document = cleartext(TEXT, &bodies.map(Vec::as_slice));
+                assert_eq!(keyring.verify_cleartext(&document).unwrap(), TEXT);
+            }
+        }
+    }
+
+    #[test]
+    fn skips_encrypt_only_rsa_keys() {
+        let key = Signer::new().public.key;
+        let mut body = vec![4, 0, 0, 0, 0, 2];
+        body.extend(mpi(&key.n().to_bytes_be()));
+        body.extend(mpi(&key.e().to_bytes_be()));
+        assert!(PublicKey::parse(&body).unwrap().is_none());
+
+        let mut keyring = Keyring::default();
+        let err = keyring
+            .add_binary(&packet(TAG_PUBLIC_KEY, &body))
+            .unwrap_err();
+        assert!(err.to_string().contains("no supported"), "{err}");
+        assert!(keyring.is_empty());
+    }
+
+    #[test]
+    fn rejects_wrong_hash_trailer_length() {
+        let signer = Signer::new();
+        let keyring = signer.keyring();
+        for delta in [1, u32::MAX] {
+            let document = signed(&signer, TEXT, |parts| {
+                let real = 6 + parts.hashed.len() as u32;
+                parts.trailer_len = Some(real.wrapping_add(delta));
+            });
+            let err = keyring.verify_cleartext(&document).unwrap_err();
+            assert!(
+                err.contains("does not match") || err.contains("bad signature"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn crc24_matches_rfc_initial_value() {
+        assert_eq!(crc24(&[]), 0x00b7_04ce);
+    }
+}
@@ -1,15 +1,30 @@
 //! LXC Images mirror selection for downloading rootfs archives.
+//!
+//! Mirrors parse from the strings their `Display` produces (`tuna`,
+//! `custom(https://...)`), and a [`MirrorSet`] can be configured from the
+//! [`MIRROR_ENV`] environment variable or a TOML file, see
+//! [`MirrorSet::load`]. A custom mirror can also be a Simplestreams tree in a
+//! local directory, addressed by a `file://` URL (see [`Mirror::local`]).
 
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::Error;
+
+/// Environment variable holding a [`MirrorSet`], in the syntax of its
+/// `FromStr` implementation (e.g. `tuna, official`).
+pub const MIRROR_ENV: &str = "ARCBOX_MIRROR";
 
 /// LXC Images mirror selection.
 ///
 /// All mirrors serve the same Simplestreams API and image files.
 /// Choose a mirror based on geographic proximity or network conditions.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub enum Mirror {
     /// Official server: images.linuxcontainers.org (Canada, GeoIP DNS)
+    #[default]
     Official,
     /// Tsinghua University TUNA: mirrors.tuna.tsinghua.edu.cn/lxc-images
     Tuna,
@@ -17,15 +32,20 @@ pub enum Mirror {
     Ustc,
     /// Beijing Foreign Studies University: mirrors.bfsu.edu.cn/lxc-images
     Bfsu,
-    /// Custom mirror URL (e.g. Cloudflare R2 self-hosted CDN)
+    /// Custom mirror URL (e.g. Cloudflare R2 self-hosted CDN), or a
+    /// `file://` URL of a local directory
     Custom(String),
+    /// Fastest preset, picked by [`MirrorProbe`](crate::MirrorProbe) when a
+    /// download starts. Where no probe runs, such as a bare
+    /// [`LxcClient`](crate::lxc::LxcClient), it behaves like `Official`.
+    Auto,
 }
 
 impl Mirror {
     /// Returns the base URL for this mirror (no trailing slash).
     pub fn base_url(&self) -> &str {
         match self {
-            Self::Official => "https://images.linuxcontainers.org",
+            Self::Official | Self::Auto => "https://images.linuxcontainers.org",
             Self::Tuna => "https://mirrors.tuna.tsinghua.edu.cn/lxc-images",
             Self::Ustc => "https://mirrors.ustc.edu.cn/lxc-images",
             Self::Bfsu => "https://mirrors.bfsu.edu.cn/lxc-images",
@@ -33,28 +53,149 @@ impl Mirror {
         }
     }
 
-    /// Returns the Simplestreams index URL.
+    /// Returns the Simplestreams index URL (`index.json`), which lists the
+    /// product streams.
+    pub fn index_url(&self) -> String {
+        format!("{}/streams/v1/index.json", self.base_url())
+    }
+
+    /// Returns the URL of the LXC images product stream (`images.json`).
     pub fn streams_url(&self) -> String {
         format!("{}/streams/v1/images.json", self.base_url())
     }
 
-    /// Returns the full download URL for a given image path from the index.
+    /// Returns the full URL for a path relative to the mirror root, such as
+    /// an image or stream path from the index.
     pub fn image_url(&self, path: &str) -> String {
         format!("{}/{}", self.base_url(), path)
     }
 
-    /// Returns all preset mirrors (excluding Custom).
+    /// Returns all preset mirrors (excluding Custom and Auto).
     pub fn presets() -> &'static [Mirror] {
         &[Self::Official, Self::Tuna, Self::Ustc, Self::Bfsu]
     }
+
+    /// Creates a mirror for a Simplestreams tree in a local directory, such
+    /// as a USB stick or an NFS share, as a `file://` URL.
+    ///
+    /// Index documents and image files are then read from the filesystem,
+    /// with the same size and SHA256 checks and progress events as
+    /// downloads. A relative `path` is resolved against the current
+    /// directory.
+    pub fn local(path: impl AsRef<Path>) -> Result<Mirror, Error> {
+        let path = path.as_ref();
+        let invalid = || Error::InvalidConfig(format!("invalid mirror path {}", path.display()));
+        let absolute = std::path::absolute(path).map_err(|_| invalid())?;
+        let url = url::Url::from_directory_path(&absolute).map_err(|()| invalid())?;
+        Ok(Self::Custom(url.as_str().trim_end_matches('/').to_owned()))
+    }
+
+    /// Returns the directory of a [`local`](Self::local) (`file://`) mirror.
+    pub fn local_path(&self) -> Option<PathBuf> {
+        file_path(self.base_url())
+    }
+
+    /// Parses a comma-separated list of mirrors, such as
+    /// `"tuna, custom(https://images.example.com), official"`.
+    pub fn parse_list(s: &str) -> Result<Vec<Mirror>, Error> {
+        split_list(s).map(str::parse).collect()
+    }
 }
 
-impl Default for Mirror {
-    fn default() -> Self {
-        Self::Official
+/// Parses a preset name (case-insensitive), `auto`, `custom(<url>)` as
+/// written by `Display`, a bare `http://`, `https://` or `file://` URL, or a
+/// directory path (absolute, or starting with `./` or `../`), which becomes
+/// a [`local`](Mirror::local) mirror.
+impl FromStr for Mirror {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let mirror = match s.to_ascii_lowercase().as_str() {
+            "official" => Self::Official,
+            "tuna" => Self::Tuna,
+            "ustc" => Self::Ustc,
+            "bfsu" => Self::Bfsu,
+            "auto" => Self::Auto,
+            lower if lower.starts_with("custom(") && lower.ends_with(')') => {
+                custom(&s["custom(".len()..s.len() - 1])?
+            }
+            lower
+                if ["http://", "https://", "file://"]
+                    .iter()
+                    .any(|scheme| lower.starts_with(scheme)) =>
+            {
+                custom(s)?
+            }
+            _ if s.starts_with("./") || s.starts_with("../") || Path::new(s).is_absolute() => {
+                Self::local(s)?
+            }
+            _ => {
+                return Err(Error::InvalidConfig(format!(
+                    "unknown mirror {s:?}: expected official, tuna, ustc, bfsu, auto, \
+                     custom(<url>), a URL or a directory path"
+                )));
+            }
+        };
+        Ok(mirror)
+    }
+}
+
+fn custom(url: &str) -> Result<Mirror, Error> {
+    match url.trim() {
+        "" => Err(Error::InvalidConfig(
+            "custom mirror without a URL".to_owned(),
+        )),
+        url => Ok(Mirror::Custom(url.to_owned())),
     }
 }
 
+/// Returns the local path of a `file://` URL.
+pub(crate) fn file_path(url: &str) -> Option<PathBuf> {
+    let url = url::Url::parse(url).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    url.to_file_path().ok()
+}
+
+/// Error for a [`MirrorSet`] whose `index` list is empty.
+pub(crate) fn no_index_mirror() -> Error {
+    Error::InvalidConfig("mirror set has no index mirror".to_owned())
+}
+
+/// Wraps an error from reading a local mirror file with its path, keeping
+/// the error kind.
+pub(crate) fn local_error(path: &Path, e: std::io::Error) -> Error {
+    Error::Io(std::io::Error::new(
+        e.kind(),
+        format!("{}: {e}", path.display()),
+    ))
+}
+
+/// Splits a comma-separated list, ignoring commas inside `custom(...)`.
+fn split_list(s: &str) -> impl Iterator<Item = &str> {
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut parts = Vec::new();
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+}
+
 impl fmt::Display for Mirror {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -63,10 +204,224 @@ impl fmt::Display for Mirror {
             Self::Ustc => write!(f, "ustc"),
             Self::Bfsu => write!(f, "bfsu"),
             Self::Custom(url) => write!(f, "custom({url})"),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// Mirrors tried in order, optionally with separate mirrors for image
+/// files.
+///
+/// The Simplestreams index, and so every checksum, comes from the first
+/// [`index`](Self::index) mirror that answers. Image files come from the
+/// [`downloads`](Self::downloads) mirrors first, in order, and then from that
+/// index mirror. Each file is verified against the index's SHA256, so a stale
+/// or tampered download mirror fails verification instead of being trusted.
+///
+/// Everything that takes a `MirrorSet` also takes a single [`Mirror`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirrorSet {
+    /// Mirrors the index is fetched from, in order. A set without any
+    /// fails with [`Error::InvalidConfig`] when used.
+    pub index: Vec<Mirror>,
+    /// Mirrors image files are downloaded from before the index mirror.
+    #[serde(default)]
+    pub downloads: Vec<Mirror>,
+}
+
+impl MirrorSet {
+    /// Creates a set that tries `mirrors` in order for everything, or
+    /// [`Mirror::Official`] if `mirrors` is empty.
+    pub fn new(mirrors: impl IntoIterator<Item = Mirror>) -> Self {
+        let mut index: Vec<Mirror> = mirrors.into_iter().collect();
+        if index.is_empty() {
+            index.push(Mirror::default());
+        }
+        Self {
+            index,
+            downloads: Vec::new(),
+        }
+    }
+
+    /// Downloads image files from `mirror` (after any download mirrors
+    /// added before), while the index still comes from the index mirrors.
+    pub fn with_download_mirror(mut self, mirror: Mirror) -> Self {
+        self.downloads.push(mirror);
+        self
+    }
+
+    /// Returns the first index mirror.
+    pub fn primary(&self) -> &Mirror {
+        self.index.first().unwrap_or(&Mirror::Official)
+    }
+
+    /// Returns the configured mirrors, in order of precedence:
+    ///
+    /// 1. the [`MIRROR_ENV`] (`ARCBOX_MIRROR`) environment variable, if set
+    ///    and not empty;
+    /// 2. `config_file`, if given and present (see
+    ///    [`from_config_file`](Self::from_config_file));
+    /// 3. [`Mirror::Official`].
+    ///
+    /// Mirrors passed explicitly in code are not affected by either; callers
+    /// that take a mirror option should prefer it over this result.
+    pub fn load(config_file: Option<&Path>) -> Result<Self, Error> {
+        let env = std::env::var(MIRROR_ENV).ok();
+        Self::load_from(env.as_deref(), config_file)
+    }
+
+    fn load_from(env: Option<&str>, config_file: Option<&Path>) -> Result<Self, Error> {
+        if let Some(value) = env.filter(|value| !value.trim().is_empty()) {
+            return value
+                .parse()
+                .map_err(|e| Error::InvalidConfig(format!("{MIRROR_ENV}: {e}")));
+        }
+        if let Some(path) = config_file {
+            if let Some(set) = Self::from_config_file(path)? {
+                return Ok(set);
+            }
+        }
+        Ok(Self::default())
+    }
+
+    /// Reads mirrors from a TOML file, or returns `None` if it does not
+    /// exist.
+    ///
+    /// ```toml
+    /// # Index mirrors, tried in order.
+    /// mirrors = ["tuna", "official"]
+    /// # Mirrors to download image files from first (optional).
+    /// downloads = ["custom(https://cdn.example.com/lxc-images)"]
+    /// ```
+    ///
+    /// Either key also takes a comma-separated string. Unknown keys are
+    /// rejected.
+    pub fn from_config_file(path: &Path) -> Result<Option<Self>, Error> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let invalid = |e: String| Error::InvalidConfig(format!("{}: {e}", path.display()));
+        let config: MirrorConfig = toml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+        let index = config.mirrors.parse().map_err(|e| invalid(e.to_string()))?;
+        let downloads = config
+            .downloads
+            .parse()
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(Some(Self {
+            downloads,
+            ..Self::new(index)
+        }))
+    }
+}
+
+/// Contents of a mirror configuration file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MirrorConfig {
+    #[serde(default)]
+    mirrors: MirrorList,
+    #[serde(default)]
+    downloads: MirrorList,
+}
+
+/// A list of mirrors, as a TOML array or a comma-separated string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MirrorList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for MirrorList {
+    fn default() -> Self {
+        Self::Many(Vec::new())
+    }
+}
+
+impl MirrorList {
+    fn parse(&self) -> Result<Vec<Mirror>, Error> {
+        match self {
+            Self::One(list) => Mirror::parse_list(list),
+            Self::Many(items) => items.iter().map(|item| item.parse()).collect(),
         }
     }
 }
 
+/// Parses a comma-separated list of index mirrors, optionally followed by
+/// `(files from <mirrors>)` as written by `Display`, e.g.
+/// `"official (files from tuna)"`.
+impl FromStr for MirrorSet {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (index, downloads) = match s
+            .strip_suffix(')')
+            .and_then(|s| s.rsplit_once("(files from"))
+        {
+            Some((index, downloads)) => (index, Mirror::parse_list(downloads)?),
+            None => (s, Vec::new()),
+        };
+        let index = Mirror::parse_list(index)?;
+        if index.is_empty() {
+            return Err(Error::InvalidConfig("empty mirror list".to_owned()));
+        }
+        Ok(Self {
+            downloads,
+            ..Self::new(index)
+        })
+    }
+}
+
+impl Default for MirrorSet {
+    fn default() -> Self {
+        Self::new([Mirror::default()])
+    }
+}
+
+impl From<Mirror> for MirrorSet {
+    fn from(mirror: Mirror) -> Self {
+        Self::new([mirror])
+    }
+}
+
+impl From<&Mirror> for MirrorSet {
+    fn from(mirror: &Mirror) -> Self {
+        Self::new([mirror.clone()])
+    }
+}
+
+impl From<Vec<Mirror>> for MirrorSet {
+    fn from(mirrors: Vec<Mirror>) -> Self {
+        Self::new(mirrors)
+    }
+}
+
+impl From<&MirrorSet> for MirrorSet {
+    fn from(set: &MirrorSet) -> Self {
+        set.clone()
+    }
+}
+
+impl fmt::Display for MirrorSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |mirrors: &[Mirror]| {
+            mirrors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(f, "{}", join(&self.index))?;
+        if !self.downloads.is_empty() {
+            write!(f, " (files from {})", join(&self.downloads))?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -89,6 +444,10 @@ mod tests {
             Mirror::Official.streams_url(),
             "https://images.linuxcontainers.org/streams/v1/images.json"
         );
+        assert_eq!(
+            Mirror::Tuna.index_url(),
+            "https://mirrors.tuna.tsinghua.edu.cn/lxc-images/streams/v1/index.json"
+        );
     }
 
     #[test]
@@ -110,6 +469,113 @@ mod tests {
         );
     }
 
+    #[test]
+    fn mirror_set() {
+        let set = MirrorSet::from(&Mirror::Tuna);
+        assert_eq!(set.primary().base_url(), Mirror::Tuna.base_url());
+        assert!(set.downloads.is_empty());
+        assert_eq!(set.to_string(), "tuna");
+
+        let set =
+            MirrorSet::new([Mirror::Official, Mirror::Bfsu]).with_download_mirror(Mirror::Ustc);
+        assert_eq!(set.to_string(), "official, bfsu (files from ustc)");
+
+        let empty = MirrorSet::new([]);
+        assert_eq!(empty.index.len(), 1);
+        assert_eq!(empty.to_string(), "official");
+    }
+
+    #[test]
+    fn parse_round_trips_display() {
+        let mut mirrors = Mirror::presets().to_vec();
+        mirrors.push(Mirror::Auto);
+        mirrors.push(Mirror::Custom("https://images.example.com/lxc".to_owned()));
+        for mirror in mirrors {
+            let parsed: Mirror = mirror.to_string().parse().unwrap();
+            assert_eq!(parsed.to_string(), mirror.to_string());
+        }
+
+        let bare: Mirror = " https://r2.example.com/ ".parse().unwrap();
+        assert_eq!(bare.base_url(), "https://r2.example.com");
+        assert_eq!(" TUNA ".parse::<Mirror>().unwrap().to_string(), "tuna");
+        assert!("nearest".parse::<Mirror>().is_err());
+        assert!("custom( )".parse::<Mirror>().is_err());
+
+        let list = Mirror::parse_list("tuna, custom(https://a.example/x,y), ,official").unwrap();
+        let names: Vec<String> = list.iter().map(ToString::to_string).collect();
+        assert_eq!(names, ["tuna", "custom(https://a.example/x,y)", "official"]);
+    }
+
+    #[test]
+    fn local_mirrors() {
+        let mirror: Mirror = "/mnt/usb/lxc images/".parse().unwrap();
+        assert_eq!(mirror.base_url(), "file:///mnt/usb/lxc%20images");
+        assert_eq!(
+            mirror.local_path().unwrap(),
+            Path::new("/mnt/usb/lxc images")
+        );
+        let parsed: Mirror = mirror.to_string().parse().unwrap();
+        assert_eq!(parsed.base_url(), mirror.base_url());
+
+        let url: Mirror = "file:///srv/mirror".parse().unwrap();
+        assert_eq!(url.local_path().unwrap(), Path::new("/srv/mirror"));
+        let relative: Mirror = "./mirror".parse().unwrap();
+        assert_eq!(
+            relative.local_path().unwrap(),
+            std::env::current_dir().unwrap().join("mirror")
+        );
+
+        assert!(Mirror::Official.local_path().is_none());
+        assert!("mirror".parse::<Mirror>().is_err());
+    }
+
+    #[test]
+    fn mirror_set_parse_round_trips_display() {
+        let set = MirrorSet::new([Mirror::Official, Mirror::Bfsu])
+            .with_download_mirror(Mirror::Custom("https://cdn.example.com".to_owned()));
+        let parsed: MirrorSet = set.to_string().parse().unwrap();
+        assert_eq!(parsed.to_string(), set.to_string());
+        assert_eq!(parsed.downloads.len(), 1);
+
+        let parsed: MirrorSet = "ustc,tuna".parse().unwrap();
+        assert_eq!(parsed.to_string(), "ustc, tuna");
+        assert!(parsed.downloads.is_empty());
+        assert!(" , ".parse::<MirrorSet>().is_err());
+    }
+
+    #[test]
+    fn load_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("mirrors.toml");
+
+        // Nothing configured.
+        let set = MirrorSet::load_from(None, Some(&config)).unwrap();
+        assert_eq!(set.to_string(), "official");
+
+        std::fs::write(
+            &config,
+            "mirrors = [\"tuna\", \"official\"]\ndownloads = \"custom(https://cdn.example.com)\"\n",
+        )
+        .unwrap();
+        let set = MirrorSet::load_from(None, Some(&config)).unwrap();
+        assert_eq!(
+            set.to_string(),
+            "tuna, official (files from custom(https://cdn.example.com))"
+        );
+
+        // The environment wins over the file; an empty value is unset.
+        let set = MirrorSet::load_from(Some("ustc"), Some(&config)).unwrap();
+        assert_eq!(set.to_string(), "ustc");
+        let set = MirrorSet::load_from(Some(" "), Some(&config)).unwrap();
+        assert_eq!(set.primary().to_string(), "tuna");
+
+        let err = MirrorSet::load_from(Some("nearest"), None).unwrap_err();
+        assert!(err.to_string().contains("ARCBOX_MIRROR"));
+        std::fs::write(&config, "mirror = \"tuna\"\n").unwrap();
+        let err = MirrorSet::load_from(None, Some(&config)).unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)));
+    }
+
     #[test]
     fn custom_trailing_slash() {
         let m = Mirror::Custom("https://example.com/".to_owned());
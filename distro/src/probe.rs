@@ -0,0 +1,336 @@
+//! Fastest-mirror selection.
+//!
+//! [`MirrorProbe`] measures each candidate mirror's time to first byte on
+//! its product stream and, optionally, the throughput of a short ranged read
+//! of a known file, then ranks the mirrors. [`Mirror::Auto`] uses the
+//! winner. A ranking can be kept in a file for a while so that every run
+//! does not probe again.
+
+use std::cmp::Reverse;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::http::{HttpClient, check_status};
+use crate::mirror::Mirror;
+
+/// Probes mirrors and ranks them by latency and throughput.
+#[derive(Debug, Clone)]
+pub struct MirrorProbe {
+    /// Mirrors to compare.
+    pub candidates: Vec<Mirror>,
+    /// Limit for each probe request, including the sample read. A mirror
+    /// that does not answer in time is ranked last.
+    pub timeout: Duration,
+    /// Path of a file relative to the mirror root (e.g. an image path from
+    /// the index) to read a sample of. `None` ranks by time to first byte
+    /// alone.
+    pub sample_path: Option<String>,
+    /// Bytes to read from `sample_path`, with a `Range` request.
+    pub sample_bytes: u64,
+    /// File that keeps the last ranking. `None` probes on every call.
+    pub cache_file: Option<PathBuf>,
+    /// Age below which a cached ranking is used without probing.
+    pub ttl: Duration,
+}
+
+/// Measurements of one mirror from [`MirrorProbe::probe`].
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    /// The mirror probed.
+    pub mirror: Mirror,
+    /// Time until the response headers of the product stream arrived.
+    pub ttfb: Option<Duration>,
+    /// Throughput of the sample read, if one was configured and succeeded.
+    pub bytes_per_sec: Option<u64>,
+    /// Why the mirror could not be probed.
+    pub error: Option<String>,
+}
+
+/// Ranking stored in [`MirrorProbe::cache_file`].
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedRanking {
+    /// Unix time of the probe.
+    probed_at: u64,
+    /// Base URLs of the candidates that were probed.
+    candidates: Vec<String>,
+    /// Candidates, fastest first.
+    ranking: Vec<Mirror>,
+}
+
+impl Default for MirrorProbe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MirrorProbe {
+    /// Creates a probe of the [preset](Mirror::presets) mirrors with a
+    /// 5-second timeout, no sample read, and no cache file. Cached rankings
+    /// are used for a day.
+    pub fn new() -> Self {
+        Self {
+            candidates: Mirror::presets().to_vec(),
+            timeout: Duration::from_secs(5),
+            sample_path: None,
+            sample_bytes: 1024 * 1024,
+            cache_file: None,
+            ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+
+    /// Probes every candidate concurrently and returns the results, best
+    /// first.
+    ///
+    /// Mirrors that answered rank before those that failed. With a sample
+    /// read, higher throughput ranks first; otherwise, and between equal
+    /// throughputs, a shorter time to first byte does.
+    pub async fn probe(&self, client: &HttpClient) -> Vec<ProbeResult> {
+        let probes = self
+            .candidates
+            .iter()
+            .map(|mirror| self.probe_one(client, mirror));
+        let mut results = futures::future::join_all(probes).await;
+        rank(&mut results);
+        for result in &results {
+            debug!(
+                mirror = %result.mirror,
+                ttfb = ?result.ttfb,
+                bytes_per_sec = ?result.bytes_per_sec,
+                error = ?result.error,
+                "probed mirror"
+            );
+        }
+        results
+    }
+
+    /// Returns the candidates, best first.
+    ///
+    /// Uses the ranking in [`cache_file`](Self::cache_file) while it is
+    /// younger than [`ttl`](Self::ttl) and was made for the same candidates.
+    /// Otherwise probes and stores the new ranking. A cache file that cannot
+    /// be written is ignored.
+    pub async fn ranked(&self, client: &HttpClient) -> Vec<Mirror> {
+        if let Some(ranking) = self.load_cached().await {
+            debug!("using cached mirror ranking");
+            return ranking;
+        }
+        let ranking: Vec<Mirror> = self
+            .probe(client)
+            .await
+            .into_iter()
+            .map(|result| result.mirror)
+            .collect();
+        info!(
+            fastest = %ranking.first().map_or(String::new(), ToString::to_string),
+            "ranked mirrors"
+        );
+        self.store(&ranking).await;
+        ranking
+    }
+
+    /// Returns the best candidate (see [`ranked`](Self::ranked)), or
+    /// [`Mirror::Official`] when there are no candidates.
+    pub async fn select(&self, client: &HttpClient) -> Mirror {
+        self.ranked(client)
+            .await
+            .into_iter()
+            .next()
+            .unwrap_or(Mirror::Official)
+    }
+
+    async fn probe_one(&self, client: &HttpClient, mirror: &Mirror) -> ProbeResult {
+        let mut result = ProbeResult {
+            mirror: mirror.clone(),
+            ttfb: None,
+            bytes_per_sec: None,
+            error: None,
+        };
+        let measured = tokio::time::timeout(self.timeout, async {
+            let start = Instant::now();
+            check_status(client.get(&mirror.streams_url()).send().await?)?;
+            result.ttfb = Some(start.elapsed());
+
+            if let Some(path) = &self.sample_path {
+                result.bytes_per_sec = Some(self.sample(client, &mirror.image_url(path)).await?);
+            }
+            Ok::<_, crate::Error>(())
+        })
+        .await;
+        match measured {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => result.error = Some(e.to_string()),
+            Err(_) => result.error = Some(format!("no answer within {:?}", self.timeout)),
+        }
+        result
+    }
+
+    /// Reads up to `sample_bytes` of `url` and returns the throughput.
+    async fn sample(&self, client: &HttpClient, url: &str) -> Result<u64, crate::Error> {
+        let range = format!("bytes=0-{}", self.sample_bytes.saturating_sub(1));
+        let mut response = check_status(
+            client
+                .get(url)
+                .header(reqwest::header::RANGE, range)
+                .send()
+                .await?,
+        )?;
+        let start = Instant::now();
+        let mut read = 0u64;
+        while read < self.sample_bytes {
+            match response.chunk().await? {
+                Some(chunk) => read += chunk.len() as u64,
+                None => break,
+            }
+        }
+        let secs = start.elapsed().as_secs_f64().max(1e-3);
+        Ok((read as f64 / secs) as u64)
+    }
+
+    async fn load_cached(&self) -> Option<Vec<Mirror>> {
+        let path = self.cache_file.as_ref()?;
+        let cached: CachedRanking =
+            serde_json::from_slice(&tokio::fs::read(path).await.ok()?).ok()?;
+        let age = unix_now().saturating_sub(cached.probed_at);
+        (age < self.ttl.as_secs() && cached.candidates == self.candidate_urls())
+            .then_some(cached.ranking)
+    }
+
+    async fn store(&self, ranking: &[Mirror]) {
+        let Some(path) = &self.cache_file else {
+            return;
+        };
+        let cached = CachedRanking {
+            probed_at: unix_now(),
+            candidates: self.candidate_urls(),
+            ranking: ranking.to_vec(),
+        };
+        let Ok(body) = serde_json::to_vec_pretty(&cached) else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = tokio::fs::create_dir_all(parent).await;
+        }
+        if let Err(e) = tokio::fs::write(path, body).await {
+            debug!(path = %path.display(), error = %e, "cannot store mirror ranking");
+        }
+    }
+
+    fn candidate_urls(&self) -> Vec<String> {
+        self.candidates
+            .iter()
+            .map(|mirror| mirror.base_url().to_owned())
+            .collect()
+    }
+}
+
+/// Sorts results best first.
+fn rank(results: &mut [ProbeResult]) {
+    results.sort_by_key(|result| {
+        (
+            result.error.is_some(),
+            Reverse(result.bytes_per_sec.unwrap_or(0)),
+            result.ttfb.unwrap_or(Duration::MAX),
+        )
+    });
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(mirror: Mirror, ttfb_ms: Option<u64>, bytes_per_sec: Option<u64>) -> ProbeResult {
+        ProbeResult {
+            mirror,
+            ttfb: ttfb_ms.map(Duration::from_millis),
+            bytes_per_sec,
+            error: ttfb_ms.is_none().then(|| "unreachable".to_owned()),
+        }
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    fn names(results: &[ProbeResult]) -> Vec<String> {
+        results.iter().map(|r| r.mirror.to_string()).collect()
+    }
+
+    #[test]
+    fn ranks_by_ttfb_then_throughput() {
+        let mut results = vec![
+            result(Mirror::Official, Some(300), None),
+            result(Mirror::Tuna, None, None),
+            result(Mirror::Ustc, Some(40), None),
+            result(Mirror::Bfsu, Some(90), None),
+        ];
+        rank(&mut results);
+        assert_eq!(names(&results), ["ustc", "bfsu", "official", "tuna"]);
+
+        // Throughput outranks latency.
+        results[2].bytes_per_sec = Some(5_000_000);
+        results[0].bytes_per_sec = Some(1_000_000);
+        rank(&mut results);
+        assert_eq!(names(&results), ["official", "ustc", "bfsu", "tuna"]);
+    }
+
+    #[test]
+    fn unreachable_mirrors_rank_last() {
+        let probe = MirrorProbe {
+            candidates: vec![
+                Mirror::Custom("http://127.0.0.1:9".to_owned()),
+                Mirror::Custom("http://127.0.0.1:9/other".to_owned()),
+            ],
+            timeout: Duration::from_secs(2),
+            ..MirrorProbe::new()
+        };
+        let rt = runtime();
+        let results = rt.block_on(probe.probe(&HttpClient::default()));
+        assert_eq!(results.len(), 2);
+        assert!(
+            results
+                .iter()
+                .all(|r| r.error.is_some() && r.ttfb.is_none())
+        );
+    }
+
+    #[test]
+    fn cached_ranking_is_reused_until_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("mirror-probe.json");
+        let probe = MirrorProbe {
+            candidates: vec![Mirror::Official, Mirror::Tuna],
+            cache_file: Some(cache_file.clone()),
+            ..MirrorProbe::new()
+        };
+        let rt = runtime();
+        rt.block_on(probe.store(&[Mirror::Tuna, Mirror::Official]));
+
+        // No network: the cached ranking is returned as-is.
+        let ranking = rt.block_on(probe.ranked(&HttpClient::default()));
+        assert_eq!(ranking[0].to_string(), "tuna");
+
+        // Other candidates or an expired ranking are not reused.
+        let other = MirrorProbe {
+            candidates: vec![Mirror::Official],
+            ..probe.clone()
+        };
+        assert!(rt.block_on(other.load_cached()).is_none());
+        let expired = MirrorProbe {
+            ttl: Duration::ZERO,
+            ..probe
+        };
+        assert!(rt.block_on(expired.load_cached()).is_none());
+    }
+}
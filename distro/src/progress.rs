@@ -0,0 +1,173 @@
+//! Structured progress reporting.
+//!
+//! Every long-running operation takes an `FnMut(ProgressEvent)` callback.
+//! Events describe the phase the operation is in, so a single callback can
+//! drive one progress display from the index fetch through to extraction.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// A progress update from a download, cache, or extraction pipeline.
+///
+/// Serializes with an `event` tag (e.g. `{"event":"downloading",...}`) for
+/// structured log output.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// A Simplestreams index is being fetched.
+    FetchingIndex {
+        /// URL of the index.
+        url: String,
+    },
+    /// The image was found in the index.
+    Resolved {
+        /// Download URL of the image.
+        url: String,
+        /// Size listed in the index, in bytes.
+        size: u64,
+    },
+    /// Bytes were received.
+    Downloading {
+        /// Bytes on disk so far, including any resumed from a partial file.
+        downloaded: u64,
+        /// Expected total size, if known from the server or the index.
+        total: Option<u64>,
+        /// Average transfer rate of the current attempt.
+        bytes_per_sec: u64,
+        /// Estimated time remaining, if the total is known.
+        eta: Option<Duration>,
+    },
+    /// The resolved release is past its end of life and no longer receives
+    /// updates.
+    EndOfLife {
+        /// Release title from the index (e.g. `"Ubuntu 20.04 LTS"`).
+        release: String,
+        /// End-of-life date (`YYYY-MM-DD`), if listed.
+        date: Option<String>,
+    },
+    /// The downloaded file is being checked against its expected checksum.
+    Verifying,
+    /// A downloaded delta is being applied to update a local copy from build
+    /// `from` to build `to`.
+    ApplyingDelta {
+        /// Build serial of the local copy.
+        from: String,
+        /// Build serial the delta produces.
+        to: String,
+    },
+    /// Another caller or process is already fetching this cache entry; the
+    /// operation waits for it to finish.
+    WaitingForLock {
+        /// Cache entry directory being waited on.
+        path: PathBuf,
+    },
+    /// A matching archive was found in the cache; nothing was downloaded.
+    CacheHit {
+        /// Path of the cached archive.
+        path: PathBuf,
+    },
+    /// The archive is being recorded in the cache.
+    Storing {
+        /// Path of the archive inside the cache.
+        path: PathBuf,
+    },
+    /// Archive entries are being extracted.
+    Extracting {
+        /// Entries extracted so far.
+        entries: u64,
+        /// Uncompressed bytes extracted so far.
+        bytes: u64,
+    },
+}
+
+/// Turns byte counts of one transfer into [`ProgressEvent::Downloading`]
+/// events with rate and ETA.
+#[derive(Debug)]
+pub(crate) struct TransferProgress {
+    started: Instant,
+    /// Bytes already present when the transfer started (resumed data).
+    initial: u64,
+    total: Option<u64>,
+}
+
+impl TransferProgress {
+    pub(crate) fn new(initial: u64, total: Option<u64>) -> Self {
+        Self {
+            started: Instant::now(),
+            initial,
+            total,
+        }
+    }
+
+    pub(crate) fn event(&self, downloaded: u64) -> ProgressEvent {
+        self.event_after(downloaded, self.started.elapsed())
+    }
+
+    fn event_after(&self, downloaded: u64, elapsed: Duration) -> ProgressEvent {
+        let transferred = downloaded.saturating_sub(self.initial);
+        let secs = elapsed.as_secs_f64();
+        let bytes_per_sec = if secs > 0.0 {
+            (transferred as f64 / secs) as u64
+        } else {
+            0
+        };
+        let eta = match self.total {
+            Some(total) if bytes_per_sec > 0 => Some(Duration::from_secs_f64(
+                total.saturating_sub(downloaded) as f64 / bytes_per_sec as f64,
+            )),
+            _ => None,
+        };
+        ProgressEvent::Downloading {
+            downloaded,
+            total: self.total,
+            bytes_per_sec,
+            eta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_and_eta_exclude_resumed_bytes() {
+        let progress = TransferProgress::new(1000, Some(5000));
+        let event = progress.event_after(3000, Duration::from_secs(2));
+        assert_eq!(
+            event,
+            ProgressEvent::Downloading {
+                downloaded: 3000,
+                total: Some(5000),
+                bytes_per_sec: 1000,
+                eta: Some(Duration::from_secs(2)),
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_total_has_no_eta() {
+        let progress = TransferProgress::new(0, None);
+        let ProgressEvent::Downloading { eta, .. } =
+            progress.event_after(100, Duration::from_secs(1))
+        else {
+            panic!("expected a downloading event");
+        };
+        assert_eq!(eta, None);
+    }
+
+    #[test]
+    fn serializes_with_event_tag() {
+        let json = serde_json::to_value(ProgressEvent::Extracting {
+            entries: 3,
+            bytes: 42,
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"event": "extracting", "entries": 3, "bytes": 42})
+        );
+    }
+}
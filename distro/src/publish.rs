@@ -0,0 +1,482 @@
+//! Simplestreams mirror publishing.
+//!
+//! [`Publisher`] turns local image files into a mirror tree that
+//! [`LxcClient`](crate::lxc::LxcClient) reads exactly like the upstream one,
+//! e.g. to serve internal images through [`Mirror::Custom`](crate::Mirror):
+//!
+//! ```text
+//! {root}/streams/v1/index.json
+//! {root}/streams/v1/images.json
+//! {root}/images/{name}/{release}/{arch}/{variant}/{serial}/rootfs.tar.xz
+//! ```
+//!
+//! Publishing into an existing tree adds to the products already listed.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::download::hash_file;
+use crate::lxc::{Item, Product, ProductVersion, SimplestreamsIndex, StreamEntry, StreamsIndex};
+use crate::{Arch, Distro, Error, ItemKind, Variant, Version};
+
+/// Content id of the product stream written by default.
+const DEFAULT_CONTENT_ID: &str = "images";
+
+/// A local image file to publish, with the product and build it belongs to.
+#[derive(Debug, Clone)]
+pub struct PublishImage {
+    /// Path of the file to publish (e.g. a `rootfs.tar.xz` built in CI).
+    pub path: PathBuf,
+    /// Distribution.
+    pub distro: Distro,
+    /// User-facing version (mapped to the LXC release, e.g. `"24.04"` →
+    /// `noble`).
+    pub version: Version,
+    /// Architecture.
+    pub arch: Arch,
+    /// Image variant.
+    pub variant: Variant,
+    /// Build serial (e.g. `"20260218_07:42"`). Serials sort chronologically,
+    /// so the newest build must have the greatest one.
+    pub serial: String,
+    /// Kind of file.
+    pub kind: ItemKind,
+}
+
+/// Writes local images into a Simplestreams mirror tree.
+#[derive(Debug, Clone)]
+pub struct Publisher {
+    root: PathBuf,
+    content_id: String,
+}
+
+impl Publisher {
+    /// Creates a publisher for the mirror tree rooted at `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            content_id: DEFAULT_CONTENT_ID.to_owned(),
+        }
+    }
+
+    /// Sets the content id of the product stream (default `"images"`).
+    pub fn with_content_id(mut self, content_id: impl Into<String>) -> Self {
+        self.content_id = content_id.into();
+        self
+    }
+
+    /// Returns the root directory of the mirror tree.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Copies `images` into the tree and rewrites `index.json` and the
+    /// product stream, returning the stream as written.
+    ///
+    /// Each file is hashed after copying, and replaces any file of the same
+    /// kind already published for its build. When a build has both LXD
+    /// metadata and another item, the `combined_*_sha256` fingerprints are
+    /// filled in. The stream is written before `index.json`, each through a
+    /// temporary file, so readers never see a half-written document.
+    pub fn publish(&self, images: &[PublishImage]) -> Result<SimplestreamsIndex, Error> {
+        let stream_path = self.stream_path();
+        let mut index = match std::fs::read(self.root.join(&stream_path)) {
+            Ok(body) => serde_json::from_slice(&body)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => SimplestreamsIndex {
+                content_id: self.content_id.clone(),
+                datatype: "image-downloads".to_owned(),
+                format: "products:1.0".to_owned(),
+                products: HashMap::new(),
+                source: None,
+            },
+            Err(e) => return Err(e.into()),
+        };
+        index.content_id.clone_from(&self.content_id);
+
+        let mut touched = Vec::new();
+        for image in images {
+            let (key, serial) = self.add_image(&mut index, image)?;
+            touched.push((key, serial));
+        }
+        for (key, serial) in &touched {
+            if let Some(build) = index
+                .products
+                .get_mut(key)
+                .and_then(|product| product.versions.get_mut(serial))
+            {
+                self.update_combined(build)?;
+            }
+        }
+
+        write_index(&self.root, &index)?;
+        info!(
+            root = %self.root.display(),
+            images = images.len(),
+            products = index.products.len(),
+            "published mirror"
+        );
+        Ok(index)
+    }
+
+    /// Path of the product stream relative to the root.
+    fn stream_path(&self) -> String {
+        stream_path(&self.content_id)
+    }
+
+    /// Copies one image into the tree and records it in `index`, returning
+    /// its product key and serial.
+    fn add_image(
+        &self,
+        index: &mut SimplestreamsIndex,
+        image: &PublishImage,
+    ) -> Result<(String, String), Error> {
+        let name = image.distro.lxc_name();
+        let release = image.distro.lxc_release(&image.version);
+        let arch = image.arch.lxc_name();
+        let variant = image.variant.as_str();
+        // Product key parts are `:`-separated; serials contain `:` themselves.
+        for (what, value, reserved) in [
+            ("version", release.as_str(), &['/', ':', ','][..]),
+            ("variant", variant, &['/', ':', ','][..]),
+            ("serial", image.serial.as_str(), &['/', '\\'][..]),
+        ] {
+            if matches!(value, "" | "." | "..") || value.contains(reserved) {
+                return Err(Error::InvalidConfig(format!(
+                    "invalid {what} for publishing: {value:?}"
+                )));
+            }
+        }
+
+        let (item_key, filename) = item_names(image.kind);
+        let path = format!(
+            "images/{name}/{release}/{arch}/{variant}/{}/{filename}",
+            image.serial
+        );
+        let dest = self.root.join(&path);
+        copy_atomic(&image.path, &dest)?;
+        let sha256 = hash_file::<Sha256>(&dest)?;
+        let size = std::fs::metadata(&dest)?.len();
+        debug!(path, sha256, size, "published file");
+
+        let key = format!("{name}:{release}:{arch}:{variant}");
+        let product = index
+            .products
+            .entry(key.clone())
+            .or_insert_with(|| Product {
+                arch: arch.to_owned(),
+                os: os_name(name),
+                release: release.clone(),
+                release_title: image.version.as_str().to_owned(),
+                release_codename: None,
+                variant: variant.to_owned(),
+                listed_aliases: aliases(name, &release, image.version.as_str(), &image.variant),
+                requirements: HashMap::new(),
+                supported: None,
+                supported_eol: None,
+                versions: HashMap::new(),
+            });
+        let build = product
+            .versions
+            .entry(image.serial.clone())
+            .or_insert_with(|| ProductVersion {
+                pubname: None,
+                items: HashMap::new(),
+            });
+        build
+            .items
+            .retain(|_, item| item.ftype != image.kind.ftype());
+        build.items.insert(
+            item_key.to_owned(),
+            Item {
+                ftype: image.kind.ftype().to_owned(),
+                sha256,
+                size,
+                path,
+                combined_sha256: None,
+                combined_rootxz_sha256: None,
+                combined_squashfs_sha256: None,
+                combined_disk_kvm_img_sha256: None,
+                delta_base: None,
+            },
+        );
+        Ok((key, image.serial.clone()))
+    }
+
+    /// Recomputes the `combined_*_sha256` fingerprints on the LXD metadata
+    /// item of `build`.
+    fn update_combined(&self, build: &mut ProductVersion) -> Result<(), Error> {
+        let Some(metadata) = build.item(ItemKind::LxdMetadata) else {
+            return Ok(());
+        };
+        let metadata_path = self.root.join(&metadata.path);
+        let mut combined = HashMap::new();
+        for kind in [ItemKind::Rootfs, ItemKind::Squashfs, ItemKind::DiskKvm] {
+            if let Some(item) = build.item(kind) {
+                let sha256 = hash_concat(&metadata_path, &self.root.join(&item.path))?;
+                combined.insert(kind, sha256);
+            }
+        }
+
+        let Some(metadata) = build
+            .items
+            .values_mut()
+            .find(|item| item.ftype == ItemKind::LxdMetadata.ftype())
+        else {
+            return Ok(());
+        };
+        metadata.combined_sha256 = combined.get(&ItemKind::Rootfs).cloned();
+        metadata.combined_rootxz_sha256 = combined.remove(&ItemKind::Rootfs);
+        metadata.combined_squashfs_sha256 = combined.remove(&ItemKind::Squashfs);
+        metadata.combined_disk_kvm_img_sha256 = combined.remove(&ItemKind::DiskKvm);
+        Ok(())
+    }
+}
+
+/// Returns the item key and file name LXC uses for `kind`.
+fn item_names(kind: ItemKind) -> (&'static str, &'static str) {
+    match kind {
+        ItemKind::Rootfs => ("root.tar.xz", "rootfs.tar.xz"),
+        ItemKind::LxdMetadata => ("lxd.tar.xz", "lxd.tar.xz"),
+        ItemKind::Squashfs => ("root.squashfs", "rootfs.squashfs"),
+        ItemKind::DiskKvm => ("disk.qcow2", "disk.qcow2"),
+    }
+}
+
+/// Returns the `os` field LXC uses for an image name (e.g. `"Ubuntu"`).
+fn os_name(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// Returns the aliases LXC publishes for a product: by release, and by
+/// version when that differs (e.g. `ubuntu/noble` and `ubuntu/24.04`).
+fn aliases(name: &str, release: &str, version: &str, variant: &Variant) -> Vec<String> {
+    let mut names = vec![release];
+    if version != release {
+        names.push(version);
+    }
+    let mut aliases = Vec::new();
+    for release in names {
+        aliases.push(format!("{name}/{release}/{variant}"));
+        if *variant == Variant::default() {
+            aliases.push(format!("{name}/{release}"));
+        }
+    }
+    aliases
+}
+
+/// Copies `source` to `dest` through a temporary file, creating parent
+/// directories. Nothing is copied when both name the same file.
+fn copy_atomic(source: &Path, dest: &Path) -> Result<(), Error> {
+    if dest.exists() && std::fs::canonicalize(source)? == std::fs::canonicalize(dest)? {
+        return Ok(());
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = tmp_path(dest);
+    std::fs::copy(source, &tmp)?;
+    std::fs::rename(&tmp, dest)?;
+    Ok(())
+}
+
+/// Path of the product stream with `content_id`, relative to the root.
+fn stream_path(content_id: &str) -> String {
+    format!("streams/v1/{content_id}.json")
+}
+
+/// Writes the product stream `index` and an `index.json` listing it under
+/// `root`, the stream first.
+pub(crate) fn write_index(root: &Path, index: &SimplestreamsIndex) -> Result<(), Error> {
+    let stream_path = stream_path(&index.content_id);
+    let mut products: Vec<String> = index.products.keys().cloned().collect();
+    products.sort_unstable();
+    let streams = StreamsIndex {
+        format: "index:1.0".to_owned(),
+        index: HashMap::from([(
+            index.content_id.clone(),
+            StreamEntry {
+                path: stream_path.clone(),
+                datatype: index.datatype.clone(),
+                format: index.format.clone(),
+                products,
+            },
+        )]),
+    };
+    write_json(&root.join(stream_path), index)?;
+    write_json(&root.join("streams/v1/index.json"), &streams)
+}
+
+/// Serializes `value` with sorted keys and writes it atomically.
+fn write_json<T: serde::Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    // `serde_json::Value` keeps object keys sorted, so the output is stable.
+    let body = serde_json::to_vec_pretty(&serde_json::to_value(value)?)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = tmp_path(path);
+    std::fs::write(&tmp, body)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Returns `{path}.tmp`.
+pub(crate) fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+/// Hashes the contents of `first` followed by `second`.
+fn hash_concat(first: &Path, second: &Path) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 8192];
+    for path in [first, second] {
+        let mut file = std::fs::File::open(path)?;
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mirror;
+    use crate::lxc::LxcClient;
+
+    fn image(dir: &Path, name: &str, data: &[u8], serial: &str, kind: ItemKind) -> PublishImage {
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        PublishImage {
+            path,
+            distro: Distro::Ubuntu,
+            version: Version::new("24.04"),
+            arch: Arch::X86_64,
+            variant: Variant::default(),
+            serial: serial.to_owned(),
+            kind,
+        }
+    }
+
+    fn read_index(root: &Path) -> SimplestreamsIndex {
+        serde_json::from_slice(&std::fs::read(root.join("streams/v1/images.json")).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn publishes_resolvable_tree() {
+        let src = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let publisher = Publisher::new(root.path());
+        publisher
+            .publish(&[image(
+                src.path(),
+                "a.tar.xz",
+                b"rootfs",
+                "20260218_07:42",
+                ItemKind::Rootfs,
+            )])
+            .unwrap();
+
+        let streams: StreamsIndex = serde_json::from_slice(
+            &std::fs::read(root.path().join("streams/v1/index.json")).unwrap(),
+        )
+        .unwrap();
+        let stream = &streams.index["images"];
+        assert_eq!(stream.path, "streams/v1/images.json");
+        assert_eq!(stream.products, ["ubuntu:noble:amd64:default"]);
+
+        let index = read_index(root.path());
+        let product = &index.products["ubuntu:noble:amd64:default"];
+        assert_eq!(product.os, "Ubuntu");
+        assert_eq!(product.release_title, "24.04");
+        assert!(product.listed_aliases.contains(&"ubuntu/24.04".to_owned()));
+
+        let client = LxcClient::new(Mirror::Custom("https://images.example.com".to_owned()));
+        let resolved = client
+            .resolve_from_index(
+                &index,
+                Distro::Ubuntu,
+                &Version::new("24.04"),
+                Arch::X86_64,
+                &Variant::default(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(resolved.serial, "20260218_07:42");
+        assert_eq!(resolved.sha256, hex::encode(Sha256::digest(b"rootfs")));
+        assert_eq!(resolved.size, 6);
+        assert_eq!(
+            resolved.url,
+            "https://images.example.com/images/ubuntu/noble/amd64/default/20260218_07:42/rootfs.tar.xz"
+        );
+        let published = root
+            .path()
+            .join("images/ubuntu/noble/amd64/default/20260218_07:42/rootfs.tar.xz");
+        assert_eq!(std::fs::read(published).unwrap(), b"rootfs");
+    }
+
+    #[test]
+    fn merges_builds_and_fills_combined_hashes() {
+        let src = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let publisher = Publisher::new(root.path());
+        publisher
+            .publish(&[image(src.path(), "a", b"old", "01", ItemKind::Rootfs)])
+            .unwrap();
+        let index = publisher
+            .publish(&[
+                image(src.path(), "b", b"new", "02", ItemKind::Rootfs),
+                image(src.path(), "c", b"meta", "02", ItemKind::LxdMetadata),
+            ])
+            .unwrap();
+
+        let product = &index.products["ubuntu:noble:amd64:default"];
+        assert_eq!(product.versions.len(), 2);
+        let metadata = product.versions["02"].item(ItemKind::LxdMetadata).unwrap();
+        let combined = hex::encode(Sha256::digest(b"metanew"));
+        assert_eq!(
+            metadata.combined_sha256_for(ItemKind::Rootfs),
+            Some(combined.as_str())
+        );
+        assert_eq!(metadata.combined_sha256.as_deref(), Some(combined.as_str()));
+
+        // Republishing a build replaces its file.
+        let index = publisher
+            .publish(&[image(src.path(), "d", b"fixed", "02", ItemKind::Rootfs)])
+            .unwrap();
+        let build = &index.products["ubuntu:noble:amd64:default"].versions["02"];
+        assert_eq!(build.items.len(), 2);
+        assert_eq!(build.rootfs().unwrap().size, 5);
+        let metadata = build.item(ItemKind::LxdMetadata).unwrap();
+        assert_eq!(
+            metadata.combined_sha256.as_deref(),
+            Some(hex::encode(Sha256::digest(b"metafixed")).as_str())
+        );
+        assert_eq!(read_index(root.path()).products.len(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_serial() {
+        let src = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let err = Publisher::new(root.path())
+            .publish(&[image(src.path(), "a", b"x", "../01", ItemKind::Rootfs)])
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)));
+        assert!(!root.path().join("streams").exists());
+    }
+}
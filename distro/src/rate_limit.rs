@@ -0,0 +1,144 @@
+//! Download bandwidth limiting.
+//!
+//! A [`RateLimit`] paces the bytes received by every download it is attached
+//! to. Clones share one budget, so a limit passed to several concurrent
+//! downloads (or to the connections of one parallel download) caps their
+//! combined throughput. The limit can be changed at any time and takes effect
+//! with the next received chunk.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shared, adjustable bandwidth limit in bytes per second.
+///
+/// The default is unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimit {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Limit in bytes per second; `0` means unlimited.
+    bytes_per_sec: AtomicU64,
+    /// Time at which the bytes received so far have been paid for.
+    paid_until: Mutex<Option<Instant>>,
+}
+
+/// Idle time that may be spent as a burst once transfers resume.
+const MAX_BURST: Duration = Duration::from_millis(250);
+
+impl RateLimit {
+    /// Creates a limit of `bytes_per_sec`. `0` means unlimited.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let limit = Self::default();
+        limit.set_bytes_per_sec(Some(bytes_per_sec));
+        limit
+    }
+
+    /// Creates a limit that does not throttle (until changed).
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current limit, or `None` if unlimited.
+    pub fn bytes_per_sec(&self) -> Option<u64> {
+        match self.inner.bytes_per_sec.load(Ordering::Relaxed) {
+            0 => None,
+            rate => Some(rate),
+        }
+    }
+
+    /// Changes the limit for every download sharing this `RateLimit`,
+    /// including ones already running. `None` or `Some(0)` lifts it.
+    pub fn set_bytes_per_sec(&self, bytes_per_sec: Option<u64>) {
+        self.inner
+            .bytes_per_sec
+            .store(bytes_per_sec.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Waits long enough to keep the overall rate within the limit after
+    /// `bytes` more bytes have been received.
+    pub(crate) async fn throttle(&self, bytes: u64) {
+        if let Some(delay) = self.reserve(bytes, Instant::now()) {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Accounts for `bytes` received at `now` and returns how long the caller
+    /// must wait before reading more.
+    fn reserve(&self, bytes: u64, now: Instant) -> Option<Duration> {
+        let mut paid_until = self
+            .inner
+            .paid_until
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let Some(rate) = self.bytes_per_sec() else {
+            *paid_until = None;
+            return None;
+        };
+
+        let cost = Duration::from_secs_f64(bytes as f64 / rate as f64);
+        // Unused budget from an idle period is capped so a paused transfer
+        // cannot later burst far above the limit.
+        let start = match *paid_until {
+            Some(t) => t.max(now.checked_sub(MAX_BURST).unwrap_or(now)),
+            None => now,
+        };
+        let until = start + cost;
+        *paid_until = Some(until);
+        until.checked_duration_since(now).filter(|d| !d.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_never_waits() {
+        let limit = RateLimit::unlimited();
+        assert_eq!(limit.bytes_per_sec(), None);
+        assert_eq!(limit.reserve(u64::MAX, Instant::now()), None);
+        assert_eq!(RateLimit::new(0).bytes_per_sec(), None);
+    }
+
+    #[test]
+    fn paces_to_the_configured_rate() {
+        let limit = RateLimit::new(1000);
+        let now = Instant::now();
+        assert_eq!(limit.reserve(500, now), Some(Duration::from_millis(500)));
+        // The next chunk queues behind the first one.
+        assert_eq!(limit.reserve(500, now), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn idle_credit_is_capped() {
+        let limit = RateLimit::new(1000);
+        let start = Instant::now();
+        limit.reserve(100, start);
+        let later = start + Duration::from_secs(60);
+        // Only MAX_BURST of credit is available after a long pause.
+        assert_eq!(limit.reserve(250, later), None);
+        assert_eq!(limit.reserve(100, later), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn clones_share_and_adjust_the_limit() {
+        let limit = RateLimit::new(1000);
+        let background = limit.clone();
+        let now = Instant::now();
+        assert!(background.reserve(1000, now).is_some());
+
+        limit.set_bytes_per_sec(None);
+        assert_eq!(background.bytes_per_sec(), None);
+        assert_eq!(background.reserve(1_000_000, now), None);
+
+        limit.set_bytes_per_sec(Some(2000));
+        assert_eq!(
+            background.reserve(1000, now),
+            Some(Duration::from_millis(500))
+        );
+    }
+}
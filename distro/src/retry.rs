@@ -0,0 +1,200 @@
+//! Retry policy for transient network failures.
+//!
+//! Downloads and index fetches retry transient errors (connection resets,
+//! timeouts, `5xx`, `429`) with exponential backoff and jitter. A
+//! `Retry-After` header on `429` / `503` responses takes precedence over the
+//! computed backoff.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+use crate::Error;
+
+/// How often and how patiently to retry a failed request.
+///
+/// Retries apply per mirror: once `max_attempts` is reached the caller moves
+/// on to the next mirror, if any.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one. `1` disables
+    /// retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for a single computed delay. A `Retry-After` longer than
+    /// this gives up on the current mirror instead of waiting.
+    pub max_backoff: Duration,
+    /// Factor applied to the delay after each failed attempt.
+    pub multiplier: f64,
+    /// Randomize each delay between half and the full computed value.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the backoff before retry number `retry` (1-based), without
+    /// jitter.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exp = self
+            .multiplier
+            .max(1.0)
+            .powi(retry.saturating_sub(1) as i32);
+        let secs = self.initial_backoff.as_secs_f64() * exp;
+        Duration::from_secs_f64(secs.min(self.max_backoff.as_secs_f64()))
+    }
+
+    /// Decides whether to retry after `attempt` (1-based) failed with `error`.
+    ///
+    /// Returns the delay to wait before the next attempt, or `None` if the
+    /// error is permanent or the attempt budget is exhausted.
+    pub fn delay_for(&self, attempt: u32, error: &Error) -> Option<Duration> {
+        if attempt >= self.max_attempts || !error.is_transient() {
+            return None;
+        }
+        if let Error::HttpStatus {
+            retry_after: Some(delay),
+            ..
+        } = error
+        {
+            return (*delay <= self.max_backoff).then_some(*delay);
+        }
+
+        let delay = self.backoff(attempt);
+        if !self.jitter {
+            return Some(delay);
+        }
+        let half = delay / 2;
+        Some(half + half.mul_f64(random_unit()))
+    }
+}
+
+/// Parses a `Retry-After` header value (delay-seconds or an HTTP-date).
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Returns a pseudo-random number in `[0, 1)` for jitter.
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status: u16, retry_after: Option<Duration>) -> Error {
+        Error::HttpStatus {
+            url: "https://example.com/streams/v1/images.json".to_owned(),
+            status,
+            retry_after,
+        }
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_and_caps() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(2));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(4), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..100 {
+            let delay = policy.delay_for(2, &status(503, None)).unwrap();
+            assert!(delay >= policy.backoff(2) / 2);
+            assert!(delay <= policy.backoff(2));
+        }
+    }
+
+    #[test]
+    fn permanent_errors_are_not_retried() {
+        let policy = RetryPolicy::default();
+        assert!(policy.delay_for(1, &status(404, None)).is_none());
+        assert!(policy.delay_for(1, &Error::ChecksumParse).is_none());
+        assert!(policy.delay_for(1, &status(502, None)).is_some());
+    }
+
+    #[test]
+    fn attempts_are_bounded() {
+        let policy = RetryPolicy {
+            max_attempts: 2,
+            ..RetryPolicy::default()
+        };
+        assert!(policy.delay_for(1, &status(500, None)).is_some());
+        assert!(policy.delay_for(2, &status(500, None)).is_none());
+        assert!(
+            RetryPolicy::none()
+                .delay_for(1, &status(500, None))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn retry_after_takes_precedence() {
+        let policy = RetryPolicy::default();
+        let delay = policy
+            .delay_for(1, &status(429, Some(Duration::from_secs(7))))
+            .unwrap();
+        assert_eq!(delay, Duration::from_secs(7));
+
+        // Longer than max_backoff: give up on this mirror instead of waiting.
+        assert!(
+            policy
+                .delay_for(1, &status(503, Some(Duration::from_secs(3600))))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn parse_retry_after_formats() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        // A date in the past means "retry now".
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
@@ -0,0 +1,539 @@
+//! Selective mirroring of an upstream Simplestreams server.
+//!
+//! [`MirrorSync`] copies the products matching a set of [`CatalogFilter`]s,
+//! and only their newest builds, into a local directory laid out like the
+//! upstream mirror. Point [`Mirror::Custom`] at the directory (served over
+//! HTTP, or synced to a bucket) to use it from an air-gapped network.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+use sha2::Sha256;
+use tracing::{debug, info};
+
+use crate::download::{DownloadOptions, download_file, hash_file, verify_hash};
+use crate::lxc::{Item, LxcClient, ProductVersion, SimplestreamsIndex, catalog_entry};
+use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
+use crate::provider::HashAlgorithm;
+use crate::publish::{tmp_path, write_index};
+use crate::{CatalogFilter, Error, ItemKind};
+
+/// Content id of the written stream when upstream names none.
+const DEFAULT_CONTENT_ID: &str = "images";
+
+/// Copies selected products of an upstream mirror into a local tree.
+#[derive(Debug, Clone)]
+pub struct MirrorSync {
+    upstream: Mirror,
+    root: PathBuf,
+    filters: Vec<CatalogFilter>,
+    kinds: Vec<ItemKind>,
+    keep_builds: usize,
+    verify_existing: bool,
+}
+
+/// Outcome of a [`MirrorSync::sync`] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Products in the written index.
+    pub products: usize,
+    /// Builds in the written index.
+    pub builds: usize,
+    /// Files downloaded from the upstream mirror.
+    pub downloaded: usize,
+    /// Bytes downloaded from the upstream mirror.
+    pub bytes_downloaded: u64,
+    /// Files that were already present and current.
+    pub up_to_date: usize,
+    /// Files removed because the index no longer lists them.
+    pub removed: usize,
+}
+
+impl MirrorSync {
+    /// Creates a sync from `upstream` into the directory `root`.
+    ///
+    /// By default every product is mirrored with the rootfs of its newest
+    /// complete build.
+    pub fn new(upstream: Mirror, root: impl Into<PathBuf>) -> Self {
+        Self {
+            upstream,
+            root: root.into(),
+            filters: Vec::new(),
+            kinds: vec![ItemKind::Rootfs],
+            keep_builds: 1,
+            verify_existing: false,
+        }
+    }
+
+    /// Adds a filter. A product is mirrored if it matches any filter; with
+    /// none, every product whose distro and architecture this crate supports
+    /// is mirrored.
+    pub fn with_filter(mut self, filter: CatalogFilter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Sets the item kinds to mirror for each build (default: the rootfs).
+    /// Kinds a build does not publish are skipped.
+    pub fn with_kinds(mut self, kinds: impl IntoIterator<Item = ItemKind>) -> Self {
+        self.kinds = kinds.into_iter().collect();
+        self
+    }
+
+    /// Sets how many of the newest complete builds of each product to keep
+    /// (default 1, at least 1).
+    pub fn with_keep_builds(mut self, keep_builds: usize) -> Self {
+        self.keep_builds = keep_builds.max(1);
+        self
+    }
+
+    /// Re-hashes files that are already present instead of trusting the
+    /// SHA256 the local index lists for them.
+    pub fn with_verify_existing(mut self, verify_existing: bool) -> Self {
+        self.verify_existing = verify_existing;
+        self
+    }
+
+    /// Returns the root directory of the local tree.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Runs the sync.
+    ///
+    /// Fetches the upstream index with the HTTP client, retry policy, index
+    /// cache and keyring of `options`, then downloads the selected files
+    /// that are missing or changed. Each download is verified against the
+    /// upstream SHA256 before it replaces a local file. Once every file is
+    /// in place, the filtered `images.json` and `index.json` are written
+    /// atomically, and files under `images/` that they no longer list are
+    /// removed. A failed or cancelled run leaves the previous index in
+    /// place; finished downloads are kept and skipped by the next run.
+    pub async fn sync<F>(
+        &self,
+        options: &DownloadOptions,
+        mut on_progress: F,
+    ) -> Result<SyncReport, Error>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let mut client = LxcClient::new(self.upstream.clone())
+            .with_http_client(options.client.clone())
+            .with_retry(options.retry.clone())
+            .with_cancellation(options.cancel.clone());
+        if let Some(cache) = &options.index_cache {
+            client = client.with_index_cache(cache.clone());
+        }
+        if let Some(keyring) = &options.keyring {
+            client = client.with_keyring(keyring.clone());
+        }
+        on_progress(ProgressEvent::FetchingIndex {
+            url: client.index_url(),
+        });
+        let upstream = client.fetch_index().await?;
+        let index = self.select(&upstream);
+        check_item_paths(&index)?;
+        let local = self.local_hashes(&index.content_id).await;
+
+        let mut report = SyncReport {
+            products: index.products.len(),
+            builds: index.products.values().map(|p| p.versions.len()).sum(),
+            ..SyncReport::default()
+        };
+        for item in index
+            .products
+            .values()
+            .flat_map(|product| product.versions.values())
+            .flat_map(|build| build.items.values())
+        {
+            if options.cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let dest = self.root.join(&item.path);
+            if self.is_current(item, &dest, &local).await? {
+                report.up_to_date += 1;
+                continue;
+            }
+            let url = client.item_url(&upstream, &item.path);
+            on_progress(ProgressEvent::Resolved {
+                url: url.clone(),
+                size: item.size,
+            });
+            self.fetch(&url, item, &dest, options, &mut on_progress)
+                .await?;
+            report.downloaded += 1;
+            report.bytes_downloaded += item.size;
+        }
+
+        let root = self.root.clone();
+        report.removed = tokio::task::spawn_blocking(move || {
+            write_index(&root, &index)?;
+            prune(&root, &index)
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))??;
+        info!(
+            root = %self.root.display(),
+            products = report.products,
+            downloaded = report.downloaded,
+            removed = report.removed,
+            "mirror synced"
+        );
+        Ok(report)
+    }
+
+    /// Builds the filtered index: matching products, their newest complete
+    /// builds, and the selected items of each.
+    fn select(&self, upstream: &SimplestreamsIndex) -> SimplestreamsIndex {
+        let products = upstream
+            .products
+            .iter()
+            .filter(|(key, product)| {
+                catalog_entry(key, product).is_some_and(|entry| {
+                    self.filters.is_empty() || self.filters.iter().any(|f| f.matches(&entry))
+                })
+            })
+            .map(|(key, product)| {
+                let mut product = product.clone();
+                let keep: HashSet<String> = product
+                    .complete_builds()
+                    .take(self.keep_builds)
+                    .map(|(serial, _)| serial.to_owned())
+                    .collect();
+                product.versions.retain(|serial, _| keep.contains(serial));
+                for build in product.versions.values_mut() {
+                    build.items = self.selected_items(build);
+                }
+                (key.clone(), product)
+            })
+            .collect();
+        let content_id = match upstream.content_id.as_str() {
+            "" => DEFAULT_CONTENT_ID.to_owned(),
+            content_id => content_id.to_owned(),
+        };
+        SimplestreamsIndex {
+            content_id,
+            datatype: upstream.datatype.clone(),
+            format: upstream.format.clone(),
+            products,
+            source: None,
+        }
+    }
+
+    /// Returns the items of `build` whose kind is mirrored.
+    fn selected_items(&self, build: &ProductVersion) -> HashMap<String, Item> {
+        build
+            .items
+            .iter()
+            .filter(|(_, item)| {
+                self.kinds
+                    .iter()
+                    .any(|&kind| build.item(kind).is_some_and(|i| std::ptr::eq(i, *item)))
+            })
+            .map(|(key, item)| (key.clone(), item.clone()))
+            .collect()
+    }
+
+    /// Reads the SHA256 of each file the current local stream lists, by
+    /// path. A missing or unreadable stream lists nothing.
+    async fn local_hashes(&self, content_id: &str) -> HashMap<String, String> {
+        let path = self.root.join(format!("streams/v1/{content_id}.json"));
+        let Ok(body) = tokio::fs::read(path).await else {
+            return HashMap::new();
+        };
+        let Ok(index) = serde_json::from_slice::<SimplestreamsIndex>(&body) else {
+            return HashMap::new();
+        };
+        index
+            .products
+            .into_values()
+            .flat_map(|product| product.versions.into_values())
+            .flat_map(|build| build.items.into_values())
+            .map(|item| (item.path, item.sha256))
+            .collect()
+    }
+
+    /// Returns `true` if `dest` already holds `item`.
+    async fn is_current(
+        &self,
+        item: &Item,
+        dest: &Path,
+        local: &HashMap<String, String>,
+    ) -> Result<bool, Error> {
+        let Ok(metadata) = tokio::fs::metadata(dest).await else {
+            return Ok(false);
+        };
+        if metadata.len() != item.size {
+            return Ok(false);
+        }
+        if !self.verify_existing && local.get(&item.path) == Some(&item.sha256) {
+            return Ok(true);
+        }
+        let path = dest.to_owned();
+        let sha256 = tokio::task::spawn_blocking(move || hash_file::<Sha256>(&path))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))??;
+        debug!(path = %item.path, current = sha256 == item.sha256, "hashed local file");
+        Ok(sha256 == item.sha256)
+    }
+
+    /// Downloads `item` next to `dest`, verifies it, and moves it in place.
+    async fn fetch<F>(
+        &self,
+        url: &str,
+        item: &Item,
+        dest: &Path,
+        options: &DownloadOptions,
+        on_progress: &mut F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = tmp_path(dest);
+        let result = download_file(
+            url,
+            &tmp,
+            HashAlgorithm::Sha256,
+            Some(item.size),
+            options,
+            &mut *on_progress,
+        )
+        .await?;
+        on_progress(ProgressEvent::Verifying);
+        if let Err(e) = verify_hash(&item.sha256, &result, HashAlgorithm::Sha256) {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+        tokio::fs::rename(&tmp, dest).await?;
+        Ok(())
+    }
+}
+
+/// Removes files under `{root}/images` that `index` does not list, and the
+/// directories this leaves empty. Symlinks count as files and are never
+/// followed. Returns the number of files removed.
+fn prune(root: &Path, index: &SimplestreamsIndex) -> Result<usize, Error> {
+    check_item_paths(index)?;
+    let keep: HashSet<PathBuf> = index
+        .products
+        .values()
+        .flat_map(|product| product.versions.values())
+        .flat_map(|build| build.items.values())
+        .map(|item| root.join(&item.path))
+        .collect();
+    let images = root.join("images");
+    if !images.is_dir() {
+        return Ok(0);
+    }
+    prune_dir(&images, &keep)
+}
+
+/// Rejects an index that lists a path which, joined to the mirror root,
+/// would point outside it: only plain relative paths are accepted.
+fn check_item_paths(index: &SimplestreamsIndex) -> Result<(), Error> {
+    let unsafe_path = index
+        .products
+        .values()
+        .flat_map(|product| product.versions.values())
+        .flat_map(|build| build.items.values())
+        .find(|item| {
+            let path = Path::new(&item.path);
+            path.as_os_str().is_empty()
+                || !path.components().all(|c| matches!(c, Component::Normal(_)))
+        });
+    match unsafe_path {
+        Some(item) => Err(Error::UnsafePath {
+            path: item.path.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn prune_dir(dir: &Path, keep: &HashSet<PathBuf>) -> Result<usize, Error> {
+    let mut removed = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // `file_type` does not follow symlinks: a link, even to a directory,
+        // is removed like a file and its target is never entered.
+        if entry.file_type()?.is_dir() {
+            removed += prune_dir(&path, keep)?;
+            // Fails, harmlessly, while the directory still has files.
+            let _ = std::fs::remove_dir(&path);
+        } else if !keep.contains(&path) {
+            debug!(path = %path.display(), "removing unlisted file");
+            std::fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Arch, Distro, Variant, Version};
+
+    fn upstream() -> SimplestreamsIndex {
+        let build = |serial: &str, complete: bool| {
+            let path = |file: &str| format!("images/alpine/3.21/amd64/default/{serial}/{file}");
+            let mut items = serde_json::json!({
+                "lxd.tar.xz": {
+                    "ftype": "lxd.tar.xz", "sha256": "aa", "size": 1, "path": path("lxd.tar.xz")
+                },
+                "delta-00": {
+                    "ftype": "root.tar.xz.vcdiff", "sha256": "bb", "size": 1,
+                    "path": path("delta-00.vcdiff"), "delta_base": "00"
+                }
+            });
+            if complete {
+                items["root.tar.xz"] = serde_json::json!({
+                    "ftype": "root.tar.xz", "sha256": "cc", "size": 1, "path": path("rootfs.tar.xz")
+                });
+            }
+            serde_json::json!({ "items": items })
+        };
+        serde_json::from_value(serde_json::json!({
+            "content_id": "images",
+            "products": {
+                "alpine:3.21:amd64:default": {
+                    "arch": "amd64", "os": "Alpine", "release": "3.21",
+                    "versions": {
+                        "01": build("01", true),
+                        "02": build("02", true),
+                        "03": build("03", true),
+                        "04": build("04", false)
+                    }
+                },
+                "alpine:3.21:arm64:default": {
+                    "arch": "arm64", "os": "Alpine", "release": "3.21",
+                    "versions": { "01": build("01", true) }
+                },
+                "debian:bookworm:amd64:default": {
+                    "arch": "amd64", "os": "Debian", "release": "bookworm",
+                    "versions": { "01": build("01", true) }
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn selects_filtered_products_and_latest_builds() {
+        let sync = MirrorSync::new(Mirror::Official, "/unused")
+            .with_filter(CatalogFilter {
+                distro: Some(Distro::Alpine),
+                arch: Some(Arch::X86_64),
+                ..Default::default()
+            })
+            .with_filter(CatalogFilter {
+                distro: Some(Distro::Debian),
+                version: Some(Version::new("12")),
+                variant: Some(Variant::default()),
+                ..Default::default()
+            })
+            .with_keep_builds(2);
+        let index = sync.select(&upstream());
+
+        let mut keys: Vec<&String> = index.products.keys().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            ["alpine:3.21:amd64:default", "debian:bookworm:amd64:default"]
+        );
+
+        // The incomplete build 04 is skipped; deltas are never mirrored.
+        let alpine = &index.products["alpine:3.21:amd64:default"];
+        let mut serials: Vec<&String> = alpine.versions.keys().collect();
+        serials.sort();
+        assert_eq!(serials, ["02", "03"]);
+        let items: Vec<&String> = alpine.versions["03"].items.keys().collect();
+        assert_eq!(items, ["root.tar.xz"]);
+
+        let sync = sync.with_kinds([ItemKind::Rootfs, ItemKind::LxdMetadata]);
+        let index = sync.select(&upstream());
+        let build = &index.products["alpine:3.21:amd64:default"].versions["03"];
+        assert_eq!(build.items.len(), 2);
+        assert!(build.item(ItemKind::LxdMetadata).is_some());
+    }
+
+    #[test]
+    fn prunes_unlisted_files() {
+        let root = tempfile::tempdir().unwrap();
+        let sync = MirrorSync::new(Mirror::Official, root.path()).with_filter(CatalogFilter {
+            arch: Some(Arch::X86_64),
+            distro: Some(Distro::Alpine),
+            ..Default::default()
+        });
+        let index = sync.select(&upstream());
+        let kept = root
+            .path()
+            .join("images/alpine/3.21/amd64/default/03/rootfs.tar.xz");
+        let old = root
+            .path()
+            .join("images/alpine/3.21/amd64/default/01/rootfs.tar.xz");
+        for path in [&kept, &old] {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"x").unwrap();
+        }
+
+        assert_eq!(prune(root.path(), &index).unwrap(), 1);
+        assert!(kept.exists());
+        assert!(!old.parent().unwrap().exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prune_does_not_follow_symlinks() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let victim = outside.path().join("keep.txt");
+        std::fs::write(&victim, b"x").unwrap();
+        std::fs::create_dir_all(root.path().join("images/alpine")).unwrap();
+        let link = root.path().join("images/alpine/linked");
+        std::os::unix::fs::symlink(outside.path(), &link).unwrap();
+
+        let index = MirrorSync::new(Mirror::Official, root.path()).select(&upstream());
+        assert_eq!(prune(root.path(), &index).unwrap(), 1);
+        assert!(victim.exists());
+        assert!(std::fs::symlink_metadata(&link).is_err());
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_root() {
+        let root = tempfile::tempdir().unwrap();
+        let sync = MirrorSync::new(Mirror::Official, root.path());
+        let mut index = sync.select(&upstream());
+        assert!(check_item_paths(&index).is_ok());
+
+        for path in [
+            "../outside",
+            "images/../../outside",
+            "/etc/passwd",
+            "./images/rootfs.tar.xz",
+            "",
+        ] {
+            let item = index
+                .products
+                .values_mut()
+                .flat_map(|product| product.versions.values_mut())
+                .flat_map(|build| build.items.values_mut())
+                .next()
+                .unwrap();
+            item.path = path.to_owned();
+            let err = check_item_paths(&index).unwrap_err();
+            assert!(
+                matches!(&err, Error::UnsafePath { path: p } if p == path),
+                "{err}"
+            );
+            assert!(matches!(
+                prune(root.path(), &index),
+                Err(Error::UnsafePath { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,512 @@
+//! VCDIFF (RFC 3284) delta decoding.
+//!
+//! LXC image servers publish `.vcdiff` deltas between consecutive builds,
+//! produced by xdelta3. This decoder supports the default code table, plus
+//! xdelta3's application header and window checksum extensions. Deltas that
+//! use secondary compression or a custom code table are rejected with
+//! [`Error::InvalidDelta`], so callers can fall back to a full download.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::Error;
+
+/// File header magic (`'V' 'C' 'D'` with the high bits set).
+const MAGIC: [u8; 3] = [0xD6, 0xC3, 0xC4];
+
+// Header indicator bits.
+const VCD_DECOMPRESS: u8 = 0x01;
+const VCD_CODETABLE: u8 = 0x02;
+/// xdelta3 extension: an application header (usually file names) follows.
+const VCD_APPHEADER: u8 = 0x04;
+
+// Window indicator bits.
+const VCD_SOURCE: u8 = 0x01;
+const VCD_TARGET: u8 = 0x02;
+/// xdelta3 extension: an Adler-32 checksum of the target window follows the
+/// section lengths.
+const VCD_ADLER32: u8 = 0x04;
+
+/// Sizes of the default address caches.
+const NEAR_SIZE: usize = 4;
+const SAME_SIZE: usize = 3;
+
+/// Upper bound for memory reserved up front from a window's declared size.
+const MAX_PREALLOC: usize = 64 * 1024 * 1024;
+
+/// Applies the VCDIFF delta at `delta` to `source`, writing the result to
+/// `target`.
+pub(crate) fn apply(source: &Path, delta: &Path, target: &Path) -> Result<(), Error> {
+    let delta = std::fs::read(delta)?;
+    let mut source = File::open(source)?;
+    let mut target = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(target)?;
+    decode(&delta, &mut source, &mut target)?;
+    target.sync_all()?;
+    Ok(())
+}
+
+/// Decodes `delta` against `source` into `target`. Windows may copy from
+/// earlier output, so `target` is read back as well.
+fn decode<S, T>(delta: &[u8], source: &mut S, target: &mut T) -> Result<(), Error>
+where
+    S: Read + Seek,
+    T: Read + Write + Seek,
+{
+    let mut input = Input::new(delta);
+    if input.bytes(3)? != MAGIC {
+        return Err(invalid("not a VCDIFF file"));
+    }
+    if input.byte()? != 0 {
+        return Err(invalid("unsupported VCDIFF version"));
+    }
+    let indicator = input.byte()?;
+    if indicator & (VCD_DECOMPRESS | VCD_CODETABLE) != 0 {
+        return Err(invalid(
+            "secondary compression and custom code tables are not supported",
+        ));
+    }
+    if indicator & VCD_APPHEADER != 0 {
+        let len = input.size()?;
+        input.bytes(len)?;
+    }
+
+    let table = default_code_table();
+    let mut written = 0u64;
+    while !input.is_empty() {
+        let window = decode_window(&mut input, &table, source, target, written)?;
+        target.seek(SeekFrom::Start(written))?;
+        target.write_all(&window)?;
+        written += window.len() as u64;
+    }
+    Ok(())
+}
+
+/// Decodes one window and returns its target bytes.
+fn decode_window<S, T>(
+    input: &mut Input<'_>,
+    table: &[[Instruction; 2]],
+    source: &mut S,
+    target: &mut T,
+    written: u64,
+) -> Result<Vec<u8>, Error>
+where
+    S: Read + Seek,
+    T: Read + Seek,
+{
+    let indicator = input.byte()?;
+    let segment = match indicator & (VCD_SOURCE | VCD_TARGET) {
+        0 => Vec::new(),
+        VCD_SOURCE => {
+            let len = source.seek(SeekFrom::End(0))?;
+            read_segment(input, source, len)?
+        }
+        VCD_TARGET => read_segment(input, target, written)?,
+        _ => return Err(invalid("window has both a source and a target segment")),
+    };
+
+    let _encoding_len = input.size()?;
+    let target_len = input.size()?;
+    if input.byte()? != 0 {
+        return Err(invalid("secondary compression is not supported"));
+    }
+    let data_len = input.size()?;
+    let inst_len = input.size()?;
+    let addr_len = input.size()?;
+    if indicator & VCD_ADLER32 != 0 {
+        // The decoded file is verified against its SHA-256 instead.
+        input.bytes(4)?;
+    }
+    let mut data = Input::new(input.bytes(data_len)?);
+    let mut inst = Input::new(input.bytes(inst_len)?);
+    let mut addr = Input::new(input.bytes(addr_len)?);
+
+    let mut out = Vec::with_capacity(target_len.min(MAX_PREALLOC));
+    let mut cache = AddressCache::new();
+    while !inst.is_empty() {
+        for instruction in table[usize::from(inst.byte()?)] {
+            let size = match instruction.size {
+                0 if instruction.kind != Kind::Noop => inst.size()?,
+                size => usize::from(size),
+            };
+            if instruction.kind == Kind::Noop {
+                continue;
+            }
+            // Checked before anything is appended, so a bogus size cannot
+            // trigger a huge allocation.
+            if out
+                .len()
+                .checked_add(size)
+                .is_none_or(|end| end > target_len)
+            {
+                return Err(invalid("window decodes past its declared length"));
+            }
+            match instruction.kind {
+                Kind::Noop => {}
+                Kind::Add => out.extend_from_slice(data.bytes(size)?),
+                Kind::Run => {
+                    let byte = data.byte()?;
+                    out.resize(out.len() + size, byte);
+                }
+                Kind::Copy => {
+                    let here = segment.len() + out.len();
+                    let start = cache.decode(&mut addr, here, instruction.mode)?;
+                    if start >= here {
+                        return Err(invalid("copy address beyond decoded data"));
+                    }
+                    copy(&segment, &mut out, start, size)?;
+                }
+            }
+        }
+    }
+    if out.len() != target_len {
+        return Err(invalid(format!(
+            "window decodes to {} bytes, expected {target_len}",
+            out.len()
+        )));
+    }
+    Ok(out)
+}
+
+/// Reads the segment a window copies from, checking it lies within the
+/// first `available` bytes of `file`.
+fn read_segment<R: Read + Seek>(
+    input: &mut Input<'_>,
+    file: &mut R,
+    available: u64,
+) -> Result<Vec<u8>, Error> {
+    let len = input.size()?;
+    let position = input.u64()?;
+    if position
+        .checked_add(len as u64)
+        .is_none_or(|end| end > available)
+    {
+        return Err(invalid("segment beyond end of file"));
+    }
+    let mut segment = vec![0; len];
+    file.seek(SeekFrom::Start(position))?;
+    file.read_exact(&mut segment)?;
+    Ok(segment)
+}
+
+/// Appends `size` bytes starting at `start` in the address space of
+/// `segment` followed by `out`. The range may overlap the bytes being
+/// appended, which repeats them.
+fn copy(segment: &[u8], out: &mut Vec<u8>, start: usize, size: usize) -> Result<(), Error> {
+    let end = start
+        .checked_add(size)
+        .ok_or_else(|| invalid("copy range overflows"))?;
+    if end <= segment.len() {
+        out.extend_from_slice(&segment[start..end]);
+        return Ok(());
+    }
+    for position in start..end {
+        let byte = match position.checked_sub(segment.len()) {
+            None => segment[position],
+            Some(offset) => out[offset],
+        };
+        out.push(byte);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Noop,
+    Add,
+    Run,
+    Copy,
+}
+
+/// One half of a code table entry.
+#[derive(Debug, Clone, Copy)]
+struct Instruction {
+    kind: Kind,
+    /// Size, or `0` if it follows in the instruction section.
+    size: u8,
+    /// Address mode of a `COPY`.
+    mode: u8,
+}
+
+impl Instruction {
+    const NOOP: Self = Self::new(Kind::Noop, 0, 0);
+
+    const fn new(kind: Kind, size: u8, mode: u8) -> Self {
+        Self { kind, size, mode }
+    }
+}
+
+/// Builds the default code table (RFC 3284, section 5.6).
+fn default_code_table() -> Vec<[Instruction; 2]> {
+    use Instruction as I;
+
+    let mut table = Vec::with_capacity(256);
+    table.push([I::new(Kind::Run, 0, 0), I::NOOP]);
+    for size in 0..=17 {
+        table.push([I::new(Kind::Add, size, 0), I::NOOP]);
+    }
+    for mode in 0..=8 {
+        table.push([I::new(Kind::Copy, 0, mode), I::NOOP]);
+        for size in 4..=18 {
+            table.push([I::new(Kind::Copy, size, mode), I::NOOP]);
+        }
+    }
+    for mode in 0..=5 {
+        for add in 1..=4 {
+            for size in 4..=6 {
+                table.push([I::new(Kind::Add, add, 0), I::new(Kind::Copy, size, mode)]);
+            }
+        }
+    }
+    for mode in 6..=8 {
+        for add in 1..=4 {
+            table.push([I::new(Kind::Add, add, 0), I::new(Kind::Copy, 4, mode)]);
+        }
+    }
+    for mode in 0..=8 {
+        table.push([I::new(Kind::Copy, 4, mode), I::new(Kind::Add, 1, 0)]);
+    }
+    debug_assert_eq!(table.len(), 256);
+    table
+}
+
+/// The `near` and `same` address caches (RFC 3284, section 5.1).
+struct AddressCache {
+    near: [usize; NEAR_SIZE],
+    next_slot: usize,
+    same: [usize; SAME_SIZE * 256],
+}
+
+impl AddressCache {
+    fn new() -> Self {
+        Self {
+            near: [0; NEAR_SIZE],
+            next_slot: 0,
+            same: [0; SAME_SIZE * 256],
+        }
+    }
+
+    /// Decodes a `COPY` address at position `here` with `mode`.
+    fn decode(&mut self, addr: &mut Input<'_>, here: usize, mode: u8) -> Result<usize, Error> {
+        let mode = usize::from(mode);
+        let address = match mode {
+            // VCD_SELF
+            0 => addr.size()?,
+            // VCD_HERE
+            1 => here
+                .checked_sub(addr.size()?)
+                .ok_or_else(|| invalid("copy address before start of window"))?,
+            m if m < 2 + NEAR_SIZE => self.near[m - 2]
+                .checked_add(addr.size()?)
+                .ok_or_else(|| invalid("copy address overflows"))?,
+            m if m < 2 + NEAR_SIZE + SAME_SIZE => {
+                self.same[(m - 2 - NEAR_SIZE) * 256 + usize::from(addr.byte()?)]
+            }
+            _ => return Err(invalid("invalid address mode")),
+        };
+        self.near[self.next_slot] = address;
+        self.next_slot = (self.next_slot + 1) % NEAR_SIZE;
+        self.same[address % (SAME_SIZE * 256)] = address;
+        Ok(address)
+    }
+}
+
+/// A bounds-checked reader over an in-memory section.
+struct Input<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| invalid("unexpected end of delta"))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Reads a variable-length integer: base 128, most significant digit
+    /// first, with the high bit set on all but the last byte.
+    fn u64(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        loop {
+            let byte = self.byte()?;
+            if value > u64::MAX >> 7 {
+                return Err(invalid("integer overflows"));
+            }
+            value = (value << 7) | u64::from(byte & 0x7f);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn size(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.u64()?).map_err(|_| invalid("size overflows"))
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidDelta(reason.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = vec![(value & 0x7f) as u8];
+        value >>= 7;
+        while value > 0 {
+            bytes.insert(0, (value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes
+    }
+
+    /// Encodes a window; `segment` is `(length, position)`.
+    fn window(
+        indicator: u8,
+        segment: Option<(u64, u64)>,
+        target_len: u64,
+        data: &[u8],
+        inst: &[u8],
+        addr: &[u8],
+    ) -> Vec<u8> {
+        let mut body = varint(target_len);
+        body.push(0);
+        body.extend(varint(data.len() as u64));
+        body.extend(varint(inst.len() as u64));
+        body.extend(varint(addr.len() as u64));
+        if indicator & VCD_ADLER32 != 0 {
+            body.extend([0; 4]);
+        }
+        body.extend([data, inst, addr].concat());
+
+        let mut window = vec![indicator];
+        if let Some((len, position)) = segment {
+            window.extend(varint(len));
+            window.extend(varint(position));
+        }
+        window.extend(varint(body.len() as u64));
+        window.extend(body);
+        window
+    }
+
+    fn run(source: &[u8], delta: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut target = Cursor::new(Vec::new());
+        decode(delta, &mut Cursor::new(source.to_vec()), &mut target)?;
+        Ok(target.into_inner())
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [0, 127, 128, 123456789, u64::MAX] {
+            let bytes = varint(value);
+            assert_eq!(Input::new(&bytes).u64().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn decodes_rfc_example() {
+        // RFC 3284, section 4.3: COPY 4 @0, ADD "wxyz", COPY 4 @4,
+        // COPY 12 @24 (overlapping the target), RUN 4 "z".
+        let mut delta = vec![0xD6, 0xC3, 0xC4, 0, 0];
+        delta.extend(window(
+            VCD_SOURCE,
+            Some((16, 0)),
+            28,
+            b"wxyzz",
+            &[20, 5, 20, 28, 0, 4],
+            &[0, 4, 24],
+        ));
+        let target = run(b"abcdefghijklmnop", &delta).unwrap();
+        assert_eq!(target, b"abcdwxyzefghefghefghefghzzzz");
+    }
+
+    #[test]
+    fn decodes_target_windows_and_address_caches() {
+        // Application header, then a window with an Adler-32 checksum.
+        let mut delta = vec![0xD6, 0xC3, 0xC4, 0, VCD_APPHEADER, 3];
+        delta.extend(b"app");
+        delta.extend(window(
+            VCD_SOURCE | VCD_ADLER32,
+            Some((4, 0)),
+            4,
+            b"",
+            &[20],
+            &[0],
+        ));
+        // Copies from the previous output: ADD "X" + COPY 4 @0 (one
+        // instruction), COPY 4 @HERE-8, COPY 4 @near[0]+2, COPY 4 @same[2].
+        delta.extend(window(
+            VCD_TARGET,
+            Some((4, 0)),
+            17,
+            b"X",
+            &[163, 36, 52, 116],
+            &[0, 8, 2, 2],
+        ));
+        let target = run(b"abcd", &delta).unwrap();
+        assert_eq!(target, b"abcdXabcdbcdXcdXacdXa");
+    }
+
+    #[test]
+    fn rejects_secondary_compression() {
+        let delta = [0xD6, 0xC3, 0xC4, 0, VCD_DECOMPRESS, 1];
+        assert!(matches!(run(b"", &delta), Err(Error::InvalidDelta(_))));
+    }
+
+    #[test]
+    fn rejects_sizes_past_target_length() {
+        // RUN of 2^40 bytes into a 4-byte window.
+        let mut inst = vec![0];
+        inst.extend(varint(1 << 40));
+        let mut delta = vec![0xD6, 0xC3, 0xC4, 0, 0];
+        delta.extend(window(0, None, 4, b"z", &inst, &[]));
+        assert!(matches!(run(b"", &delta), Err(Error::InvalidDelta(_))));
+
+        // COPY whose size overflows the address space.
+        let mut inst = vec![19];
+        inst.extend(varint(u64::MAX >> 1));
+        let mut delta = vec![0xD6, 0xC3, 0xC4, 0, 0];
+        delta.extend(window(VCD_SOURCE, Some((4, 0)), 4, b"", &inst, &[2]));
+        assert!(matches!(run(b"abcd", &delta), Err(Error::InvalidDelta(_))));
+        assert!(copy(b"abcd", &mut Vec::new(), 2, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_copies() {
+        let mut delta = vec![0xD6, 0xC3, 0xC4, 0, 0];
+        delta.extend(window(VCD_SOURCE, Some((4, 0)), 4, b"", &[20], &[9]));
+        assert!(matches!(run(b"abcd", &delta), Err(Error::InvalidDelta(_))));
+
+        // The segment itself lies beyond the source.
+        let mut delta = vec![0xD6, 0xC3, 0xC4, 0, 0];
+        delta.extend(window(VCD_SOURCE, Some((8, 0)), 4, b"", &[20], &[0]));
+        assert!(matches!(run(b"abcd", &delta), Err(Error::InvalidDelta(_))));
+    }
+}